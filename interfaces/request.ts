@@ -4,6 +4,13 @@ interface RequestResponse {
   body?: unknown;
 }
 
+export interface BodyMatcher {
+  /** `json`, `jsonPath`, `contains` or `regex`. */
+  type: string;
+  path?: string | null;
+  value: string;
+}
+
 interface RequestConfig {
   name: string;
   path: string;
@@ -11,6 +18,7 @@ interface RequestConfig {
   headers?: Record<string, string>;
   queryParameters?: Record<string, string>;
   body?: Record<string, string>;
+  bodyMatchers?: BodyMatcher[];
   params?: Record<string, string>;
   response?: RequestResponse;
 }
@@ -0,0 +1,154 @@
+use crate::generators::Generator;
+use crate::template::active_variant;
+use crate::types::{BlockMatch, DelayConfig};
+use std::time::Duration;
+
+/// Samples the active variant's delay (or the block's), records it on the match and waits it
+/// out. Only the calling task sleeps, so other requests are served meanwhile.
+pub async fn apply_delay(block_match: &mut BlockMatch) {
+    let config = active_variant(&block_match.block)
+        .and_then(|variant| variant.delay.as_ref())
+        .or(block_match.block.delay.as_ref());
+    let Some(config) = config else {
+        return;
+    };
+    let delay_ms = sample_delay(config, &mut Generator::new(None));
+    if delay_ms == 0 {
+        return;
+    }
+    block_match.delay_ms = Some(delay_ms);
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+}
+
+/// A delay in ms drawn from `config`.
+pub fn sample_delay(config: &DelayConfig, generator: &mut Generator) -> u64 {
+    match config.distribution.as_str() {
+        "uniform" => {
+            let (min, max) = if config.min_ms <= config.max_ms {
+                (config.min_ms, config.max_ms)
+            } else {
+                (config.max_ms, config.min_ms)
+            };
+            min + generator.next_u64() % (max - min).saturating_add(1)
+        }
+        "normal" => {
+            // Box-Muller; `1 - x` keeps the logarithm's argument above zero.
+            let u1 = 1.0 - generator.next_f64();
+            let u2 = generator.next_f64();
+            let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+            let sample = (config.ms as f64 + z * config.std_dev_ms as f64).round();
+            let upper = if config.max_ms > 0 {
+                config.max_ms as f64
+            } else {
+                f64::MAX
+            };
+            sample.clamp(config.min_ms as f64, upper.max(config.min_ms as f64)) as u64
+        }
+        _ => config.ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block, block_match};
+    use crate::types::TemplateVariant;
+
+    fn distribution(name: &str) -> DelayConfig {
+        DelayConfig {
+            distribution: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fixed_delays_ignore_the_range() {
+        let config = DelayConfig {
+            ms: 250,
+            min_ms: 10,
+            max_ms: 20,
+            ..distribution("")
+        };
+        assert_eq!(sample_delay(&config, &mut Generator::new(Some(1))), 250);
+    }
+
+    #[test]
+    fn uniform_delays_stay_within_the_range() {
+        let mut generator = Generator::new(Some(7));
+        let config = DelayConfig {
+            min_ms: 100,
+            max_ms: 110,
+            ..distribution("uniform")
+        };
+        let samples: Vec<u64> = (0..500)
+            .map(|_| sample_delay(&config, &mut generator))
+            .collect();
+        assert!(samples.iter().all(|ms| (100..=110).contains(ms)));
+        assert!(samples.contains(&100));
+        assert!(samples.contains(&110));
+
+        let swapped = DelayConfig {
+            min_ms: 110,
+            max_ms: 100,
+            ..distribution("uniform")
+        };
+        assert!((100..=110).contains(&sample_delay(&swapped, &mut generator)));
+    }
+
+    #[test]
+    fn normal_delays_are_clamped_to_their_bounds() {
+        let mut generator = Generator::new(Some(3));
+        let config = DelayConfig {
+            ms: 100,
+            std_dev_ms: 1_000,
+            min_ms: 50,
+            max_ms: 150,
+            ..distribution("normal")
+        };
+        let samples: Vec<u64> = (0..500)
+            .map(|_| sample_delay(&config, &mut generator))
+            .collect();
+        assert!(samples.iter().all(|ms| (50..=150).contains(ms)));
+
+        let unbounded_above = DelayConfig {
+            max_ms: 0,
+            ..config.clone()
+        };
+        assert!((0..500)
+            .map(|_| sample_delay(&unbounded_above, &mut generator))
+            .all(|ms| ms >= 50));
+    }
+
+    #[test]
+    fn normal_delays_without_spread_are_the_mean() {
+        let config = DelayConfig {
+            ms: 80,
+            ..distribution("normal")
+        };
+        assert_eq!(sample_delay(&config, &mut Generator::new(Some(9))), 80);
+    }
+
+    #[tokio::test]
+    async fn the_variant_delay_takes_precedence_and_is_recorded() {
+        let mut with_variant = block("b1", "GET", "/slow");
+        with_variant.delay = Some(DelayConfig {
+            ms: 500,
+            ..distribution("fixed")
+        });
+        with_variant.template_variants = vec![TemplateVariant {
+            id: "v1".to_string(),
+            delay: Some(DelayConfig {
+                ms: 5,
+                ..distribution("fixed")
+            }),
+            ..Default::default()
+        }];
+        let mut matched = block_match(with_variant);
+        apply_delay(&mut matched).await;
+        assert_eq!(matched.delay_ms, Some(5));
+
+        let mut undelayed = block_match(block("b2", "GET", "/fast"));
+        apply_delay(&mut undelayed).await;
+        assert_eq!(undelayed.delay_ms, None);
+    }
+}
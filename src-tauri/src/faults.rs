@@ -0,0 +1,198 @@
+use crate::response::header_map_to_string_map;
+use crate::state::LoggedResponse;
+use crate::template::active_variant;
+use crate::types::Block;
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use futures_util::stream::{self, Stream};
+use std::io;
+use std::time::Duration;
+
+/// How long a faulted body stalls before ending, so what was sent reaches the client first.
+const FLUSH_PAUSE: Duration = Duration::from_millis(50);
+
+/// The active variant's fault, or the block's; `None` when neither sets a known mode.
+pub fn active_fault(block: &Block) -> Option<&str> {
+    let fault = active_variant(block)
+        .and_then(|variant| variant.fault.as_deref())
+        .or(block.fault.as_deref())?;
+    matches!(
+        fault,
+        "closeConnection" | "hang" | "resetAfterHeaders" | "truncatedBody"
+    )
+    .then_some(fault)
+}
+
+/// A response that breaks off instead of completing. `closeConnection` fails the body before the
+/// head is written, so the server drops the connection with nothing sent; the other faults end
+/// the body before its declared `Content-Length`, which also makes the server drop the
+/// connection, after the head (and `sent`) reached the client.
+pub struct FaultPlan {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Body bytes sent before the connection drops; `None` when not even the head is sent.
+    pub sent: Option<Bytes>,
+    /// `hang`: nothing is sent and the request is held open.
+    pub hang: bool,
+}
+
+impl FaultPlan {
+    /// The body for either server. For `closeConnection` its first item is an error.
+    pub fn body_stream<E>(&self) -> impl Stream<Item = Result<Bytes, E>> + Send + Sync + 'static
+    where
+        E: From<io::Error> + Send + Sync + 'static,
+    {
+        let sent = self.sent.clone();
+        let close = sent.is_none();
+        stream::unfold(Some(sent.unwrap_or_default()), move |pending| async move {
+            let sent = pending?;
+            if close {
+                let error = io::Error::new(io::ErrorKind::ConnectionAborted, "closeConnection");
+                return Some((Err(error.into()), None));
+            }
+            if !sent.is_empty() {
+                return Some((Ok(sent), Some(Bytes::new())));
+            }
+            // Stall before ending so what was sent is flushed rather than dropped with the
+            // connection.
+            tokio::time::sleep(FLUSH_PAUSE).await;
+            None
+        })
+    }
+
+    /// For `hang`, never returns, leaving the request open until the client gives up.
+    pub async fn hold(&self) {
+        if self.hang {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// What the client receives, for the request log: nothing for `closeConnection` and `hang`,
+    /// otherwise the head and the body bytes sent before the drop.
+    pub fn logged_response(&self) -> Option<LoggedResponse> {
+        let sent = self.sent.as_ref()?;
+        Some(LoggedResponse {
+            status: Some(self.status.as_u16()),
+            headers: header_map_to_string_map(&self.headers),
+            body: (!sent.is_empty()).then(|| String::from_utf8_lossy(sent).to_string()),
+        })
+    }
+
+    pub fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from_stream(self.body_stream::<io::Error>()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+/// Turns a rendered block response into `fault`.
+pub async fn fault_plan(fault: &str, response: Response) -> FaultPlan {
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+    let mut headers = parts.headers;
+    headers.remove(header::TRANSFER_ENCODING);
+    // Declare at least one byte so even an empty body ends short.
+    let declared = body.len().max(1);
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(declared));
+    let sent = match fault {
+        "closeConnection" | "hang" => None,
+        "truncatedBody" => Some(body.slice(..body.len() / 2)),
+        _ => Some(Bytes::new()),
+    };
+    FaultPlan {
+        status: parts.status,
+        headers,
+        sent,
+        hang: fault == "hang",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::block;
+    use crate::types::TemplateVariant;
+
+    fn faulty(fault: &str) -> Block {
+        Block {
+            fault: Some(fault.to_string()),
+            ..block("b1", "GET", "/flaky")
+        }
+    }
+
+    fn response(body: &'static str) -> Response {
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = StatusCode::CREATED;
+        response.headers_mut().insert(
+            header::TRANSFER_ENCODING,
+            HeaderValue::from_static("chunked"),
+        );
+        response
+    }
+
+    #[test]
+    fn the_variant_fault_overrides_the_block_fault() {
+        assert_eq!(active_fault(&faulty("hang")), Some("hang"));
+        assert_eq!(active_fault(&faulty("explode")), None);
+        assert_eq!(active_fault(&block("b1", "GET", "/")), None);
+
+        let mut overridden = faulty("hang");
+        overridden.template_variants = vec![TemplateVariant {
+            id: "v1".to_string(),
+            fault: Some("truncatedBody".to_string()),
+            ..Default::default()
+        }];
+        assert_eq!(active_fault(&overridden), Some("truncatedBody"));
+        overridden.template_variants[0].fault = Some(String::new());
+        assert_eq!(active_fault(&overridden), None);
+    }
+
+    #[tokio::test]
+    async fn truncated_bodies_send_half_of_the_declared_length() {
+        let plan = fault_plan("truncatedBody", response("0123456789")).await;
+        assert_eq!(plan.status, StatusCode::CREATED);
+        assert_eq!(plan.headers[header::CONTENT_LENGTH], "10");
+        assert!(!plan.headers.contains_key(header::TRANSFER_ENCODING));
+        assert_eq!(plan.sent.as_deref(), Some(&b"01234"[..]));
+        let logged = plan.logged_response().unwrap();
+        assert_eq!(logged.status, Some(201));
+        assert_eq!(logged.body.as_deref(), Some("01234"));
+
+        let body = to_bytes(plan.into_response().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "01234");
+    }
+
+    #[tokio::test]
+    async fn reset_connections_send_only_the_head() {
+        let reset = fault_plan("resetAfterHeaders", response("")).await;
+        assert_eq!(reset.sent.as_deref(), Some(&b""[..]));
+        assert_eq!(reset.headers[header::CONTENT_LENGTH], "1");
+        assert_eq!(reset.logged_response().unwrap().body, None);
+    }
+
+    #[tokio::test]
+    async fn closed_connections_fail_before_the_head() {
+        let closed = fault_plan("closeConnection", response("0123456789")).await;
+        assert_eq!(closed.sent, None);
+        assert!(closed.logged_response().is_none());
+        closed.hold().await;
+
+        let error = to_bytes(closed.into_response().into_body(), usize::MAX).await;
+        assert!(error.is_err());
+    }
+
+    #[tokio::test]
+    async fn hanging_requests_never_answer() {
+        let hang = fault_plan("hang", response("0123456789")).await;
+        assert!(hang.logged_response().is_none());
+        assert!(tokio::time::timeout(Duration::from_millis(20), hang.hold())
+            .await
+            .is_err());
+    }
+}
@@ -1,10 +1,22 @@
+use crate::delay;
+use crate::faults;
 use crate::logs;
 use crate::matching;
+use crate::mock_state;
+use crate::modifiers;
+use crate::network;
 use crate::process_lookup;
+use crate::recorder;
+use crate::resources;
 use crate::response;
-use crate::state::AppState;
-use crate::store;
+use crate::rewrites;
+use crate::routing;
+use crate::state::{AppState, LoggedResponse};
+use crate::variants;
 
+use axum::body::Bytes;
+use futures_util::StreamExt;
+use http_body_util::{BodyExt, Full};
 use hudsucker::{
     certificate_authority::RcgenAuthority,
     hyper::{Request, Response},
@@ -13,9 +25,22 @@ use hudsucker::{
 };
 use std::net::SocketAddr;
 
+/// Largest request body buffered for matching, the same as the reverse proxy's body limit.
+const MAX_BUFFERED_REQUEST_BYTES: usize = 2 * 1024 * 1024;
+/// Largest upstream response body buffered for body modifiers; longer ones pass unmodified.
+const MAX_MODIFIED_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
 #[derive(Clone)]
 struct MapyProxyHandler {
     state: AppState,
+    /// Throttle for the request being handled, applied to its response too.
+    throttle: Option<network::Throttle>,
+    /// Log entry of a passed-through request, completed once the upstream response arrives.
+    pending_log: Option<u64>,
+    /// Set while record mode is snapshotting the upstream response of a passed-through request.
+    pending_record: Option<recorder::RecordTarget>,
+    /// Response modifiers in scope for a passed-through request.
+    pending_modifiers: Vec<routing::CompiledModifier>,
 }
 
 impl HttpHandler for MapyProxyHandler {
@@ -24,6 +49,11 @@ impl HttpHandler for MapyProxyHandler {
         ctx: &HttpContext,
         req: Request<Body>,
     ) -> RequestOrResponse {
+        let (mut req, body_bytes) = match buffer_request(req, MAX_BUFFERED_REQUEST_BYTES).await {
+            Ok(buffered) => buffered,
+            Err(response) => return response.into(),
+        };
+
         let method_str = req.method().to_string();
         let path = req.uri().path().to_string();
 
@@ -32,7 +62,7 @@ impl HttpHandler for MapyProxyHandler {
             Err(_) => return req.into(),
         };
 
-        let store = store::read_store(&self.state).await;
+        let table = routing::current(&self.state).await;
         let active_profile = self.state.active_profile.lock().await.clone();
 
         let query = extract_query_params(req.uri());
@@ -42,9 +72,23 @@ impl HttpHandler for MapyProxyHandler {
             .and_then(|v| v.to_str().ok())
             .map(|s| s.to_string())
             .or_else(|| req.uri().host().map(|h| h.to_string()));
+        self.throttle = network::throttle_for(&self.state, host.as_deref()).await;
 
-        let block_match =
-            matching::find_block_match(&store, active_profile.as_deref(), &axum_method, &path);
+        let incoming = matching::IncomingRequest {
+            method: &axum_method,
+            path: &path,
+            host: host.as_deref(),
+            headers: req.headers(),
+            query: &query,
+            body: &body_bytes,
+        };
+        let mut block_match =
+            matching::find_block_match(&table, active_profile.as_deref(), &incoming);
+        if let Some(found) = block_match.as_mut() {
+            variants::apply_selection_mode(&self.state, found).await;
+            mock_state::apply_effects(&self.state, found, &incoming).await;
+            delay::apply_delay(found).await;
+        }
 
         if let Some(found) = block_match.as_ref() {
             // Only do process lookup when we're going to log (block matched); skip on pass-through
@@ -52,41 +96,96 @@ impl HttpHandler for MapyProxyHandler {
             let source_port = ctx.client_addr.port();
             let source_app = process_lookup::lookup_process_name(source_port).await;
 
-            let (axum_response, logged_response) = response::build_block_response(found);
+            let (axum_response, logged_response) = response::build_block_response(found, &incoming);
+            if let Some(fault) = faults::active_fault(&found.block) {
+                let plan = faults::fault_plan(fault, axum_response).await;
+                logs::record_request(
+                    &self.state,
+                    &incoming,
+                    None,
+                    Some(found),
+                    plan.logged_response(),
+                    source_app,
+                )
+                .await;
+                plan.hold().await;
+                if let Some(r) = fault_to_hudsucker_response(plan) {
+                    return self.throttled(r).into();
+                }
+                return req.into();
+            }
+
             logs::record_request(
                 &self.state,
-                &axum_method,
-                &path,
-                &query,
+                &incoming,
                 None,
                 Some(found),
                 Some(logged_response),
                 source_app,
-                host.clone(),
             )
             .await;
 
             if let Some(r) = axum_to_hudsucker_response(axum_response).await {
-                return r.into();
+                return self.throttled(r).into();
             }
 
             // If conversion fails, fall back to pass-through without emitting an extra unmatched log.
             return req.into();
         }
 
-        // No match — record as unmatched and pass through to real server.
-        logs::record_request(
-            &self.state,
-            &axum_method,
-            &path,
-            &query,
-            None,
-            None,
-            None,
-            None,
-            host,
-        )
-        .await;
+        let resource_match = resources::find_resource(&table, active_profile.as_deref(), &incoming);
+        if let Some(found) = resource_match {
+            let source_port = ctx.client_addr.port();
+            let source_app = process_lookup::lookup_process_name(source_port).await;
+            let (axum_response, logged_response) =
+                resources::handle_resource(&self.state, &found, &incoming).await;
+            logs::record_resource_request(
+                &self.state,
+                &incoming,
+                &found,
+                logged_response,
+                source_app,
+            )
+            .await;
+            if let Some(r) = axum_to_hudsucker_response(axum_response).await {
+                return self.throttled(r).into();
+            }
+            return req.into();
+        }
+
+        // No match — record as unmatched and pass through to real server; the entry gets its
+        // response in `handle_response`.
+        let log_id = logs::record_request(&self.state, &incoming, None, None, None, None).await;
+        self.pending_log = Some(log_id);
+
+        self.pending_record =
+            recorder::record_target(&self.state, &axum_method, host.as_deref(), &path).await;
+        if self.pending_record.is_some() {
+            // Ask for an unencoded body so it can become a response template.
+            req.headers_mut().remove("accept-encoding");
+        }
+        // A CONNECT only opens a tunnel; the requests inside it come through here on their own.
+        let compiled = active_profile
+            .as_deref()
+            .and_then(|name| table.profile(name))
+            .filter(|_| axum_method != axum::http::Method::CONNECT);
+        if let Some(compiled) = compiled {
+            self.pending_modifiers = modifiers::matching_modifiers(
+                &compiled.modifiers,
+                &axum_method,
+                host.as_deref(),
+                &path,
+            );
+            let request_rewrites = rewrites::matching_rewrites(
+                &compiled.rewrites,
+                &axum_method,
+                host.as_deref(),
+                &path,
+            );
+            if !request_rewrites.is_empty() {
+                rewrite_request(&request_rewrites, &mut req, body_bytes);
+            }
+        }
 
         req.into()
     }
@@ -96,10 +195,182 @@ impl HttpHandler for MapyProxyHandler {
         _ctx: &HttpContext,
         res: Response<Body>,
     ) -> Response<Body> {
-        res
+        let res = match self.pending_record.take() {
+            Some(target) => self.record(&target, res).await,
+            None => res,
+        };
+        let response_modifiers = std::mem::take(&mut self.pending_modifiers);
+        let res = if response_modifiers.is_empty() {
+            res
+        } else {
+            modify_response(&response_modifiers, res, MAX_MODIFIED_RESPONSE_BYTES).await
+        };
+        let Some(log_id) = self.pending_log.take() else {
+            return self.throttled(res);
+        };
+        let (parts, body) = res.into_parts();
+        let logged_response = LoggedResponse {
+            status: Some(parts.status.as_u16()),
+            headers: response::header_map_to_string_map(&parts.headers),
+            body: None,
+        };
+        logs::record_response(&self.state, log_id, logged_response).await;
+        let content_encoding = parts
+            .headers
+            .get("content-encoding")
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.eq_ignore_ascii_case("identity"))
+            .map(|value| value.to_string());
+        let body = logs::capture_response_body(
+            self.state.clone(),
+            log_id,
+            content_encoding,
+            body.into_data_stream(),
+        );
+        self.throttled(Response::from_parts(parts, Body::from_stream(body)))
+    }
+}
+
+impl MapyProxyHandler {
+    /// Buffers the upstream response and snapshots it into the record library; responses too
+    /// large to record are passed through unbuffered.
+    async fn record(&self, target: &recorder::RecordTarget, res: Response<Body>) -> Response<Body> {
+        let (parts, body) = res.into_parts();
+        let body_bytes = match buffer_body(body, recorder::MAX_RECORDED_BODY_BYTES).await {
+            Ok(Buffered::Complete(bytes)) => bytes,
+            // Too large to record; streamed on as it came.
+            Ok(Buffered::TooLarge(body)) => return Response::from_parts(parts, body),
+            Err(error) => return bad_gateway(format!("Unable to read upstream response: {error}")),
+        };
+        // A response that cannot be recorded is still served as usual.
+        let _ = recorder::record_response(
+            &self.state,
+            target,
+            parts.status.as_u16(),
+            &parts.headers,
+            &body_bytes,
+        )
+        .await;
+        Response::from_parts(parts, Body::from(Full::new(body_bytes)))
+    }
+
+    /// Streams the response through the request's throttle, if it has one.
+    fn throttled(&self, res: Response<Body>) -> Response<Body> {
+        let Some(throttle) = self.throttle else {
+            return res;
+        };
+        let (parts, body) = res.into_parts();
+        let body = network::throttle_stream(body.into_data_stream(), throttle);
+        Response::from_parts(parts, Body::from_stream(body))
+    }
+}
+
+/// Applies request rewrites to a passed-through request before hudsucker forwards it.
+fn rewrite_request(
+    request_rewrites: &[rewrites::MatchedRewrite],
+    req: &mut Request<Body>,
+    body: Bytes,
+) {
+    let Ok(mut url) = reqwest::Url::parse(&req.uri().to_string()) else {
+        return;
+    };
+    let next_body =
+        rewrites::apply_rewrites(request_rewrites, &mut url, req.headers_mut(), body.clone());
+    if let Ok(uri) = url.as_str().parse() {
+        *req.uri_mut() = uri;
+    }
+    if next_body != body {
+        *req.body_mut() = Body::from(Full::new(next_body));
     }
 }
 
+/// Rewrites a passed-through response, buffering the body only when a rule edits it. A body
+/// longer than `limit` is passed on with the whole response unmodified, and one that fails to
+/// read becomes a 502.
+async fn modify_response(
+    response_modifiers: &[routing::CompiledModifier],
+    res: Response<Body>,
+    limit: usize,
+) -> Response<Body> {
+    let (mut parts, body) = res.into_parts();
+    let body = if modifiers::rewrites_body(response_modifiers) {
+        let body_bytes = match buffer_body(body, limit).await {
+            Ok(Buffered::Complete(body_bytes)) => body_bytes,
+            Ok(Buffered::TooLarge(body)) => return Response::from_parts(parts, body),
+            Err(error) => {
+                return bad_gateway(format!("Unable to read upstream response: {error}"));
+            }
+        };
+        let body_bytes =
+            modifiers::apply_to_body(response_modifiers, &mut parts.headers, body_bytes);
+        Body::from(Full::new(body_bytes))
+    } else {
+        body
+    };
+    modifiers::apply_to_head(response_modifiers, &mut parts.status, &mut parts.headers);
+    Response::from_parts(parts, body)
+}
+
+/// Buffers the body so it can take part in matching and hands back an equivalent request. A
+/// body over `limit` is matched as empty, so body matchers never accept it, and goes upstream as
+/// it came.
+async fn buffer_request(
+    req: Request<Body>,
+    limit: usize,
+) -> Result<(Request<Body>, Bytes), Response<Body>> {
+    let (parts, body) = req.into_parts();
+    match buffer_body(body, limit).await {
+        Ok(Buffered::Complete(bytes)) => {
+            let body = Body::from(Full::new(bytes.clone()));
+            Ok((Request::from_parts(parts, body), bytes))
+        }
+        Ok(Buffered::TooLarge(body)) => Ok((Request::from_parts(parts, body), Bytes::new())),
+        Err(error) => Err(bad_gateway(format!("Unable to read request body: {error}"))),
+    }
+}
+
+enum Buffered {
+    Complete(Bytes),
+    /// The body went over the limit; rebuilt from what was read and the rest of the stream.
+    TooLarge(Body),
+}
+
+/// Reads a body into memory unless it is longer than `limit` bytes.
+async fn buffer_body(mut body: Body, limit: usize) -> Result<Buffered, hudsucker::Error> {
+    if hudsucker::hyper::body::Body::size_hint(&body).lower() > limit as u64 {
+        return Ok(Buffered::TooLarge(body));
+    }
+    let mut chunks: Vec<Bytes> = Vec::new();
+    let mut length = 0;
+    while let Some(frame) = body.frame().await {
+        let Ok(data) = frame?.into_data() else {
+            continue;
+        };
+        length += data.len();
+        chunks.push(data);
+        if length > limit {
+            let read = futures_util::stream::iter(chunks.into_iter().map(Ok));
+            let rest = read.chain(body.into_data_stream());
+            return Ok(Buffered::TooLarge(Body::from_stream(rest)));
+        }
+    }
+    Ok(Buffered::Complete(match chunks.len() {
+        1 => chunks.remove(0),
+        _ => Bytes::from(chunks.concat()),
+    }))
+}
+
+fn bad_gateway(message: String) -> Response<Body> {
+    let body = serde_json::json!({ "error": message }).to_string();
+    let mut response = Response::new(Body::from(Full::new(Bytes::from(body))));
+    *response.status_mut() = hudsucker::hyper::StatusCode::BAD_GATEWAY;
+    response.headers_mut().insert(
+        "content-type",
+        hudsucker::hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
 fn extract_query_params(uri: &hudsucker::hyper::Uri) -> std::collections::HashMap<String, String> {
     let mut map = std::collections::HashMap::new();
     if let Some(query) = uri.query() {
@@ -126,7 +397,19 @@ async fn axum_to_hudsucker_response(axum_resp: axum::response::Response) -> Opti
     }
 
     builder
-        .body(Body::from(http_body_util::Full::new(body_bytes)))
+        .body(Body::from(Full::new(body_bytes)))
+        .ok()
+}
+
+/// Streams a fault to the client instead of collecting it, so the body can end short.
+fn fault_to_hudsucker_response(plan: faults::FaultPlan) -> Option<Response<Body>> {
+    let status = hudsucker::hyper::StatusCode::from_u16(plan.status.as_u16()).ok()?;
+    let mut builder = Response::builder().status(status);
+    for (name, value) in plan.headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder
+        .body(Body::from_stream(plan.body_stream::<hudsucker::Error>()))
         .ok()
 }
 
@@ -149,7 +432,13 @@ pub async fn run_forward_proxy(
 
     let ca = RcgenAuthority::new(key_pair, ca_cert, 1_000);
 
-    let handler = MapyProxyHandler { state: app_state };
+    let handler = MapyProxyHandler {
+        state: app_state,
+        throttle: None,
+        pending_log: None,
+        pending_record: None,
+        pending_modifiers: Vec::new(),
+    };
 
     let proxy = Proxy::builder()
         .with_addr(SocketAddr::from(([0, 0, 0, 0], proxy_port)))
@@ -167,3 +456,102 @@ pub async fn run_forward_proxy(
             )
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ResponseModifier, TextReplacement};
+    use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+
+    #[tokio::test]
+    async fn faults_stream_only_the_bytes_sent_before_the_drop() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(10));
+        let plan = faults::FaultPlan {
+            status: StatusCode::BAD_GATEWAY,
+            headers,
+            sent: Some("01234".into()),
+            hang: false,
+        };
+
+        let response = fault_to_hudsucker_response(plan).unwrap();
+        assert_eq!(response.status(), 502);
+        assert_eq!(response.headers()["content-length"], "10");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "01234");
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_passed_on_whole() {
+        let chunks = || {
+            let chunks =
+                ["0123", "4567", "89"].map(|chunk| Ok::<_, hudsucker::Error>(Bytes::from(chunk)));
+            Body::from_stream(futures_util::stream::iter(chunks))
+        };
+        let Ok(Buffered::Complete(complete)) = buffer_body(chunks(), 10).await else {
+            panic!("a body within the limit is buffered");
+        };
+        assert_eq!(complete, "0123456789");
+
+        let Ok(Buffered::TooLarge(body)) = buffer_body(chunks(), 5).await else {
+            panic!("a body over the limit is not buffered");
+        };
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "0123456789");
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_are_matched_as_empty_and_forwarded_whole() {
+        let upload = |body: &'static str| Request::new(Body::from(Full::new(Bytes::from(body))));
+
+        let (req, matched_on) = buffer_request(upload("0123456789"), 10).await.ok().unwrap();
+        assert_eq!(matched_on, "0123456789");
+        let forwarded = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(forwarded, "0123456789");
+
+        let (req, matched_on) = buffer_request(upload("0123456789"), 5).await.ok().unwrap();
+        assert!(matched_on.is_empty());
+        let forwarded = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(forwarded, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn modified_responses_are_buffered_within_the_limit_only() {
+        let modifiers = crate::test_support::compiled_modifiers(vec![ResponseModifier {
+            id: "rename".to_string(),
+            status: Some(201),
+            replacements: vec![TextReplacement {
+                find: "Ada".to_string(),
+                replace: "Grace".to_string(),
+            }],
+            ..Default::default()
+        }]);
+        let upstream = |chunks: Vec<Result<Bytes, std::io::Error>>| {
+            Response::new(Body::from_stream(futures_util::stream::iter(chunks)))
+        };
+        let body = |chunks: &[&'static str]| {
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from(*chunk)))
+                .collect::<Vec<_>>()
+        };
+
+        let res = modify_response(&modifiers, upstream(body(&["hi ", "Ada"])), 10).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(
+            res.into_body().collect().await.unwrap().to_bytes(),
+            "hi Grace"
+        );
+
+        let res = modify_response(&modifiers, upstream(body(&["hello ", "Ada"])), 5).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.into_body().collect().await.unwrap().to_bytes(),
+            "hello Ada"
+        );
+
+        let mut broken = body(&["hi "]);
+        broken.push(Err(std::io::Error::other("connection reset")));
+        let res = modify_response(&modifiers, upstream(broken), 10).await;
+        assert_eq!(res.status(), 502);
+    }
+}
@@ -0,0 +1,277 @@
+use regex::Regex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::{Iso8601, Rfc2822, Rfc3339};
+use time::OffsetDateTime;
+
+const FIRST_NAMES: &[&str] = &[
+    "Ada", "Alan", "Amara", "Ben", "Carmen", "Chen", "Dara", "Elena", "Felix", "Grace", "Hana",
+    "Ivan", "Jonas", "Kai", "Lena", "Mateo", "Nia", "Omar", "Priya", "Rosa", "Sam", "Tomas", "Uma",
+    "Yuki",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Anderson", "Bauer", "Costa", "Dubois", "Evans", "Fischer", "Garcia", "Hughes", "Ito",
+    "Jensen", "Kowalski", "Lopez", "Moreau", "Nakamura", "Okafor", "Patel", "Quinn", "Rossi",
+    "Silva", "Turner", "Novak", "Walsh", "Young", "Zhang",
+];
+
+const STREET_NAMES: &[&str] = &[
+    "Maple", "Oak", "Cedar", "Elm", "Harbor", "Hill", "Lake", "Mill", "Park", "River", "Station",
+    "Sunset",
+];
+
+const STREET_SUFFIXES: &[&str] = &["St", "Ave", "Rd", "Ln", "Blvd", "Way"];
+
+const CITIES: &[&str] = &[
+    "Springfield",
+    "Riverside",
+    "Fairview",
+    "Greenville",
+    "Kingston",
+    "Madison",
+    "Salem",
+    "Ashford",
+];
+
+const EMAIL_DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Mixed into the clock for unseeded generators so two responses in the same tick still differ.
+static UNSEEDED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Small deterministic PRNG (SplitMix64) behind the `{{$...}}` generator tokens. A fixed seed
+/// makes every response rendered with it identical, which keeps test fixtures stable.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    state: u64,
+}
+
+impl Generator {
+    /// Seeded from `seed` when set, otherwise from the clock.
+    pub fn new(seed: Option<u64>) -> Self {
+        let state = seed.unwrap_or_else(|| {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos() as u64)
+                .unwrap_or_default();
+            nanos
+                ^ UNSEEDED_COUNTER
+                    .fetch_add(1, Ordering::Relaxed)
+                    .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        });
+        Generator { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+
+    /// Uniform integer in `min..=max`.
+    pub fn int_between(&mut self, min: i64, max: i64) -> i64 {
+        let (low, high) = if min <= max { (min, max) } else { (max, min) };
+        let span = high.abs_diff(low).saturating_add(1);
+        if span == 0 {
+            return self.next_u64() as i64;
+        }
+        low.wrapping_add((self.next_u64() % span) as i64)
+    }
+
+    /// Uniform float in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[(self.next_u64() % items.len() as u64) as usize]
+    }
+
+    pub fn uuid(&mut self) -> String {
+        let high = self.next_u64();
+        let low = self.next_u64();
+        let high = (high & !0xF000) | 0x4000;
+        let low = (low & !(0b11 << 62)) | (0b10 << 62);
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32,
+            (high >> 16) & 0xFFFF,
+            high & 0xFFFF,
+            low >> 48,
+            low & 0xFFFF_FFFF_FFFF
+        )
+    }
+
+    pub fn random_string(&mut self, length: usize) -> String {
+        (0..length)
+            .map(|_| ALPHANUMERIC[(self.next_u64() % ALPHANUMERIC.len() as u64) as usize] as char)
+            .collect()
+    }
+
+    fn email(&mut self) -> String {
+        let first = self.pick(FIRST_NAMES).to_lowercase();
+        let last = self.pick(LAST_NAMES).to_lowercase();
+        let domain = self.pick(EMAIL_DOMAINS);
+        format!("{}.{}{}@{}", first, last, self.int_between(1, 99), domain)
+    }
+
+    fn street_address(&mut self) -> String {
+        format!(
+            "{} {} {}",
+            self.int_between(1, 9999),
+            self.pick(STREET_NAMES),
+            self.pick(STREET_SUFFIXES)
+        )
+    }
+
+    /// Value for the inside of one `{{$name arg ...}}` tag, or `None` when it is not a known
+    /// generator.
+    pub fn generate_tag(&mut self, tag: &str) -> Option<String> {
+        let mut parts = tag.trim().strip_prefix('$')?.split_whitespace();
+        let name = parts.next()?;
+        let args: Vec<&str> = parts.collect();
+        self.generate(name, &args)
+    }
+
+    /// Value for one generator token, or `None` for an unknown name so the placeholder is kept.
+    fn generate(&mut self, name: &str, args: &[&str]) -> Option<String> {
+        let int_arg = |index: usize, default: i64| {
+            args.get(index)
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(default)
+        };
+        let value = match name {
+            "uuid" => self.uuid(),
+            "now" => format_now(args.first().copied().unwrap_or("iso8601")),
+            "timestamp" => OffsetDateTime::now_utc().unix_timestamp().to_string(),
+            "randomInt" => self.int_between(int_arg(0, 0), int_arg(1, 100)).to_string(),
+            "randomString" => self.random_string(int_arg(0, 16).clamp(0, 4096) as usize),
+            "randomBool" => (self.next_u64() & 1 == 1).to_string(),
+            "firstName" => self.pick(FIRST_NAMES).to_string(),
+            "lastName" => self.pick(LAST_NAMES).to_string(),
+            "fullName" | "name" => {
+                format!("{} {}", self.pick(FIRST_NAMES), self.pick(LAST_NAMES))
+            }
+            "email" => self.email(),
+            "streetAddress" => self.street_address(),
+            "city" => self.pick(CITIES).to_string(),
+            "zipCode" => format!("{:05}", self.int_between(501, 99950)),
+            "address" => format!(
+                "{}, {} {:05}",
+                self.street_address(),
+                self.pick(CITIES),
+                self.int_between(501, 99950)
+            ),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Current UTC time as `iso8601` (default), `rfc3339`, `rfc2822`, `date`, `unix` or `unixMillis`.
+fn format_now(format: &str) -> String {
+    let now = OffsetDateTime::now_utc();
+    let formatted = match format {
+        "unix" => return now.unix_timestamp().to_string(),
+        "unixMillis" => return (now.unix_timestamp_nanos() / 1_000_000).to_string(),
+        "date" => return now.date().to_string(),
+        "rfc3339" => now.format(&Rfc3339),
+        "rfc2822" => now.format(&Rfc2822),
+        _ => now.format(&Iso8601::DEFAULT),
+    };
+    formatted.unwrap_or_default()
+}
+
+static GENERATOR_TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\s*\$([A-Za-z][A-Za-z0-9]*)((?:\s+[^\s{}]+)*)\s*\}\}")
+        .expect("invalid generator regex")
+});
+
+/// Replaces `{{$name arg ...}}` generator tokens such as `{{$uuid}}`, `{{$now iso8601}}`,
+/// `{{$randomInt 1 100}}` or `{{$email}}`. Unknown generators are left untouched.
+pub fn render_generators(template: &str, generator: &mut Generator) -> String {
+    if !template.contains("{{") || !template.contains('$') {
+        return template.to_string();
+    }
+    GENERATOR_TOKEN
+        .replace_all(template, |captures: &regex::Captures| {
+            let args: Vec<&str> = captures[2].split_whitespace().collect();
+            generator
+                .generate(&captures[1], &args)
+                .unwrap_or_else(|| captures[0].to_string())
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_seed_renders_the_same_values() {
+        let template = "{{$uuid}} {{$randomInt 1 6}} {{$email}} {{$address}} {{$randomString 8}}";
+        let first = render_generators(template, &mut Generator::new(Some(42)));
+        let second = render_generators(template, &mut Generator::new(Some(42)));
+        let other = render_generators(template, &mut Generator::new(Some(43)));
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert!(!first.contains("{{"));
+    }
+
+    #[test]
+    fn unseeded_generators_differ_within_the_same_tick() {
+        let mut first = Generator::new(None);
+        let mut second = Generator::new(None);
+        assert_ne!(first.uuid(), second.uuid());
+    }
+
+    #[test]
+    fn uuids_are_version_4() {
+        let mut generator = Generator::new(Some(7));
+        for _ in 0..20 {
+            let uuid = generator.uuid();
+            let parts: Vec<&str> = uuid.split('-').collect();
+            let lengths: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+            assert_eq!(lengths, [8, 4, 4, 4, 12]);
+            assert!(parts[2].starts_with('4'));
+            assert!(matches!(parts[3].as_bytes()[0], b'8' | b'9' | b'a' | b'b'));
+        }
+    }
+
+    #[test]
+    fn ranges_are_inclusive_and_may_be_reversed() {
+        let mut generator = Generator::new(Some(1));
+        let values: Vec<i64> = (0..200).map(|_| generator.int_between(5, 3)).collect();
+        assert!(values.iter().all(|value| (3..=5).contains(value)));
+        assert!([3, 4, 5].iter().all(|bound| values.contains(bound)));
+        assert_eq!(generator.int_between(9, 9), 9);
+    }
+
+    #[test]
+    fn tags_take_arguments_and_unknown_ones_are_left_alone() {
+        let mut generator = Generator::new(Some(3));
+        let rendered = render_generators("{{ $randomString 5 }}|{{$now date}}", &mut generator);
+        let (text, date) = rendered.split_once('|').unwrap();
+        assert_eq!(text.len(), 5);
+        assert_eq!(date.len(), 10);
+        assert_eq!(
+            render_generators(
+                "{{$nope}} {{ $randomInt 7 7 }} {{name}} {{uuid}}",
+                &mut generator
+            ),
+            "{{$nope}} 7 {{name}} {{uuid}}"
+        );
+
+        assert_eq!(
+            generator.generate_tag("$randomInt 2 2").as_deref(),
+            Some("2")
+        );
+        assert_eq!(generator.generate_tag("$nope"), None);
+        assert_eq!(generator.generate_tag("uuid"), None);
+    }
+}
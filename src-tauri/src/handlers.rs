@@ -1,24 +1,39 @@
 use crate::blocks;
+use crate::delay;
+use crate::faults;
 use crate::logs;
 use crate::matching;
+use crate::mock_state;
+use crate::modifiers;
+use crate::network;
 use crate::response;
 use crate::proxy;
-use crate::state::{AppState, RequestLogEntry, RequestMatchCount};
+use crate::recorder;
+use crate::resources;
+use crate::rewrites;
+use crate::routing;
+use crate::state::{AppState, ProfileKey, RequestLogEntry, RequestMatchCount};
 use crate::store;
 use crate::system_proxy;
+use crate::template;
 use crate::types::{
-    ActiveProfileResponse, AddLibraryInput, Block, BlocksPayload, CreateProfileInput,
-    CreateRequestInput, CreateSubProfileInput, Library, Profile, SetActiveProfileInput,
-    SubProfile, UpdateLibraryInput, UpdateProfileInput, UpdateSubProfileInput,
+    ActiveProfileResponse, AddLibraryInput, Block, BlockValidation, BlocksPayload,
+    CreateProfileInput, CreateRequestInput, CreateSubProfileInput, ExplainMatchInput, Library,
+    NetworkConditions, Profile, RecordConfig, SetActiveProfileInput, SetActiveSubProfileInput,
+    Store, SubProfile, UpdateBlocksResponse, UpdateLibraryInput, UpdateProfileInput,
+    UpdateSubProfileInput,
 };
+use crate::validation;
+use crate::variants;
 use axum::{
     extract::{Path as AxumPath, Query, State},
     http::{HeaderMap, Method, StatusCode, Uri},
+    middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -74,6 +89,7 @@ pub async fn create_profile(
     State(state): State<AppState>,
     Json(input): Json<CreateProfileInput>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     if store
         .profiles
@@ -101,7 +117,14 @@ pub async fn create_profile(
             name: "Local".to_string(),
             lib_type: "local".to_string(),
             folder_path: None,
+            variables: Vec::new(),
         }],
+        seed: None,
+        variables: Vec::new(),
+        active_sub_profile: None,
+        resources: Vec::new(),
+        modifiers: Vec::new(),
+        rewrites: Vec::new(),
     };
     store.profiles.push(profile.clone());
 
@@ -121,8 +144,15 @@ pub async fn update_profile(
     AxumPath(profile_name): AxumPath<String>,
     Json(input): Json<UpdateProfileInput>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let active_profile = state.active_profile.lock().await.clone();
+    if let Some(variables) = input.variables.as_deref() {
+        if let Err(error) = template::validate_variables(variables) {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    }
+
     if let Some(next_name) = input.name.clone() {
         if next_name.is_empty() {
             return (
@@ -141,6 +171,19 @@ pub async fn update_profile(
         }
     }
 
+    if let Some(response_modifiers) = &input.modifiers {
+        if let Err(error) = modifiers::validate_modifiers(response_modifiers) {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    }
+
+    if let Some(request_rewrites) = &input.rewrites {
+        if let Err(error) = rewrites::validate_rewrites(request_rewrites) {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    }
+
+    let mut reset_resources = false;
     let updated_profile = {
         let Some(profile) = store
             .profiles
@@ -166,6 +209,27 @@ pub async fn update_profile(
             profile.params = params;
         }
 
+        if let Some(seed) = input.seed {
+            profile.seed = Some(seed);
+        }
+
+        if let Some(variables) = input.variables {
+            profile.variables = variables;
+        }
+
+        if let Some(resources) = input.resources {
+            profile.resources = resources;
+            reset_resources = true;
+        }
+
+        if let Some(modifiers) = input.modifiers {
+            profile.modifiers = modifiers;
+        }
+
+        if let Some(rewrites) = input.rewrites {
+            profile.rewrites = rewrites;
+        }
+
         profile.clone()
     };
 
@@ -182,13 +246,50 @@ pub async fn update_profile(
             .into_response();
     }
 
+    if reset_resources {
+        // Redefined resources start again from their seed items.
+        state
+            .resources
+            .lock()
+            .await
+            .retain(|(profile, _), _| *profile != profile_name);
+    }
+    if updated_profile.name != profile_name {
+        move_profile_state(&state, &profile_name, &updated_profile.name).await;
+    }
+
     Json(updated_profile).into_response()
 }
 
+/// Moves the resource items, block hits and mock state kept for a profile to its new name.
+async fn move_profile_state(state: &AppState, from: &str, to: &str) {
+    fn rename<T>(entries: &mut HashMap<ProfileKey, T>, from: &str, to: &str) {
+        let moved: Vec<ProfileKey> = entries
+            .keys()
+            .filter(|(profile, _)| profile == from)
+            .cloned()
+            .collect();
+        entries.retain(|(profile, _), _| profile != to);
+        for key in moved {
+            if let Some(value) = entries.remove(&key) {
+                entries.insert((to.to_string(), key.1), value);
+            }
+        }
+    }
+    rename(&mut *state.resources.lock().await, from, to);
+    rename(&mut *state.block_hits.lock().await, from, to);
+    let mut mock_state = state.mock_state.lock().await;
+    mock_state.remove(to);
+    if let Some(values) = mock_state.remove(from) {
+        mock_state.insert(to.to_string(), values);
+    }
+}
+
 pub async fn delete_profile(
     State(state): State<AppState>,
     AxumPath(profile_name): AxumPath<String>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let initial_len = store.profiles.len();
     let was_active = state.active_profile.lock().await.as_deref() == Some(profile_name.as_str());
@@ -220,6 +321,7 @@ pub async fn create_sub_profile(
     AxumPath(profile_name): AxumPath<String>,
     Json(input): Json<CreateSubProfileInput>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store
         .profiles
@@ -267,6 +369,7 @@ pub async fn update_sub_profile(
     AxumPath((profile_name, subprofile_name)): AxumPath<(String, String)>,
     Json(input): Json<UpdateSubProfileInput>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
 
     let Some(profile) = store
@@ -334,7 +437,11 @@ pub async fn update_sub_profile(
             sub_profile.params = params;
         }
 
-        sub_profile.clone()
+        let updated = sub_profile.clone();
+        if profile.active_sub_profile.as_deref() == Some(subprofile_name.as_str()) {
+            profile.active_sub_profile = Some(updated.name.clone());
+        }
+        updated
     };
 
     if let Err(error) = store::write_store(&state, &store).await {
@@ -348,10 +455,55 @@ pub async fn update_sub_profile(
     Json(updated_subprofile).into_response()
 }
 
+/// Selects the sub-profile whose params block templates can read; `null` clears it.
+pub async fn set_active_sub_profile(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+    Json(input): Json<SetActiveSubProfileInput>,
+) -> Response {
+    let _guard = state.store_lock.lock().await;
+    let mut store = store::read_store(&state).await;
+    let Some(profile) = store
+        .profiles
+        .iter_mut()
+        .find(|profile| profile.name == profile_name)
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Profile not found" })),
+        )
+            .into_response();
+    };
+
+    let name = input.name.filter(|name| !name.trim().is_empty());
+    if let Some(name) = name.as_deref() {
+        if !profile.sub_profiles.iter().any(|sub| sub.name == name) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "SubProfile not found" })),
+            )
+                .into_response();
+        }
+    }
+    profile.active_sub_profile = name;
+    let updated_profile = profile.clone();
+
+    if let Err(error) = store::write_store(&state, &store).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": error })),
+        )
+            .into_response();
+    }
+
+    Json(updated_profile).into_response()
+}
+
 pub async fn delete_sub_profile(
     State(state): State<AppState>,
     AxumPath((profile_name, subprofile_name)): AxumPath<(String, String)>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store
         .profiles
@@ -378,6 +530,10 @@ pub async fn delete_sub_profile(
             .into_response();
     }
 
+    if profile.active_sub_profile.as_deref() == Some(subprofile_name.as_str()) {
+        profile.active_sub_profile = None;
+    }
+
     if let Err(error) = store::write_store(&state, &store).await {
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -394,6 +550,7 @@ pub async fn create_request(
     AxumPath(profile_name): AxumPath<String>,
     Json(input): Json<CreateRequestInput>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store
         .profiles
@@ -429,6 +586,7 @@ pub async fn create_request(
         query_parameters: input.query_parameters.unwrap_or_default(),
         headers: input.headers.unwrap_or_default(),
         body: input.body.unwrap_or_default(),
+        body_matchers: input.body_matchers.unwrap_or_default(),
         params: input.params.unwrap_or_default(),
         response: input.response,
     };
@@ -536,7 +694,9 @@ pub async fn add_library(
         name: input.name.clone(),
         lib_type: "remote".to_string(),
         folder_path: Some(folder_path_str),
+        variables: Vec::new(),
     };
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store.profiles.iter_mut().find(|p| p.name == profile_name) else {
         return (
@@ -561,6 +721,7 @@ pub async fn update_library(
     AxumPath((profile_name, lib_id)): AxumPath<(String, String)>,
     Json(input): Json<UpdateLibraryInput>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store.profiles.iter_mut().find(|p| p.name == profile_name) else {
         return (
@@ -576,9 +737,17 @@ pub async fn update_library(
         )
             .into_response();
     };
+    if let Some(variables) = input.variables.as_deref() {
+        if let Err(error) = template::validate_variables(variables) {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    }
     if let Some(name) = input.name {
         lib.name = name;
     }
+    if let Some(variables) = input.variables {
+        lib.variables = variables;
+    }
     let lib_clone = lib.clone();
     if let Err(e) = store::write_store(&state, &store).await {
         return (
@@ -601,6 +770,7 @@ pub async fn delete_library(
         )
             .into_response();
     }
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store.profiles.iter_mut().find(|p| p.name == profile_name) else {
         return (
@@ -669,6 +839,7 @@ pub async fn update_blocks(
     AxumPath(profile_name): AxumPath<String>,
     Json(input): Json<BlocksPayload>,
 ) -> Response {
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     let Some(profile) = store
         .profiles
@@ -682,6 +853,15 @@ pub async fn update_blocks(
             .into_response();
     };
 
+    let invalid_values = new_invalid_values(profile, &input).await;
+    if !invalid_values.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": invalid_values.join("; ") })),
+        )
+            .into_response();
+    }
+
     let local_blocks: Vec<Block> = input
         .library_blocks
         .iter()
@@ -722,6 +902,16 @@ pub async fn update_blocks(
         }
     }
 
+    let mut validated_ids = HashSet::new();
+    let warnings: Vec<BlockValidation> = input
+        .active_blocks
+        .iter()
+        .chain(input.library_blocks.iter())
+        .filter(|block| validated_ids.insert(block.id.clone()))
+        .map(|block| validation::validate_block(profile, block))
+        .filter(validation::has_issues)
+        .collect();
+
     profile.library_blocks = local_blocks;
     profile.active_blocks = input.active_blocks.clone();
     profile.categories = input.categories.clone();
@@ -734,14 +924,204 @@ pub async fn update_blocks(
             .into_response();
     }
 
-    Json(BlocksPayload {
-        library_blocks: input.library_blocks,
-        active_blocks: input.active_blocks,
-        categories: input.categories,
+    Json(UpdateBlocksResponse {
+        blocks: BlocksPayload {
+            library_blocks: input.library_blocks,
+            active_blocks: input.active_blocks,
+            categories: input.categories,
+        },
+        warnings,
     })
     .into_response()
 }
 
+/// Bad typed values the payload brings in. Values the saved block with the same id already had
+/// are left to the save warnings, so blocks from before typed values were checked still save.
+async fn new_invalid_values(profile: &Profile, input: &BlocksPayload) -> Vec<String> {
+    let mut saved_blocks: Vec<Block> = profile
+        .library_blocks
+        .iter()
+        .chain(profile.active_blocks.iter())
+        .cloned()
+        .collect();
+    for lib in &profile.libraries {
+        if lib.lib_type == "remote" {
+            if let Some(ref folder_path) = lib.folder_path {
+                let path = Path::new(folder_path);
+                saved_blocks.extend(blocks::read_blocks_from_path(path, &lib.id).await);
+            }
+        }
+    }
+    let mut saved_values: HashMap<&str, Vec<String>> = HashMap::new();
+    for block in &saved_blocks {
+        saved_values
+            .entry(block.id.as_str())
+            .or_default()
+            .extend(template::validate_block_values(block));
+    }
+
+    let mut invalid_values = Vec::new();
+    for block in input
+        .library_blocks
+        .iter()
+        .chain(input.active_blocks.iter())
+    {
+        let saved = saved_values.get(block.id.as_str());
+        for value in template::validate_block_values(block) {
+            let known = saved.is_some_and(|saved| saved.contains(&value));
+            if !known && !invalid_values.contains(&value) {
+                invalid_values.push(value);
+            }
+        }
+    }
+    invalid_values
+}
+
+/// Hit counts of the profile's blocks, keyed by block id.
+pub async fn get_block_hits(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+) -> Json<HashMap<String, u64>> {
+    let hits = state.block_hits.lock().await;
+    Json(
+        hits.iter()
+            .filter(|((profile, _), _)| *profile == profile_name)
+            .map(|((_, block_id), count)| (block_id.clone(), *count))
+            .collect(),
+    )
+}
+
+/// Resets the hit counters of every block in the profile, restarting sequences.
+pub async fn reset_block_hits(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+) -> Response {
+    state
+        .block_hits
+        .lock()
+        .await
+        .retain(|(profile, _), _| *profile != profile_name);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Resets one block's hit counter.
+pub async fn reset_block_hit(
+    State(state): State<AppState>,
+    AxumPath((profile_name, block_id)): AxumPath<(String, String)>,
+) -> Response {
+    state
+        .block_hits
+        .lock()
+        .await
+        .remove(&variants::hit_key(&profile_name, &block_id));
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// The profile's mock state; empty until a block effect writes to it.
+pub async fn get_mock_state(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+) -> Json<Map<String, Value>> {
+    let mock_state = state.mock_state.lock().await;
+    Json(mock_state.get(&profile_name).cloned().unwrap_or_default())
+}
+
+/// Replaces the profile's mock state, e.g. to seed it before a test run.
+pub async fn replace_mock_state(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+    Json(payload): Json<Map<String, Value>>,
+) -> Json<Map<String, Value>> {
+    state
+        .mock_state
+        .lock()
+        .await
+        .insert(profile_name, payload.clone());
+    Json(payload)
+}
+
+/// Clears the profile's mock state.
+pub async fn reset_mock_state(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+) -> Response {
+    state.mock_state.lock().await.remove(&profile_name);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Removes one key from the profile's mock state.
+pub async fn delete_mock_state_key(
+    State(state): State<AppState>,
+    AxumPath((profile_name, key)): AxumPath<(String, String)>,
+) -> Response {
+    if let Some(profile_state) = state.mock_state.lock().await.get_mut(&profile_name) {
+        profile_state.remove(&key);
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Current items of a resource collection, seeded if it has not been used yet.
+pub async fn get_resource_items(
+    State(state): State<AppState>,
+    AxumPath((profile_name, resource_id)): AxumPath<(String, String)>,
+) -> Response {
+    let store = store::read_store(&state).await;
+    let Some(resource) = store
+        .profiles
+        .iter()
+        .find(|profile| profile.name == profile_name)
+        .and_then(|profile| profile.resources.iter().find(|item| item.id == resource_id))
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Resource not found" })),
+        )
+            .into_response();
+    };
+    let collections = state.resources.lock().await;
+    let items = collections
+        .get(&resources::data_key(&profile_name, &resource_id))
+        .cloned()
+        .unwrap_or_else(|| resource.seed.clone());
+    Json(items).into_response()
+}
+
+/// Returns a resource collection to its seed items.
+pub async fn reset_resource_items(
+    State(state): State<AppState>,
+    AxumPath((profile_name, resource_id)): AxumPath<(String, String)>,
+) -> Response {
+    state
+        .resources
+        .lock()
+        .await
+        .remove(&resources::data_key(&profile_name, &resource_id));
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Renders a block with its active variant and reports template, JSON, header and value
+/// problems without saving anything.
+pub async fn validate_block(
+    State(state): State<AppState>,
+    AxumPath(profile_name): AxumPath<String>,
+    Json(block): Json<Block>,
+) -> Response {
+    let store = store::read_store(&state).await;
+    let Some(profile) = store
+        .profiles
+        .iter()
+        .find(|profile| profile.name == profile_name)
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Profile not found" })),
+        )
+            .into_response();
+    };
+
+    Json(validation::validate_block(profile, &block)).into_response()
+}
+
 pub async fn get_active_profile(State(state): State<AppState>) -> Json<ActiveProfileResponse> {
     let active_profile = state.active_profile.lock().await.clone();
     Json(ActiveProfileResponse {
@@ -762,6 +1142,7 @@ pub async fn set_active_profile(
             .into_response();
     }
 
+    let _guard = state.store_lock.lock().await;
     let mut store = store::read_store(&state).await;
     if !store.profiles.iter().any(|profile| profile.name == name) {
         return (
@@ -791,47 +1172,69 @@ pub async fn proxy_handler(
     Query(query): Query<HashMap<String, String>>,
     body: axum::body::Bytes,
 ) -> Response {
-    let store = store::read_store(&state).await;
+    let table = routing::current(&state).await;
+    let store = &table.store;
     let path = uri.path().to_string();
     let active_profile = state.active_profile.lock().await.clone();
-    let block_match = matching::find_block_match(&store, active_profile.as_deref(), &method, &path);
+    let host = request_host(store, active_profile.as_deref(), &headers);
+    let incoming = matching::IncomingRequest {
+        method: &method,
+        path: &path,
+        host: host.as_deref(),
+        headers: &headers,
+        query: &query,
+        body: &body,
+    };
+    let mut block_match = matching::find_block_match(&table, active_profile.as_deref(), &incoming);
+    if let Some(found) = block_match.as_mut() {
+        variants::apply_selection_mode(&state, found).await;
+        mock_state::apply_effects(&state, found, &incoming).await;
+        delay::apply_delay(found).await;
+    }
     if let Some(found) = block_match.as_ref() {
-        let (response, logged_response) = response::build_block_response(found);
+        let (response, logged_response) = response::build_block_response(found, &incoming);
+        if let Some(fault) = faults::active_fault(&found.block) {
+            let plan = faults::fault_plan(fault, response).await;
+            logs::record_request(
+                &state,
+                &incoming,
+                None,
+                Some(found),
+                plan.logged_response(),
+                None,
+            )
+            .await;
+            plan.hold().await;
+            return plan.into_response();
+        }
         logs::record_request(
             &state,
-            &method,
-            &path,
-            &query,
+            &incoming,
             None,
             Some(found),
             Some(logged_response),
             None,
-            None,
         )
         .await;
         return response;
     }
-    let match_result = matching::find_match(
-        &store,
-        &method,
-        &path,
-        &headers,
-        &query,
-        active_profile.as_deref(),
-    );
+    if let Some(found) = resources::find_resource(&table, active_profile.as_deref(), &incoming) {
+        let (response, logged_response) =
+            resources::handle_resource(&state, &found, &incoming).await;
+        logs::record_resource_request(&state, &incoming, &found, logged_response, None).await;
+        return response;
+    }
+    let match_result = matching::find_match(&table, active_profile.as_deref(), &incoming);
     match match_result {
         Some(found) => {
             let (response, logged_response) = response::build_response(&found, &path, &query);
             logs::record_request(
                 &state,
-                &method,
-                &path,
-                &query,
+                &incoming,
                 Some(&found),
                 None,
                 Some(logged_response),
                 None,
-                None,
             )
             .await;
             response
@@ -839,31 +1242,194 @@ pub async fn proxy_handler(
         None => {
             let (response, logged_response) = proxy::proxy_request(
                 &state,
-                &store,
+                &table,
                 active_profile.as_deref(),
                 &method,
-                uri,
-                headers,
-                body,
-            )
-            .await;
-            logs::record_request(
-                &state,
-                &method,
-                &path,
-                &query,
-                None,
-                None,
-                Some(logged_response),
-                None,
-                None,
+                uri.clone(),
+                headers.clone(),
+                body.clone(),
             )
             .await;
+            logs::record_request(&state, &incoming, None, None, Some(logged_response), None).await;
             response
         }
     }
 }
 
+/// Applies the network profile for the request's host to whatever `proxy_handler` answers,
+/// mocks and proxied responses alike.
+pub async fn throttle_proxy_response(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let table = routing::current(&state).await;
+    let active_profile = state.active_profile.lock().await.clone();
+    let host = request_host(&table.store, active_profile.as_deref(), request.headers());
+    let response = next.run(request).await;
+    match network::throttle_for(&state, host.as_deref()).await {
+        Some(throttle) => network::throttle_response(response, throttle),
+        None => response,
+    }
+}
+
+/// Global and per-host network profiles currently in force.
+pub async fn get_network_conditions(State(state): State<AppState>) -> Json<NetworkConditions> {
+    Json(state.network.lock().await.clone())
+}
+
+/// Replaces the network profiles; takes effect for the next response.
+pub async fn set_network_conditions(
+    State(state): State<AppState>,
+    Json(input): Json<NetworkConditions>,
+) -> Response {
+    if let Err(error) = network::validate_conditions(&input) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    *state.network.lock().await = input.clone();
+    Json(input).into_response()
+}
+
+/// Host used for host-scoped matching on the reverse proxy. Compares against the upstream the
+/// request is mapped to, falling back to whatever the client sent when the profile has no
+/// baseUrl.
+fn request_host(
+    store: &Store,
+    active_profile: Option<&str>,
+    headers: &HeaderMap,
+) -> Option<String> {
+    proxy::upstream_host(store, active_profile).or_else(|| {
+        headers
+            .get("host")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    })
+}
+
+pub async fn explain_match(
+    State(state): State<AppState>,
+    Json(input): Json<ExplainMatchInput>,
+) -> Response {
+    let method_name = input
+        .method
+        .as_deref()
+        .unwrap_or("GET")
+        .trim()
+        .to_uppercase();
+    let Ok(method) = Method::from_bytes(method_name.as_bytes()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Invalid method" })),
+        )
+            .into_response();
+    };
+    let Some((url_host, path, query)) = split_request_url(&input.url) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Invalid url" })),
+        )
+            .into_response();
+    };
+
+    let table = routing::current(&state).await;
+    let active_profile = state.active_profile.lock().await.clone();
+    let headers = response::string_map_to_header_map(&input.headers);
+    let host = url_host.or_else(|| request_host(&table.store, active_profile.as_deref(), &headers));
+    let body = input.body.unwrap_or_default();
+    let incoming = matching::IncomingRequest {
+        method: &method,
+        path: &path,
+        host: host.as_deref(),
+        headers: &headers,
+        query: &query,
+        body: body.as_bytes(),
+    };
+    Json(matching::explain_match(
+        &table,
+        active_profile.as_deref(),
+        &incoming,
+    ))
+    .into_response()
+}
+
+/// Explains a logged request against the current profiles, so an unmatched entry can show why
+/// nothing caught it. Entries whose log lost part of the request are refused, since matching
+/// what is left could explain a different request.
+pub async fn explain_logged_request(
+    State(state): State<AppState>,
+    AxumPath(log_id): AxumPath<u64>,
+) -> Response {
+    let entry = {
+        let log_store = state.log_store.lock().await;
+        log_store
+            .entries
+            .iter()
+            .find(|entry| entry.id == log_id)
+            .cloned()
+    };
+    let Some(entry) = entry else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Log entry not found" })),
+        )
+            .into_response();
+    };
+    if !entry.explainable {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": "Log entry keeps only part of its request and cannot be explained"
+            })),
+        )
+            .into_response();
+    }
+    let Ok(method) = Method::from_bytes(entry.method.as_bytes()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Invalid method" })),
+        )
+            .into_response();
+    };
+
+    let table = routing::current(&state).await;
+    let active_profile = state.active_profile.lock().await.clone();
+    let headers = response::string_map_to_header_map(&entry.request_headers);
+    let body = entry.request_body.unwrap_or_default();
+    let incoming = matching::IncomingRequest {
+        method: &method,
+        path: &entry.path,
+        host: entry.host.as_deref(),
+        headers: &headers,
+        query: &entry.query,
+        body: body.as_bytes(),
+    };
+    Json(matching::explain_match(
+        &table,
+        active_profile.as_deref(),
+        &incoming,
+    ))
+    .into_response()
+}
+
+/// Splits an absolute URL or a path with query string into (host, path, query).
+fn split_request_url(url: &str) -> Option<(Option<String>, String, HashMap<String, String>)> {
+    let url = url.trim();
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let parsed = reqwest::Url::parse(url).ok()?;
+        let host = parsed.host_str().map(|host| match parsed.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        });
+        let query = parsed.query_pairs().into_owned().collect();
+        return Some((host, parsed.path().to_string(), query));
+    }
+    if !url.starts_with('/') {
+        return None;
+    }
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    Some((None, path.to_string(), matching::parse_form_body(query)))
+}
+
 // --- Proxy management handlers ---
 
 fn configured_proxy_port() -> u16 {
@@ -1164,3 +1730,243 @@ pub async fn recording_status(State(state): State<AppState>) -> Json<Value> {
         .load(std::sync::atomic::Ordering::Relaxed);
     Json(json!({ "recording": recording }))
 }
+
+pub async fn get_record_config(State(state): State<AppState>) -> Json<RecordConfig> {
+    Json(state.record.lock().await.clone())
+}
+
+/// Switches record mode; the target library must exist in the active profile.
+pub async fn set_record_config(
+    State(state): State<AppState>,
+    Json(input): Json<RecordConfig>,
+) -> Response {
+    if let Err(error) = recorder::validate_config(&state, &input).await {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    *state.record.lock().await = input.clone();
+    Json(input).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, block, json_body, request, store};
+
+    #[tokio::test]
+    async fn update_blocks_returns_warnings_for_the_saved_blocks() {
+        let state = app_state();
+        store::write_store(&state, &store(Vec::new()))
+            .await
+            .unwrap();
+        let clean = Block {
+            response_template: r#"{"ok": true}"#.to_string(),
+            ..block("clean", "GET", "/clean")
+        };
+        let broken = Block {
+            response_template: r#"{"name": "{{name}}"}"#.to_string(),
+            ..block("broken", "GET", "/broken")
+        };
+        let payload = BlocksPayload {
+            library_blocks: vec![clean.clone(), broken.clone()],
+            active_blocks: vec![broken],
+            categories: Vec::new(),
+        };
+
+        let response = update_blocks(
+            State(state.clone()),
+            AxumPath("default".to_string()),
+            Json(payload),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["activeBlocks"][0]["id"], "broken");
+        let warnings = body["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0]["blockId"], "broken");
+        assert_eq!(
+            warnings[0]["missingPlaceholders"][0]["message"],
+            "No template value for {{name}}"
+        );
+
+        let saved = store::read_store(&state).await;
+        assert_eq!(saved.profiles[0].active_blocks[0].id, "broken");
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_newly_invalid_values_are_rejected_on_save() {
+        let state = app_state();
+        let with_count = |id: &str, count: &str| Block {
+            template_values: vec![crate::types::TemplateValue {
+                id: "count".to_string(),
+                key: "count".to_string(),
+                value: count.to_string(),
+                value_type: "number".to_string(),
+            }],
+            ..block(id, "GET", "/count")
+        };
+        let legacy = with_count("legacy", "many");
+        store::write_store(&state, &store(vec![legacy.clone()]))
+            .await
+            .unwrap();
+        let save = |blocks: Vec<Block>| {
+            update_blocks(
+                State(state.clone()),
+                AxumPath("default".to_string()),
+                Json(BlocksPayload {
+                    library_blocks: Vec::new(),
+                    active_blocks: blocks,
+                    categories: Vec::new(),
+                }),
+            )
+        };
+
+        let response = save(vec![legacy.clone(), with_count("new", "2")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["warnings"][0]["blockId"], "legacy");
+        assert_eq!(
+            body["warnings"][0]["invalidValues"][0],
+            "legacy: number value \"count\" \"many\" is not a number"
+        );
+
+        let response = save(vec![legacy, with_count("new", "lots")]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json_body(response).await["error"],
+            "new: number value \"count\" \"lots\" is not a number"
+        );
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renamed_profiles_keep_their_runtime_state() {
+        let state = app_state();
+        let mut profiles = store(Vec::new());
+        let mut other = profiles.profiles[0].clone();
+        profiles.profiles[0].name = "team".to_string();
+        other.name = "crew/x".to_string();
+        profiles.profiles.push(other);
+        store::write_store(&state, &profiles).await.unwrap();
+        for profile in ["team", "crew/x"] {
+            state.resources.lock().await.insert(
+                resources::data_key(profile, "todos"),
+                vec![json!({"id": 1})],
+            );
+        }
+        state
+            .block_hits
+            .lock()
+            .await
+            .insert(variants::hit_key("team", "users"), 3);
+        state.mock_state.lock().await.insert(
+            "team".to_string(),
+            Map::from_iter([("n".to_string(), json!(1))]),
+        );
+        let update = |profile: &str, input: Value| {
+            update_profile(
+                State(state.clone()),
+                AxumPath(profile.to_string()),
+                Json(serde_json::from_value(input).unwrap()),
+            )
+        };
+
+        let response = update("team", json!({"name": "crew"})).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state
+            .resources
+            .lock()
+            .await
+            .contains_key(&resources::data_key("crew", "todos")));
+        assert_eq!(
+            state.block_hits.lock().await[&variants::hit_key("crew", "users")],
+            3
+        );
+        assert_eq!(state.mock_state.lock().await["crew"]["n"], 1);
+        assert!(!state.mock_state.lock().await.contains_key("team"));
+
+        update("crew", json!({"resources": []})).await;
+        let resources = state.resources.lock().await;
+        assert!(!resources.contains_key(&resources::data_key("crew", "todos")));
+        assert!(resources.contains_key(&resources::data_key("crew/x", "todos")));
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn store_writers_wait_for_a_pending_read_modify_write() {
+        let state = app_state();
+        store::write_store(&state, &store(Vec::new()))
+            .await
+            .unwrap();
+        let guard = state.store_lock.lock().await;
+        let update = tokio::spawn(update_profile(
+            State(state.clone()),
+            AxumPath("default".to_string()),
+            Json(serde_json::from_value(json!({"baseUrl": "http://api.test"})).unwrap()),
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!update.is_finished());
+        drop(guard);
+        assert_eq!(update.await.unwrap().status(), StatusCode::OK);
+        let saved = store::read_store(&state).await;
+        assert_eq!(saved.profiles[0].base_url, "http://api.test");
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn faulted_blocks_end_their_body_short() {
+        let state = app_state();
+        let flaky = Block {
+            response_template: "0123456789".to_string(),
+            fault: Some("truncatedBody".to_string()),
+            ..block("flaky", "GET", "/flaky")
+        };
+        store::write_store(&state, &store(vec![flaky]))
+            .await
+            .unwrap();
+        *state.active_profile.lock().await = Some("default".to_string());
+
+        let response = proxy_handler(
+            State(state.clone()),
+            Method::GET,
+            Uri::from_static("/flaky"),
+            HeaderMap::new(),
+            Query(HashMap::new()),
+            axum::body::Bytes::new(),
+        )
+        .await;
+        assert_eq!(response.headers()["content-length"], "10");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "01234");
+        let log_store = state.log_store.lock().await;
+        let logged = log_store.entries[0].response.as_ref().unwrap();
+        assert_eq!(logged.body.as_deref(), Some("01234"));
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn logged_requests_missing_their_credentials_are_not_explained() {
+        let state = app_state();
+        store::write_store(&state, &store(vec![block("me", "GET", "/me")]))
+            .await
+            .unwrap();
+        *state.active_profile.lock().await = Some("default".to_string());
+        let plain = request(Method::GET, "/me");
+        let signed = request(Method::GET, "/me").header("authorization", "Bearer secret");
+        let plain_id =
+            logs::record_request(&state, &plain.incoming(), None, None, None, None).await;
+        let signed_id =
+            logs::record_request(&state, &signed.incoming(), None, None, None, None).await;
+
+        let response = explain_logged_request(State(state.clone()), AxumPath(plain_id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["outcome"], "block");
+        let response = explain_logged_request(State(state.clone()), AxumPath(signed_id)).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+}
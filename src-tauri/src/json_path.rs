@@ -0,0 +1,50 @@
+use serde_json::Value;
+
+/// Splits a JSON path such as `$.user.emails[0]` or `user.emails.0` into its segments.
+pub fn parse_json_path(path: &str) -> Vec<String> {
+    let trimmed = path.trim();
+    let trimmed = trimmed.strip_prefix('$').unwrap_or(trimmed);
+    let mut segments = Vec::new();
+    for part in trimmed.split('.') {
+        let mut rest = part;
+        while !rest.is_empty() {
+            if let Some(after_bracket) = rest.strip_prefix('[') {
+                let Some(end) = after_bracket.find(']') else {
+                    segments.push(after_bracket.to_string());
+                    break;
+                };
+                let inner = after_bracket[..end].trim_matches(|c| c == '"' || c == '\'');
+                segments.push(inner.to_string());
+                rest = &after_bracket[end + 1..];
+            } else {
+                let end = rest.find('[').unwrap_or(rest.len());
+                segments.push(rest[..end].to_string());
+                rest = &rest[end..];
+            }
+        }
+    }
+    segments.retain(|segment| !segment.is_empty());
+    segments
+}
+
+/// Resolves a JSON path against a value. Numeric segments index into arrays.
+pub fn lookup_json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = value;
+    for segment in parse_json_path(path) {
+        current = match current {
+            Value::Object(map) => map.get(&segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Plain-text form of a JSON value: strings without quotes, everything else as JSON.
+pub fn value_to_plain_string(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
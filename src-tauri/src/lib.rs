@@ -1,18 +1,34 @@
 pub mod blocks;
 pub mod ca;
+pub mod delay;
+pub mod faults;
 pub mod forward_proxy;
+pub mod generators;
 pub mod handlers;
+pub mod json_path;
 pub mod logs;
 pub mod matching;
+pub mod mock_state;
+pub mod modifiers;
+pub mod network;
 pub mod process_lookup;
 pub mod proxy;
+pub mod recorder;
+pub mod resources;
+pub mod rewrites;
 pub mod system_proxy;
 pub mod response;
+pub mod routing;
 pub mod state;
 pub mod store;
 pub mod template;
+pub mod template_engine;
 pub mod types;
+pub mod validation;
+pub mod variants;
 
 pub use server::run_server;
 
 mod server;
+#[cfg(test)]
+mod test_support;
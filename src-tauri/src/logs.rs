@@ -1,20 +1,27 @@
-use crate::state::{AppState, LoggedResponse, MatchKey, RequestLogEntry, MAX_LOG_ENTRIES};
+use crate::faults::active_fault;
+use crate::matching::IncomingRequest;
+use crate::modifiers::decode_body;
+use crate::resources::ResourceMatch;
+use crate::response::header_map_to_string_map;
+use crate::state::{
+    AppState, LoggedResponse, MatchKey, RequestLogEntry, MAX_LOGGED_BODY_BYTES, MAX_LOG_ENTRIES,
+};
 use crate::types::{BlockMatch, MatchResult};
-use axum::http::Method;
+use axum::body::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
 use std::collections::HashMap;
+use std::pin::Pin;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Records a handled request and returns the id of the new log entry.
 pub async fn record_request(
     state: &AppState,
-    method: &Method,
-    path: &str,
-    query: &HashMap<String, String>,
+    incoming: &IncomingRequest<'_>,
     match_result: Option<&MatchResult>,
     block_match: Option<&BlockMatch>,
     response: Option<LoggedResponse>,
     source_app: Option<String>,
-    host: Option<String>,
-) {
+) -> u64 {
     let timestamp_ms = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|value| value.as_millis())
@@ -38,22 +45,50 @@ pub async fn record_request(
         _ => (None, None, None, None, false),
     };
 
+    let request_body = if incoming.body.is_empty() {
+        None
+    } else {
+        let end = incoming.body.len().min(MAX_LOGGED_BODY_BYTES);
+        Some(String::from_utf8_lossy(&incoming.body[..end]).to_string())
+    };
+    let request_headers = header_map_to_string_map(incoming.headers);
+    let explainable = incoming.body.len() <= MAX_LOGGED_BODY_BYTES
+        && std::str::from_utf8(incoming.body).is_ok()
+        && request_headers.len() == incoming.headers.len()
+        && !request_headers.keys().any(|name| is_redacted(name));
+
+    let mut log_store = state.log_store.lock().await;
+    log_store.next_id += 1;
+    let id = log_store.next_id;
+
     let entry = RequestLogEntry {
+        id,
         timestamp_ms,
-        method: method.as_str().to_string(),
-        path: path.to_string(),
-        query: query.clone(),
+        method: incoming.method.as_str().to_string(),
+        path: incoming.path.to_string(),
+        query: incoming.query.clone(),
+        request_headers: redact_headers(request_headers),
+        request_body,
+        explainable,
         matched,
         profile: profile.clone(),
         sub_profile,
         request: request.clone(),
         block,
-        response,
+        variant: block_match.and_then(|found| found.variant.clone()),
+        resource: None,
+        delay_ms: block_match.and_then(|found| found.delay_ms),
+        fault: block_match
+            .and_then(|found| active_fault(&found.block))
+            .map(str::to_string),
+        shadowed_blocks: block_match
+            .map(|found| found.shadowed.clone())
+            .unwrap_or_default(),
+        response: response.map(redact_response),
         source_app,
-        host,
+        host: incoming.host.map(|host| host.to_string()),
     };
 
-    let mut log_store = state.log_store.lock().await;
     log_store.entries.push_back(entry);
     if log_store.entries.len() > MAX_LOG_ENTRIES {
         log_store.entries.pop_front();
@@ -63,4 +98,343 @@ pub async fn record_request(
         let key = MatchKey { profile, request };
         *log_store.counts.entry(key).or_insert(0) += 1;
     }
+
+    id
+}
+
+/// Records a request answered by a resource collection.
+pub async fn record_resource_request(
+    state: &AppState,
+    incoming: &IncomingRequest<'_>,
+    resource_match: &ResourceMatch<'_>,
+    response: LoggedResponse,
+    source_app: Option<String>,
+) -> u64 {
+    let id = record_request(state, incoming, None, None, Some(response), source_app).await;
+    update_entry(state, id, |entry| {
+        entry.matched = true;
+        entry.profile = Some(resource_match.profile.name.clone());
+        entry.resource = Some(resource_match.resource.path.clone());
+    })
+    .await;
+    id
+}
+
+/// Fills in the response of a request recorded before it was answered.
+pub async fn record_response(state: &AppState, id: u64, response: LoggedResponse) {
+    update_entry(state, id, |entry| {
+        entry.response = Some(redact_response(response))
+    })
+    .await;
+}
+
+/// Headers whose values are credentials and are never kept in the log.
+const REDACTED_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+fn redact_headers(mut headers: HashMap<String, String>) -> HashMap<String, String> {
+    for (name, value) in headers.iter_mut() {
+        if is_redacted(name) {
+            *value = "<redacted>".to_string();
+        }
+    }
+    headers
+}
+
+fn is_redacted(name: &str) -> bool {
+    REDACTED_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+fn redact_response(mut response: LoggedResponse) -> LoggedResponse {
+    response.headers = redact_headers(response.headers);
+    response
+}
+
+/// Passes `body` through unchanged while keeping its first `MAX_LOGGED_BODY_BYTES`, which
+/// become the logged response body of entry `id` once the body ends or is dropped, as when the
+/// client goes away. Encoded bodies are decoded for the log; one that cannot be is noted by size
+/// and encoding instead.
+pub fn capture_response_body<S, E>(
+    state: AppState,
+    id: u64,
+    content_encoding: Option<String>,
+    body: S,
+) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>> + 'static,
+{
+    struct Capture<S> {
+        body: Pin<Box<S>>,
+        state: AppState,
+        id: u64,
+        content_encoding: Option<String>,
+        kept: Vec<u8>,
+        total: usize,
+        done: bool,
+    }
+
+    impl<S> Capture<S> {
+        fn logged_body(&self) -> Option<String> {
+            if self.total == 0 {
+                return None;
+            }
+            let Some(encoding) = self.content_encoding.as_deref() else {
+                return Some(String::from_utf8_lossy(&self.kept).to_string());
+            };
+            let encoding = encoding.trim().to_ascii_lowercase();
+            let decoded = (self.kept.len() == self.total)
+                .then(|| decode_body(&encoding, &self.kept))
+                .flatten();
+            Some(match decoded {
+                Some(decoded) => {
+                    let end = decoded.len().min(MAX_LOGGED_BODY_BYTES);
+                    String::from_utf8_lossy(&decoded[..end]).to_string()
+                }
+                None => format!("<{} bytes, {} encoded>", self.total, encoding),
+            })
+        }
+    }
+
+    impl<S> Drop for Capture<S> {
+        fn drop(&mut self) {
+            if self.done {
+                return;
+            }
+            // Dropped before the body ended; log what had been passed on by then.
+            let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+                return;
+            };
+            let logged_body = self.logged_body();
+            let state = self.state.clone();
+            let id = self.id;
+            runtime.spawn(async move { set_response_body(&state, id, logged_body).await });
+        }
+    }
+
+    let capture = Capture {
+        body: Box::pin(body),
+        state,
+        id,
+        content_encoding,
+        kept: Vec::new(),
+        total: 0,
+        done: false,
+    };
+    stream::unfold(capture, |mut capture| async move {
+        if capture.done {
+            return None;
+        }
+        let next = capture.body.next().await;
+        if let Some(Ok(bytes)) = &next {
+            let room = MAX_LOGGED_BODY_BYTES.saturating_sub(capture.kept.len());
+            capture
+                .kept
+                .extend_from_slice(&bytes[..bytes.len().min(room)]);
+            capture.total += bytes.len();
+            return next.map(|item| (item, capture));
+        }
+        set_response_body(&capture.state, capture.id, capture.logged_body()).await;
+        capture.done = true;
+        next.map(|item| (item, capture))
+    })
+}
+
+async fn set_response_body(state: &AppState, id: u64, body: Option<String>) {
+    update_entry(state, id, |entry| {
+        if let Some(response) = entry.response.as_mut() {
+            response.body = body;
+        }
+    })
+    .await;
+}
+
+async fn update_entry(state: &AppState, id: u64, update: impl FnOnce(&mut RequestLogEntry)) {
+    let mut log_store = state.log_store.lock().await;
+    if let Some(entry) = log_store
+        .entries
+        .iter_mut()
+        .rev()
+        .find(|entry| entry.id == id)
+    {
+        update(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, request};
+    use axum::http::Method;
+    use flate2::{write::GzEncoder, Compression};
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::io::Write;
+
+    async fn passed_through(state: &AppState) -> u64 {
+        let request = request(Method::GET, "/upstream");
+        let id = record_request(state, &request.incoming(), None, None, None, None).await;
+        let response = LoggedResponse {
+            status: Some(201),
+            headers: HashMap::from([("x-upstream".to_string(), "yes".to_string())]),
+            body: None,
+        };
+        record_response(state, id, response).await;
+        id
+    }
+
+    async fn capture(
+        state: &AppState,
+        id: u64,
+        encoding: Option<&str>,
+        chunks: &[&[u8]],
+    ) -> Vec<u8> {
+        let body = stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok::<_, Infallible>(Bytes::copy_from_slice(chunk)))
+                .collect::<Vec<_>>(),
+        );
+        capture_response_body(state.clone(), id, encoding.map(str::to_string), body)
+            .map(|chunk| chunk.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat()
+    }
+
+    async fn logged_response(state: &AppState) -> LoggedResponse {
+        let log_store = state.log_store.lock().await;
+        assert_eq!(log_store.entries.len(), 1);
+        log_store.entries[0].response.clone().unwrap()
+    }
+
+    #[tokio::test]
+    async fn upstream_responses_complete_the_pending_entry() {
+        let state = app_state();
+        let id = passed_through(&state).await;
+        let forwarded = capture(&state, id, None, &[b"hello ", b"world"]).await;
+        assert_eq!(forwarded, b"hello world");
+
+        let response = logged_response(&state).await;
+        assert_eq!(response.status, Some(201));
+        assert_eq!(response.headers["x-upstream"], "yes");
+        assert_eq!(response.body.as_deref(), Some("hello world"));
+        assert!(!state.log_store.lock().await.entries[0].matched);
+    }
+
+    #[tokio::test]
+    async fn long_bodies_are_logged_truncated_but_forwarded_whole() {
+        let state = app_state();
+        let id = passed_through(&state).await;
+        let long = vec![b'a'; MAX_LOGGED_BODY_BYTES + 10];
+        let forwarded = capture(&state, id, None, &[&long, b"end"]).await;
+        assert_eq!(forwarded.len(), MAX_LOGGED_BODY_BYTES + 13);
+
+        let body = logged_response(&state).await.body.unwrap();
+        assert_eq!(body.len(), MAX_LOGGED_BODY_BYTES);
+    }
+
+    #[tokio::test]
+    async fn encoded_bodies_are_decoded_for_the_log() {
+        let state = app_state();
+        let id = passed_through(&state).await;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"{\"ok\": true}").unwrap();
+        let gzip = encoder.finish().unwrap();
+        let (head, tail) = gzip.split_at(4);
+        let forwarded = capture(&state, id, Some(" GZIP "), &[head, tail]).await;
+        assert_eq!(forwarded, gzip);
+        assert_eq!(
+            logged_response(&state).await.body.as_deref(),
+            Some("{\"ok\": true}")
+        );
+    }
+
+    #[tokio::test]
+    async fn undecodable_and_empty_bodies_are_noted_instead_of_logged() {
+        let state = app_state();
+        let id = passed_through(&state).await;
+        capture(&state, id, Some("gzip"), &[b"\x1f\x8b\x08", b"\x00"]).await;
+        assert_eq!(
+            logged_response(&state).await.body.as_deref(),
+            Some("<4 bytes, gzip encoded>")
+        );
+
+        let state = app_state();
+        let id = passed_through(&state).await;
+        capture(&state, id, None, &[]).await;
+        assert_eq!(logged_response(&state).await.body, None);
+    }
+
+    #[tokio::test]
+    async fn bodies_dropped_midway_log_what_was_passed_on() {
+        let state = app_state();
+        let id = passed_through(&state).await;
+        let body = stream::iter([Ok::<_, Infallible>(Bytes::from_static(b"first"))])
+            .chain(stream::pending());
+        let mut captured = Box::pin(capture_response_body(state.clone(), id, None, body));
+        assert_eq!(captured.next().await.unwrap().unwrap(), "first");
+        drop(captured);
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(logged_response(&state).await.body.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn credentials_are_redacted_and_repeated_headers_joined() {
+        let state = app_state();
+        let request = request(Method::GET, "/me")
+            .header("Authorization", "Bearer secret")
+            .header("accept", "text/html")
+            .header("accept", "application/json");
+        let id = record_request(&state, &request.incoming(), None, None, None, None).await;
+        let response = LoggedResponse {
+            status: Some(200),
+            headers: HashMap::from([("Set-Cookie".to_string(), "session=1".to_string())]),
+            body: None,
+        };
+        record_response(&state, id, response).await;
+
+        let log_store = state.log_store.lock().await;
+        let entry = &log_store.entries[0];
+        assert!(!entry.explainable);
+        assert_eq!(entry.request_headers["authorization"], "<redacted>");
+        assert_eq!(
+            entry.request_headers["accept"],
+            "text/html, application/json"
+        );
+        let response = entry.response.as_ref().unwrap();
+        assert_eq!(response.headers["Set-Cookie"], "<redacted>");
+    }
+
+    #[tokio::test]
+    async fn only_requests_logged_whole_are_explainable() {
+        let state = app_state();
+        let long = "a".repeat(MAX_LOGGED_BODY_BYTES + 1);
+        let requests = [
+            request(Method::POST, "/upload")
+                .header("accept", "*/*")
+                .body("short"),
+            request(Method::POST, "/upload").body(&long),
+            request(Method::POST, "/upload").body("caf\u{e9}"),
+        ];
+        for request in &requests {
+            record_request(&state, &request.incoming(), None, None, None, None).await;
+        }
+        let mut binary = request(Method::POST, "/upload");
+        binary.body = vec![0xff, 0xfe];
+        record_request(&state, &binary.incoming(), None, None, None, None).await;
+
+        let log_store = state.log_store.lock().await;
+        let explainable: Vec<bool> = log_store
+            .entries
+            .iter()
+            .map(|entry| entry.explainable)
+            .collect();
+        assert_eq!(explainable, [true, false, true, false]);
+    }
 }
@@ -1,94 +1,382 @@
-use crate::types::{Block, BlockMatch, MatchResult, Profile, RequestConfig, Store};
+use crate::json_path::{lookup_json_path, value_to_plain_string};
+use crate::resources::find_resource;
+use crate::routing::{CompiledBlock, CompiledProfile, CompiledRequest, RoutingTable};
+use crate::template::active_variant;
+use crate::types::{
+    Block, BlockMatch, BlockRef, BodyMatcher, CandidateExplanation, FieldCondition,
+    MatchExplanation, MatchResult, Profile, RequestConfig, TemplateVariant,
+};
 use axum::http::{HeaderMap, Method};
 use regex::Regex;
+use serde_json::{Map, Value};
+use std::cmp::Reverse;
 use std::collections::HashMap;
 
+/// Everything about an incoming request that matching can look at.
+#[derive(Debug, Clone, Copy)]
+pub struct IncomingRequest<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub host: Option<&'a str>,
+    pub headers: &'a HeaderMap,
+    pub query: &'a HashMap<String, String>,
+    pub body: &'a [u8],
+}
+
 pub fn find_match(
-    store: &Store,
-    method: &Method,
-    path: &str,
-    headers: &HeaderMap,
-    query: &HashMap<String, String>,
+    table: &RoutingTable,
     active_profile: Option<&str>,
+    incoming: &IncomingRequest,
 ) -> Option<MatchResult> {
-    let active_profile = active_profile?;
-    for profile in &store.profiles {
-        if profile.name != active_profile {
-            continue;
-        }
-        for sub_profile in &profile.sub_profiles {
-            for request in &profile.requests {
-                if !method_matches(request, method) {
-                    continue;
-                }
-
-                if !headers_match(&request.headers, headers) {
-                    continue;
-                }
-
-                if !query_match(&request.query_parameters, query) {
-                    continue;
-                }
-
-                let mut params = sub_profile.params.clone();
-                params.extend(request.params.clone());
-
-                let template = build_request_path(profile, request);
-                let (regex, tokens) = compile_path_matcher(&template, &params);
-                if let Some(captures) = regex.captures(path) {
-                    let extracted = extract_params(&tokens, &captures);
-                    return Some(MatchResult {
-                        profile: profile.clone(),
-                        sub_profile: sub_profile.clone(),
-                        request: request.clone(),
-                        extracted_params: extracted,
-                    });
-                }
-            }
+    let compiled = table.profile(active_profile?)?;
+    for position in compiled.request_candidates(incoming.method, incoming.path) {
+        let candidate = &compiled.requests[position];
+        if let Ok(extracted) = evaluate_request(candidate, incoming) {
+            return Some(MatchResult {
+                profile: compiled.profile.clone(),
+                sub_profile: candidate.sub_profile.clone(),
+                request: candidate.request.clone(),
+                extracted_params: extracted,
+            });
         }
     }
 
     None
 }
 
+/// Finds the most specific active block for a request. Candidates are ranked by explicit
+/// priority, then literal paths over parameterized ones (more literal segments first), then
+/// by how many conditions they carry; active list order only breaks exact ties.
 pub fn find_block_match(
-    store: &Store,
+    table: &RoutingTable,
     active_profile: Option<&str>,
-    method: &Method,
-    path: &str,
+    incoming: &IncomingRequest,
 ) -> Option<BlockMatch> {
-    let active_profile = active_profile?;
-    let profile = store
-        .profiles
+    let compiled = table.profile(active_profile?)?;
+    let mut candidates = ranked_block_candidates(compiled, incoming).into_iter();
+    let (_, candidate, extracted_params) = candidates.next()?;
+    let mut block = candidate.block.clone();
+    let conditional_variant = select_variant(
+        &candidate.block,
+        incoming,
+        &extracted_params,
+        &candidate.patterns,
+    );
+    if let Some(variant) = conditional_variant {
+        block.active_variant_id = Some(variant.id.clone());
+    }
+    Some(BlockMatch {
+        profile: compiled.profile.clone(),
+        variant: active_variant(&block).map(|variant| variant.name.clone()),
+        conditional_variant: conditional_variant.is_some(),
+        block,
+        extracted_params,
+        shadowed: candidates
+            .map(|(_, candidate, _)| block_ref(&candidate.block))
+            .collect(),
+        state: Map::new(),
+        delay_ms: None,
+    })
+}
+
+/// The first variant whose conditions all accept the request. Variants without conditions are
+/// never picked here, so the block's active variant stays the fallback.
+fn select_variant<'a>(
+    block: &'a Block,
+    incoming: &IncomingRequest,
+    path_params: &HashMap<String, String>,
+    patterns: &Patterns,
+) -> Option<&'a TemplateVariant> {
+    let mut body: Option<(Option<Value>, HashMap<String, String>)> = None;
+    block.template_variants.iter().find(|variant| {
+        !variant.conditions.is_empty()
+            && variant.conditions.iter().all(|variant_condition| {
+                let condition = &variant_condition.condition;
+                let name = condition.name.trim();
+                let values: Vec<String> = match variant_condition.source.as_str() {
+                    "query" => incoming.query.get(name).cloned().into_iter().collect(),
+                    "header" => incoming
+                        .headers
+                        .get_all(name.to_ascii_lowercase().as_str())
+                        .iter()
+                        .filter_map(|value| value.to_str().ok())
+                        .map(|value| value.to_string())
+                        .collect(),
+                    "path" => path_params.get(name).cloned().into_iter().collect(),
+                    "body" => {
+                        let (json, form) = body.get_or_insert_with(|| parse_body(incoming.body));
+                        match json {
+                            Some(json) => lookup_json_path(json, name)
+                                .map(value_to_plain_string)
+                                .into_iter()
+                                .collect(),
+                            None => form.get(name).cloned().into_iter().collect(),
+                        }
+                    }
+                    _ => return false,
+                };
+                let values: Vec<&str> = values.iter().map(String::as_str).collect();
+                condition_matches(condition, &values, patterns)
+            })
+    })
+}
+
+/// A request body as JSON, or as form fields when it is not JSON.
+fn parse_body(body: &[u8]) -> (Option<Value>, HashMap<String, String>) {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(json) => (Some(json), HashMap::new()),
+        Err(_) => (None, parse_form_body(&String::from_utf8_lossy(body))),
+    }
+}
+
+/// Runs a request through the same checks as `find_block_match`, `find_resource` and
+/// `find_match`, in the order the proxies try them, keeping the reason every candidate was
+/// accepted or rejected.
+pub fn explain_match(
+    table: &RoutingTable,
+    active_profile: Option<&str>,
+    incoming: &IncomingRequest,
+) -> MatchExplanation {
+    let Some(compiled) = active_profile.and_then(|name| table.profile(name)) else {
+        return MatchExplanation {
+            profile: active_profile.map(|name| name.to_string()),
+            outcome: "passthrough".to_string(),
+            summary: "No active profile; the request is passed through".to_string(),
+            ..Default::default()
+        };
+    };
+
+    let ranked = ranked_block_candidates(compiled, incoming);
+    let winner: Option<&Block> = ranked.first().map(|(_, candidate, _)| &candidate.block);
+    let blocks: Vec<CandidateExplanation> = compiled
+        .blocks
         .iter()
-        .find(|profile| profile.name == active_profile)?
-        .clone();
-    for block in profile.active_blocks.iter() {
-        if !block_method_matches(block, method) {
-            continue;
-        }
-        let block_path = derive_block_path(block)?;
-        if block_path == path {
-            return Some(BlockMatch {
-                profile: profile.clone(),
-                block: block.clone(),
-                extracted_params: HashMap::new(),
-            });
-        }
-        if block_path.contains('{') && block_path.contains('}') {
-            let (regex, tokens) = compile_path_matcher(&block_path, &HashMap::new());
-            if let Some(captures) = regex.captures(path) {
-                let extracted = extract_params(&tokens, &captures);
-                return Some(BlockMatch {
-                    profile: profile.clone(),
-                    block: block.clone(),
-                    extracted_params: extracted,
-                });
+        .map(|candidate| {
+            let block = &candidate.block;
+            let (accepted, reason, params) = match (evaluate_block(candidate, incoming), winner) {
+                (Ok(params), Some(winner)) if std::ptr::eq(winner, block) => {
+                    (true, "Matched and ranked first".to_string(), params)
+                }
+                (Ok(params), winner) => (
+                    false,
+                    format!(
+                        "Matched but shadowed by higher-ranked block {}",
+                        winner.map(|block| block.name.as_str()).unwrap_or_default()
+                    ),
+                    params,
+                ),
+                (Err(reason), _) => (false, reason, HashMap::new()),
+            };
+            CandidateExplanation {
+                id: Some(block.id.clone()),
+                name: block.name.clone(),
+                sub_profile: None,
+                accepted,
+                reason,
+                params,
+            }
+        })
+        .collect();
+
+    let resource = match winner {
+        Some(_) => None,
+        None => find_resource(table, Some(&compiled.profile.name), incoming),
+    };
+
+    let mut requests = Vec::new();
+    let mut matched_request = None;
+    for candidate in &compiled.requests {
+        let (accepted, reason, params) = match evaluate_request(candidate, incoming) {
+            Err(reason) => (false, reason, HashMap::new()),
+            Ok(params) if winner.is_some() => (
+                false,
+                "Matched but a block took precedence".to_string(),
+                params,
+            ),
+            Ok(params) if resource.is_some() => (
+                false,
+                "Matched but a resource took precedence".to_string(),
+                params,
+            ),
+            Ok(params) if matched_request.is_some() => (
+                false,
+                "Matched but an earlier request won".to_string(),
+                params,
+            ),
+            Ok(params) => {
+                matched_request = Some(candidate.request.name.clone());
+                (true, "Matched".to_string(), params)
             }
+        };
+        requests.push(CandidateExplanation {
+            id: None,
+            name: candidate.request.name.clone(),
+            sub_profile: Some(candidate.sub_profile.name.clone()),
+            accepted,
+            reason,
+            params,
+        });
+    }
+
+    let matched_resource = resource.map(|found| found.resource.path.clone());
+    let (outcome, summary) = match (winner, &matched_resource, matched_request.as_ref()) {
+        (Some(block), _, _) => ("block", format!("Served by block {}", block.name)),
+        (None, Some(resource), _) => ("resource", format!("Served by resource {}", resource)),
+        (None, None, Some(request)) => ("request", format!("Served by request {}", request)),
+        (None, None, None) => (
+            "passthrough",
+            "No block, resource or request matched; the request is passed through".to_string(),
+        ),
+    };
+
+    MatchExplanation {
+        profile: Some(compiled.profile.name.clone()),
+        outcome: outcome.to_string(),
+        summary,
+        matched_block: winner.map(block_ref),
+        matched_resource,
+        matched_request,
+        blocks,
+        requests,
+    }
+}
+
+fn block_ref(block: &Block) -> BlockRef {
+    BlockRef {
+        id: block.id.clone(),
+        name: block.name.clone(),
+    }
+}
+
+/// Active blocks that accept the request, best first.
+fn ranked_block_candidates<'a>(
+    compiled: &'a CompiledProfile,
+    incoming: &IncomingRequest,
+) -> Vec<(BlockRank, &'a CompiledBlock, HashMap<String, String>)> {
+    let mut candidates: Vec<(BlockRank, &CompiledBlock, HashMap<String, String>)> = compiled
+        .block_candidates(incoming.method, incoming.path)
+        .into_iter()
+        .filter_map(|position| {
+            let candidate = &compiled.blocks[position];
+            let extracted = evaluate_block(candidate, incoming).ok()?;
+            Some((
+                block_rank(candidate, incoming.path, position),
+                candidate,
+                extracted,
+            ))
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.0.cmp(&a.0));
+    candidates
+}
+
+/// Returns the extracted path params when every condition on the request accepts it, or the
+/// reason the first failing condition rejected it.
+fn evaluate_request(
+    candidate: &CompiledRequest,
+    incoming: &IncomingRequest,
+) -> Result<HashMap<String, String>, String> {
+    let request = &candidate.request;
+    if !method_matches(request, incoming.method) {
+        return Err(format!(
+            "Method {} does not match {}",
+            incoming.method, request.method
+        ));
+    }
+    headers_match(&request.headers, incoming.headers)?;
+    query_match(&request.query_parameters, incoming.query)?;
+    body_matches(
+        &request.body,
+        &request.body_matchers,
+        incoming.body,
+        &candidate.patterns,
+    )?;
+
+    candidate
+        .path
+        .captures(incoming.path)
+        .ok_or_else(|| candidate.path.describe_mismatch(incoming.path))
+}
+
+/// Returns the extracted path params when every condition on the block accepts the request, or
+/// the reason the first failing condition rejected it.
+fn evaluate_block(
+    candidate: &CompiledBlock,
+    incoming: &IncomingRequest,
+) -> Result<HashMap<String, String>, String> {
+    let block = &candidate.block;
+    if !block_method_matches(block, incoming.method) {
+        return Err(format!(
+            "Method {} does not match {}",
+            incoming.method, block.method
+        ));
+    }
+    if let Some(pattern) = candidate.host.as_ref() {
+        if !incoming
+            .host
+            .map(|host| pattern.matches(host))
+            .unwrap_or(false)
+        {
+            return Err(format!(
+                "Host {} does not match {}",
+                incoming.host.unwrap_or("(none)"),
+                block.host.as_deref().unwrap_or_default()
+            ));
         }
     }
+    header_conditions_match(
+        &block.header_conditions,
+        incoming.headers,
+        &candidate.patterns,
+    )?;
+    query_conditions_match(&block.query_conditions, incoming.query, &candidate.patterns)?;
+    body_matches(
+        &HashMap::new(),
+        &block.body_matchers,
+        incoming.body,
+        &candidate.patterns,
+    )?;
+    let Some(path) = candidate.path.as_ref() else {
+        return Err("Block has no path".to_string());
+    };
+    path.captures(incoming.path)
+        .ok_or_else(|| path.describe_mismatch(incoming.path))
+}
 
-    None
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct BlockRank {
+    priority: i32,
+    literal: bool,
+    literal_segments: usize,
+    bounded: bool,
+    constrained: usize,
+    conditions: usize,
+    order: Reverse<usize>,
+}
+
+fn block_rank(candidate: &CompiledBlock, path: &str, index: usize) -> BlockRank {
+    let block = &candidate.block;
+    let matcher = candidate.path.as_ref();
+    let template = matcher.map(|path| path.template()).unwrap_or_default();
+    let has_method = !(block.method.is_empty() || block.method == "*");
+    BlockRank {
+        priority: block.priority.unwrap_or(0),
+        literal: template == path,
+        literal_segments: template
+            .split('/')
+            .filter(|segment| {
+                !segment.is_empty() && !segment.contains('{') && !segment.contains('*')
+            })
+            .count(),
+        bounded: !matcher.is_some_and(|path| path.has_catch_all()),
+        constrained: matcher.map_or(0, |path| path.constrained_params()),
+        conditions: usize::from(candidate.host.is_some())
+            + usize::from(has_method)
+            + block.header_conditions.len()
+            + block.query_conditions.len()
+            + block.body_matchers.len(),
+        order: Reverse(index),
+    }
 }
 
 fn method_matches(request: &RequestConfig, method: &Method) -> bool {
@@ -98,13 +386,95 @@ fn method_matches(request: &RequestConfig, method: &Method) -> bool {
     request.method.to_uppercase() == method.as_str()
 }
 
-fn headers_match(expected: &HashMap<String, String>, actual: &HeaderMap) -> bool {
-    expected.iter().all(|(key, value)| {
+fn headers_match(expected: &HashMap<String, String>, actual: &HeaderMap) -> Result<(), String> {
+    for (key, value) in expected {
         let header_value = actual
             .get(key.as_str())
             .and_then(|value| value.to_str().ok());
-        header_value.map(|actual| actual == value).unwrap_or(false)
-    })
+        match header_value {
+            Some(actual) if actual == value => {}
+            Some(actual) => {
+                return Err(format!(
+                    "Header {} is \"{}\", expected \"{}\"",
+                    key, actual, value
+                ))
+            }
+            None => return Err(format!("Header {} is missing", key)),
+        }
+    }
+    Ok(())
+}
+
+fn header_conditions_match(
+    conditions: &[FieldCondition],
+    headers: &HeaderMap,
+    patterns: &Patterns,
+) -> Result<(), String> {
+    for condition in conditions {
+        let values: Vec<&str> = headers
+            .get_all(condition.name.trim().to_ascii_lowercase().as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if !condition_matches(condition, &values, patterns) {
+            return Err(describe_failed_condition("Header", condition, &values));
+        }
+    }
+    Ok(())
+}
+
+fn query_conditions_match(
+    conditions: &[FieldCondition],
+    query: &HashMap<String, String>,
+    patterns: &Patterns,
+) -> Result<(), String> {
+    for condition in conditions {
+        let values: Vec<&str> = query
+            .get(condition.name.trim())
+            .map(|value| vec![value.as_str()])
+            .unwrap_or_default();
+        if !condition_matches(condition, &values, patterns) {
+            return Err(describe_failed_condition(
+                "Query parameter",
+                condition,
+                &values,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn describe_failed_condition(kind: &str, condition: &FieldCondition, values: &[&str]) -> String {
+    let actual = if values.is_empty() {
+        "missing".to_string()
+    } else {
+        format!("\"{}\"", values.join("\", \""))
+    };
+    match condition.operator.as_str() {
+        "present" => format!("{} {} is missing", kind, condition.name),
+        "absent" => format!("{} {} is present ({})", kind, condition.name, actual),
+        "regex" => format!(
+            "{} {} is {}, which does not match /{}/",
+            kind, condition.name, actual, condition.value
+        ),
+        _ => format!(
+            "{} {} is {}, expected \"{}\"",
+            kind, condition.name, actual, condition.value
+        ),
+    }
+}
+
+/// Evaluates a condition against every value the request carries for that name; `equals`
+/// and `regex` pass when any of them fits.
+fn condition_matches(condition: &FieldCondition, values: &[&str], patterns: &Patterns) -> bool {
+    match condition.operator.as_str() {
+        "present" => !values.is_empty(),
+        "absent" => values.is_empty(),
+        "regex" => values
+            .iter()
+            .any(|value| patterns.is_match(&condition.value, value)),
+        _ => values.iter().any(|value| *value == condition.value),
+    }
 }
 
 fn block_method_matches(block: &Block, method: &Method) -> bool {
@@ -114,7 +484,166 @@ fn block_method_matches(block: &Block, method: &Method) -> bool {
     block.method.to_uppercase() == method.as_str()
 }
 
-fn derive_block_path(block: &Block) -> Option<String> {
+/// A compiled block host pattern.
+#[derive(Debug, Clone)]
+pub enum HostPattern {
+    /// Exact hostname; `with_port` when the pattern names a port.
+    Exact { host: String, with_port: bool },
+    /// `*` wildcard pattern compiled to a regex.
+    Wildcard { regex: Regex, with_port: bool },
+    /// `regex:` pattern, tried with and without the port.
+    Regex(Regex),
+    /// A `regex:` pattern that failed to compile; never matches.
+    Invalid,
+}
+
+impl HostPattern {
+    /// Parses a host pattern; returns `None` for blank patterns, which match every host.
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return None;
+        }
+        if let Some(expression) = pattern.strip_prefix("regex:") {
+            return Some(
+                Regex::new(expression.trim())
+                    .map(HostPattern::Regex)
+                    .unwrap_or(HostPattern::Invalid),
+            );
+        }
+
+        let pattern = pattern.to_ascii_lowercase();
+        let with_port = strip_port(&pattern) != pattern;
+        if !pattern.contains('*') {
+            return Some(HostPattern::Exact {
+                host: pattern,
+                with_port,
+            });
+        }
+
+        let wildcard = pattern
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join(".*");
+        Some(
+            Regex::new(&format!("^{}$", wildcard))
+                .map(|regex| HostPattern::Wildcard { regex, with_port })
+                .unwrap_or(HostPattern::Invalid),
+        )
+    }
+
+    /// The port is ignored unless the pattern names one; hostnames compare case-insensitively.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim().to_ascii_lowercase();
+        let host_for = |with_port: bool| {
+            if with_port {
+                host.as_str()
+            } else {
+                strip_port(&host)
+            }
+        };
+        match self {
+            HostPattern::Exact {
+                host: expected,
+                with_port,
+            } => expected == host_for(*with_port),
+            HostPattern::Wildcard { regex, with_port } => regex.is_match(host_for(*with_port)),
+            HostPattern::Regex(regex) => regex.is_match(&host) || regex.is_match(strip_port(&host)),
+            HostPattern::Invalid => false,
+        }
+    }
+}
+
+/// Compares a host pattern against a request host. Blank patterns match every host.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    HostPattern::parse(pattern)
+        .map(|pattern| pattern.matches(host))
+        .unwrap_or(true)
+}
+
+/// The method, path template and host pattern a modifier or rewrite is scoped to, compiled
+/// like a block's. Blank scopes match everything.
+#[derive(Debug, Clone)]
+pub struct RuleScope {
+    method: String,
+    path: Option<PathMatcher>,
+    host: Option<HostPattern>,
+}
+
+impl RuleScope {
+    pub fn compile(method: &str, path: &str, host: Option<&str>) -> Self {
+        let path = path.trim();
+        RuleScope {
+            method: method.trim().to_string(),
+            path: (!path.is_empty()).then(|| PathMatcher::compile(path, &HashMap::new())),
+            host: host.and_then(HostPattern::parse),
+        }
+    }
+
+    /// Path params when the rule applies to the request.
+    pub fn captures(
+        &self,
+        method: &Method,
+        host: Option<&str>,
+        path: &str,
+    ) -> Option<HashMap<String, String>> {
+        if !(self.method.is_empty()
+            || self.method == "*"
+            || self.method.eq_ignore_ascii_case(method.as_str()))
+        {
+            return None;
+        }
+        if let Some(pattern) = &self.host {
+            if !host.is_some_and(|host| pattern.matches(host)) {
+                return None;
+            }
+        }
+        match &self.path {
+            Some(matcher) => matcher.captures(path),
+            None => Some(HashMap::new()),
+        }
+    }
+}
+
+/// Regexes of a block's or request's conditions and body matchers, compiled when the routing
+/// table is built and looked up by their source. A pattern that does not compile never matches.
+#[derive(Debug, Default)]
+pub struct Patterns(HashMap<String, Regex>);
+
+impl Patterns {
+    pub fn compile<'a>(sources: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut regexes = HashMap::new();
+        for source in sources {
+            if regexes.contains_key(source) {
+                continue;
+            }
+            if let Ok(regex) = Regex::new(source) {
+                regexes.insert(source.to_string(), regex);
+            }
+        }
+        Patterns(regexes)
+    }
+
+    fn is_match(&self, source: &str, text: &str) -> bool {
+        self.0.get(source).is_some_and(|regex| regex.is_match(text))
+    }
+}
+
+/// `host` without its port; IPv6 literals keep their brackets.
+pub(crate) fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.find(']').map(|end| &host[..=end]).unwrap_or(host);
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    }
+}
+
+pub(crate) fn derive_block_path(block: &Block) -> Option<String> {
     if !block.path.is_empty() {
         return Some(block.path.clone());
     }
@@ -127,16 +656,158 @@ fn derive_block_path(block: &Block) -> Option<String> {
     }
 }
 
-fn query_match(expected: &HashMap<String, String>, actual: &HashMap<String, String>) -> bool {
-    expected.iter().all(|(key, value)| {
-        actual
-            .get(key)
-            .map(|actual| actual == value)
-            .unwrap_or(false)
-    })
+fn query_match(
+    expected: &HashMap<String, String>,
+    actual: &HashMap<String, String>,
+) -> Result<(), String> {
+    for (key, value) in expected {
+        match actual.get(key) {
+            Some(actual) if actual == value => {}
+            Some(actual) => {
+                return Err(format!(
+                    "Query parameter {} is \"{}\", expected \"{}\"",
+                    key, actual, value
+                ))
+            }
+            None => return Err(format!("Query parameter {} is missing", key)),
+        }
+    }
+    Ok(())
+}
+
+/// Checks the legacy `body` field map (JSON path or form field -> expected text) and every
+/// body matcher against the raw request body.
+fn body_matches(
+    fields: &HashMap<String, String>,
+    matchers: &[BodyMatcher],
+    body: &[u8],
+    patterns: &Patterns,
+) -> Result<(), String> {
+    if fields.is_empty() && matchers.is_empty() {
+        return Ok(());
+    }
+    let text = String::from_utf8_lossy(body);
+    let (json, form) = parse_body(body);
+
+    for (key, expected) in fields {
+        let actual = match json.as_ref() {
+            Some(json) => lookup_json_path(json, key).map(value_to_plain_string),
+            None => form.get(key).cloned(),
+        };
+        match actual {
+            Some(actual) if actual == *expected => {}
+            Some(actual) => {
+                return Err(format!(
+                    "Body field {} is \"{}\", expected \"{}\"",
+                    key, actual, expected
+                ))
+            }
+            None => return Err(format!("Body field {} is missing", key)),
+        }
+    }
+
+    for matcher in matchers {
+        if !body_matcher_matches(matcher, &text, json.as_ref(), patterns) {
+            return Err(match matcher.path.as_deref() {
+                Some(path) if matcher.matcher_type == "jsonPath" => format!(
+                    "Body does not satisfy {} {} = {}",
+                    matcher.matcher_type, path, matcher.value
+                ),
+                _ => format!(
+                    "Body does not satisfy {} {}",
+                    matcher.matcher_type, matcher.value
+                ),
+            });
+        }
+    }
+    Ok(())
 }
 
-fn build_request_path(profile: &Profile, request: &RequestConfig) -> String {
+fn body_matcher_matches(
+    matcher: &BodyMatcher,
+    text: &str,
+    json: Option<&Value>,
+    patterns: &Patterns,
+) -> bool {
+    let expected_json = serde_json::from_str::<Value>(&matcher.value).ok();
+    match matcher.matcher_type.as_str() {
+        "json" => match (json, expected_json.as_ref()) {
+            (Some(actual), Some(expected)) => actual == expected,
+            _ => text.trim() == matcher.value.trim(),
+        },
+        "jsonPath" => {
+            let Some(path) = matcher.path.as_deref() else {
+                return false;
+            };
+            let actual = json.and_then(|json| lookup_json_path(json, path));
+            match (actual, expected_json.as_ref()) {
+                (Some(actual), Some(expected)) if actual == expected => true,
+                (Some(actual), _) => value_to_plain_string(actual) == matcher.value,
+                (None, _) => false,
+            }
+        }
+        "contains" => match (json, expected_json.as_ref()) {
+            (Some(actual), Some(expected)) => json_contains(actual, expected),
+            _ => text.contains(matcher.value.as_str()),
+        },
+        "regex" => patterns.is_match(&matcher.value, text),
+        _ => false,
+    }
+}
+
+/// True when every field of `expected` is present in `actual`; arrays need each expected item
+/// to match some actual item.
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .map(|actual| json_contains(actual, value))
+                .unwrap_or(false)
+        }),
+        (Value::Array(actual), Value::Array(expected)) => expected
+            .iter()
+            .all(|value| actual.iter().any(|item| json_contains(item, value))),
+        _ => actual == expected,
+    }
+}
+
+/// Decodes an `application/x-www-form-urlencoded` body or query string.
+pub fn parse_form_body(text: &str) -> HashMap<String, String> {
+    text.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_form_component(key), decode_form_component(value))
+        })
+        .collect()
+}
+
+fn decode_form_component(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+pub(crate) fn build_request_path(profile: &Profile, request: &RequestConfig) -> String {
     let base = normalize_path(&profile.base_url);
     let path = normalize_path(&request.path);
     if profile.base_url.is_empty() {
@@ -159,72 +830,898 @@ fn normalize_path(value: &str) -> String {
     }
 }
 
-#[derive(Debug)]
+/// A block or request path template compiled once into a regex.
+#[derive(Debug, Clone)]
+pub struct PathMatcher {
+    template: String,
+    regex: Option<Regex>,
+    tokens: Vec<ParamToken>,
+    literal: bool,
+    error: Option<String>,
+}
+
+impl PathMatcher {
+    /// Compiles `template`, substituting any `{param}` that has a value in `param_values`.
+    /// Templates with a bad constraint never match; the reason is kept for match explanations.
+    pub fn compile(template: &str, param_values: &HashMap<String, String>) -> Self {
+        let (regex, tokens, error) = match compile_path_matcher(template, param_values) {
+            Ok((regex, tokens)) => (Some(regex), tokens, None),
+            Err(error) => (None, Vec::new(), Some(error)),
+        };
+        PathMatcher {
+            template: template.to_string(),
+            regex,
+            literal: error.is_none() && tokens.is_empty(),
+            tokens,
+            error,
+        }
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.regex.as_ref().map(Regex::as_str).unwrap_or_default()
+    }
+
+    pub fn is_literal(&self) -> bool {
+        self.literal
+    }
+
+    /// Whether the template ends in `{*name}` or `**` and so accepts any remaining path.
+    pub fn has_catch_all(&self) -> bool {
+        self.tokens
+            .iter()
+            .any(|token| token.capture && token.kind == ParamKind::CatchAll)
+    }
+
+    /// Number of captured params restricted by an `int`, `uuid` or `regex(...)` constraint.
+    pub fn constrained_params(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|token| token.capture && token.kind == ParamKind::Constrained)
+            .count()
+    }
+
+    /// Names of the params captured from the path.
+    pub fn param_names(&self) -> impl Iterator<Item = &str> {
+        self.tokens
+            .iter()
+            .filter(|token| token.capture)
+            .map(|token| token.name.as_str())
+    }
+
+    /// Path params for a matching path, or `None` when the path does not fit.
+    pub fn captures(&self, path: &str) -> Option<HashMap<String, String>> {
+        if self.literal {
+            return (self.template == path).then(HashMap::new);
+        }
+        let captures = self.regex.as_ref()?.captures(path)?;
+        Some(extract_params(&self.tokens, &captures))
+    }
+
+    /// Reason a path was rejected, for match explanations.
+    pub fn describe_mismatch(&self, path: &str) -> String {
+        if let Some(error) = &self.error {
+            return format!("Path template {} is invalid: {}", self.template, error);
+        }
+        if self.literal {
+            return format!("Path {} does not match {}", path, self.template);
+        }
+        format!(
+            "Path {} does not match {} (pattern {})",
+            path,
+            self.template,
+            self.pattern()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParamKind {
+    Segment,
+    Constrained,
+    CatchAll,
+}
+
+#[derive(Debug, Clone)]
 struct ParamToken {
     name: String,
     value: Option<String>,
     capture: bool,
+    kind: ParamKind,
 }
 
+const UUID_PATTERN: &str =
+    "[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}";
+
+/// Compiles a path template into an anchored regex. Supported placeholders:
+/// `{name}` (one segment), `{name:int}`, `{name:uuid}`, `{name:regex(...)}`, and the catch-alls
+/// `{*name}` and `**` (captured as `wildcard`), which match the rest of the path.
 fn compile_path_matcher(
     template: &str,
     param_values: &HashMap<String, String>,
-) -> (Regex, Vec<ParamToken>) {
-    let param_regex = Regex::new(r"\{([^}]+)\}").expect("invalid param regex");
+) -> Result<(Regex, Vec<ParamToken>), String> {
     let mut pattern = String::new();
     let mut tokens = Vec::new();
-    let mut last_index = 0;
+    let mut literal_start = 0;
+    let mut index = 0;
 
-    for capture in param_regex.captures_iter(template) {
-        let match_span = capture.get(0).expect("capture span");
-        pattern.push_str(&regex::escape(&template[last_index..match_span.start()]));
+    while index < template.len() {
+        let rest = &template[index..];
+        let placeholder = if rest.starts_with("**") {
+            Some(("*wildcard", 2))
+        } else if rest.starts_with('{') {
+            placeholder_end(rest).map(|end| (&rest[1..end], end + 1))
+        } else {
+            None
+        };
+        let Some((spec, length)) = placeholder else {
+            index += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
 
-        let name = capture
-            .get(1)
-            .expect("capture group")
-            .as_str()
-            .trim()
-            .to_string();
+        pattern.push_str(&regex::escape(&template[literal_start..index]));
+        index += length;
+        literal_start = index;
 
+        let (name, kind, param_pattern) = parse_placeholder(spec)?;
+        let group = format!("__mapy{}", tokens.len());
         if let Some(value) = param_values.get(&name) {
             pattern.push_str(&regex::escape(value));
             tokens.push(ParamToken {
                 name,
                 value: Some(value.clone()),
                 capture: false,
+                kind,
             });
-        } else {
-            pattern.push_str("([^/]+)");
-            tokens.push(ParamToken {
-                name,
-                value: None,
-                capture: true,
-            });
+            continue;
         }
 
-        last_index = match_span.end();
+        if kind == ParamKind::CatchAll && index == template.len() && pattern.ends_with('/') {
+            // `/files/**` also matches `/files` itself.
+            pattern.pop();
+            pattern.push_str(&format!("(?:/(?P<{}>{}))?", group, param_pattern));
+        } else {
+            pattern.push_str(&format!("(?P<{}>{})", group, param_pattern));
+        }
+        tokens.push(ParamToken {
+            name,
+            value: None,
+            capture: true,
+            kind,
+        });
     }
 
-    pattern.push_str(&regex::escape(&template[last_index..]));
-    let regex = Regex::new(&format!("^{}$", pattern)).expect("invalid matcher regex");
+    pattern.push_str(&regex::escape(&template[literal_start..]));
+    let regex = Regex::new(&format!("^{}$", pattern)).map_err(|error| error.to_string())?;
 
-    (regex, tokens)
+    Ok((regex, tokens))
+}
+
+/// Byte offset of the `}` closing the placeholder at the start of `rest`, allowing nested braces
+/// (e.g. `{code:regex([A-Z]{3})}`) and backslash escapes inside constraints.
+fn placeholder_end(rest: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (offset, character) in rest.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match character {
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(offset);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_placeholder(spec: &str) -> Result<(String, ParamKind, String), String> {
+    let spec = spec.trim();
+    if let Some(name) = spec.strip_prefix('*') {
+        return Ok((
+            name.trim().to_string(),
+            ParamKind::CatchAll,
+            ".*".to_string(),
+        ));
+    }
+    let Some((name, constraint)) = spec.split_once(':') else {
+        return Ok((spec.to_string(), ParamKind::Segment, "[^/]+".to_string()));
+    };
+    let constraint = constraint.trim();
+    let param_pattern = match constraint {
+        "int" => r"\d+".to_string(),
+        "uuid" => UUID_PATTERN.to_string(),
+        _ => {
+            let inner = constraint
+                .strip_prefix("regex(")
+                .and_then(|inner| inner.strip_suffix(')'))
+                .ok_or_else(|| format!("Unknown constraint {} on {{{}}}", constraint, spec))?;
+            Regex::new(inner)
+                .map_err(|error| format!("Invalid regex on {{{}}}: {}", spec, error))?;
+            format!("(?:{})", inner)
+        }
+    };
+    Ok((
+        name.trim().to_string(),
+        ParamKind::Constrained,
+        param_pattern,
+    ))
 }
 
 fn extract_params(tokens: &[ParamToken], captures: &regex::Captures) -> HashMap<String, String> {
     let mut values = HashMap::new();
-    let mut capture_index = 1;
 
-    for token in tokens {
+    for (position, token) in tokens.iter().enumerate() {
         if let Some(value) = &token.value {
             values.insert(token.name.clone(), value.clone());
         } else if token.capture {
-            if let Some(value) = captures.get(capture_index) {
-                values.insert(token.name.clone(), value.as_str().to_string());
+            let value = captures
+                .name(&format!("__mapy{}", position))
+                .map(|value| value.as_str().to_string());
+            match value {
+                Some(value) => {
+                    values.insert(token.name.clone(), value);
+                }
+                None if token.kind == ParamKind::CatchAll => {
+                    values.insert(token.name.clone(), String::new());
+                }
+                None => {}
             }
-            capture_index += 1;
         }
     }
 
     values
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block, request, routing_table, store, Request};
+    use crate::types::{SubProfile, TemplateVariant, VariantCondition};
+
+    fn matched(table: &RoutingTable, request: Request) -> Option<String> {
+        find_block_match(table, Some("default"), &request.incoming()).map(|found| found.block.id)
+    }
+
+    fn get(path: &str) -> Request {
+        request(Method::GET, path)
+    }
+
+    fn post(body: &str) -> Request {
+        request(Method::POST, "/users").body(body)
+    }
+
+    fn pinned(id: &str, path: &str, host: &str) -> Block {
+        Block {
+            host: Some(host.to_string()),
+            ..block(id, "GET", path)
+        }
+    }
+
+    fn matcher(matcher_type: &str, path: Option<&str>, value: &str) -> BodyMatcher {
+        BodyMatcher {
+            matcher_type: matcher_type.to_string(),
+            path: path.map(str::to_string),
+            value: value.to_string(),
+        }
+    }
+
+    fn body_block(id: &str, matchers: Vec<BodyMatcher>) -> Block {
+        Block {
+            body_matchers: matchers,
+            ..block(id, "POST", "/users")
+        }
+    }
+
+    #[test]
+    fn exact_and_wildcard_hosts_ignore_case_and_port() {
+        assert!(host_matches("api.example.com", "API.example.com:8443"));
+        assert!(!host_matches("api.example.com", "api.example.org"));
+        assert!(host_matches("*.example.com", "eu.api.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(host_matches("[::1]", "[::1]:3000"));
+    }
+
+    #[test]
+    fn host_patterns_with_a_port_require_it() {
+        assert!(host_matches("localhost:3000", "localhost:3000"));
+        assert!(!host_matches("localhost:3000", "localhost:4000"));
+        assert!(!host_matches("localhost:3000", "localhost"));
+    }
+
+    #[test]
+    fn regex_hosts_match_with_or_without_the_port() {
+        assert!(host_matches(
+            r"regex:^api\d+\.example\.com$",
+            "api7.example.com:443"
+        ));
+        assert!(!host_matches(
+            r"regex:^api\d+\.example\.com$",
+            "api.example.com"
+        ));
+        assert!(!host_matches("regex:(", "api.example.com"));
+    }
+
+    #[test]
+    fn blocks_pinned_to_a_host_only_answer_that_host() {
+        let table = routing_table(vec![
+            pinned("eu", "/users", "eu.example.com"),
+            pinned("us", "/users", "*.us.example.com"),
+            block("any", "GET", "/users"),
+        ]);
+
+        assert_eq!(
+            matched(&table, get("/users").host("eu.example.com")).as_deref(),
+            Some("eu")
+        );
+        assert_eq!(
+            matched(&table, get("/users").host("a.us.example.com")).as_deref(),
+            Some("us")
+        );
+        assert_eq!(
+            matched(&table, get("/users").host("other.test")).as_deref(),
+            Some("any")
+        );
+        assert_eq!(matched(&table, get("/users")).as_deref(), Some("any"));
+    }
+
+    #[test]
+    fn blank_host_patterns_match_every_host() {
+        let table = routing_table(vec![pinned("blank", "/users", "  ")]);
+
+        assert_eq!(matched(&table, get("/users")).as_deref(), Some("blank"));
+        assert_eq!(
+            matched(&table, get("/users").host("a.test")).as_deref(),
+            Some("blank")
+        );
+    }
+
+    #[test]
+    fn json_matchers_compare_values_not_formatting() {
+        let table = routing_table(vec![body_block(
+            "exact",
+            vec![matcher("json", None, r#"{"a": 1, "b": [true]}"#)],
+        )]);
+
+        assert!(matched(&table, post(r#"{"b":[true],"a":1}"#)).is_some());
+        assert!(matched(&table, post(r#"{"a":1,"b":[true],"c":null}"#)).is_none());
+        assert!(matched(&table, post("")).is_none());
+    }
+
+    #[test]
+    fn json_path_matchers_accept_json_or_plain_expected_values() {
+        let table = routing_table(vec![
+            body_block(
+                "number",
+                vec![matcher("jsonPath", Some("$.user.age"), "42")],
+            ),
+            body_block(
+                "text",
+                vec![matcher("jsonPath", Some("user.emails[1]"), "b@x.io")],
+            ),
+        ]);
+
+        assert_eq!(
+            matched(&table, post(r#"{"user":{"age":42}}"#)).as_deref(),
+            Some("number")
+        );
+        assert_eq!(
+            matched(
+                &table,
+                post(r#"{"user":{"age":7,"emails":["a@x.io","b@x.io"]}}"#)
+            )
+            .as_deref(),
+            Some("text")
+        );
+        assert!(matched(&table, post(r#"{"user":{"age":"7"}}"#)).is_none());
+        assert!(matched(&table, post("age=42")).is_none());
+    }
+
+    #[test]
+    fn contains_matchers_accept_json_subsets_or_text() {
+        let table = routing_table(vec![
+            body_block(
+                "subset",
+                vec![matcher(
+                    "contains",
+                    None,
+                    r#"{"tags":["b"],"user":{"id":1}}"#,
+                )],
+            ),
+            body_block("text", vec![matcher("contains", None, "needle")]),
+        ]);
+
+        assert_eq!(
+            matched(
+                &table,
+                post(r#"{"tags":["a","b"],"user":{"id":1,"name":"Ada"}}"#)
+            )
+            .as_deref(),
+            Some("subset")
+        );
+        assert!(matched(&table, post(r#"{"tags":["a"],"user":{"id":1}}"#)).is_none());
+        assert_eq!(
+            matched(&table, post("hay needle stack")).as_deref(),
+            Some("text")
+        );
+    }
+
+    #[test]
+    fn every_matcher_of_a_block_must_hold() {
+        let table = routing_table(vec![body_block(
+            "both",
+            vec![
+                matcher("regex", None, r"^\{.*\}$"),
+                matcher("jsonPath", Some("kind"), "order"),
+            ],
+        )]);
+
+        assert!(matched(&table, post(r#"{"kind":"order"}"#)).is_some());
+        assert!(matched(&table, post(r#"{"kind":"refund"}"#)).is_none());
+        assert!(body_matches(
+            &HashMap::new(),
+            &[matcher("regex", None, "(")],
+            b"(",
+            &Patterns::compile(["("])
+        )
+        .is_err());
+        assert!(body_matches(
+            &HashMap::new(),
+            &[matcher("xml", None, "")],
+            b"",
+            &Patterns::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn legacy_body_fields_read_json_paths_or_form_fields() {
+        let fields = HashMap::from([("user.name".to_string(), "Ada L".to_string())]);
+
+        assert!(body_matches(
+            &fields,
+            &[],
+            br#"{"user":{"name":"Ada L"}}"#,
+            &Patterns::default()
+        )
+        .is_ok());
+        assert!(body_matches(&fields, &[], b"user.name=Ada+L&x=%41", &Patterns::default()).is_ok());
+        assert!(body_matches(&fields, &[], b"user.name=Grace", &Patterns::default()).is_err());
+        assert_eq!(parse_form_body("a=%41%2&b")["a"], "A%2");
+    }
+
+    fn condition(name: &str, operator: &str, value: &str) -> FieldCondition {
+        FieldCondition {
+            name: name.to_string(),
+            operator: operator.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn conditional(headers: Vec<FieldCondition>, query: Vec<FieldCondition>) -> Block {
+        Block {
+            header_conditions: headers,
+            query_conditions: query,
+            ..block("conditional", "GET", "/users")
+        }
+    }
+
+    #[test]
+    fn header_conditions_check_every_value_of_the_header() {
+        let table = routing_table(vec![conditional(
+            vec![
+                condition("X-Tenant", "equals", "acme"),
+                condition("Authorization", "regex", "^Bearer "),
+            ],
+            Vec::new(),
+        )]);
+        let tenants = |tenants: &[&str]| {
+            tenants.iter().fold(
+                get("/users").header("authorization", "Bearer t"),
+                |request, tenant| request.header("x-tenant", tenant),
+            )
+        };
+
+        assert!(matched(&table, tenants(&["acme"])).is_some());
+        assert!(matched(&table, tenants(&["other", "acme"])).is_some());
+        assert!(matched(&table, tenants(&["ACME"])).is_none());
+        assert!(matched(&table, get("/users").header("x-tenant", "acme")).is_none());
+    }
+
+    #[test]
+    fn present_and_absent_only_look_at_the_name() {
+        let table = routing_table(vec![conditional(
+            vec![condition("x-debug", "present", "")],
+            vec![condition("page", "absent", "")],
+        )]);
+
+        assert!(matched(&table, get("/users").header("x-debug", "")).is_some());
+        assert!(matched(&table, get("/users")).is_none());
+        assert!(matched(
+            &table,
+            get("/users").header("x-debug", "1").query("page", "2")
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn query_conditions_compare_decoded_values() {
+        let table = routing_table(vec![conditional(
+            Vec::new(),
+            vec![
+                condition(" status ", "equals", "open"),
+                condition("page", "regex", r"^\d+$"),
+            ],
+        )]);
+
+        assert!(matched(
+            &table,
+            get("/users").query("status", "open").query("page", "3")
+        )
+        .is_some());
+        assert!(matched(
+            &table,
+            get("/users").query("status", "open").query("page", "x")
+        )
+        .is_none());
+        assert!(matched(&table, get("/users").query("page", "3")).is_none());
+    }
+
+    #[test]
+    fn bad_condition_regexes_never_match() {
+        let table = routing_table(vec![conditional(
+            vec![condition("x-id", "regex", "(")],
+            Vec::new(),
+        )]);
+
+        assert!(matched(&table, get("/users").header("x-id", "(")).is_none());
+    }
+
+    #[test]
+    fn literal_path_beats_parameter_regardless_of_order() {
+        let table = routing_table(vec![
+            block("by-id", "GET", "/users/{id}"),
+            block("me", "GET", "/users/me"),
+        ]);
+
+        assert_eq!(matched(&table, get("/users/me")).as_deref(), Some("me"));
+        assert_eq!(matched(&table, get("/users/7")).as_deref(), Some("by-id"));
+    }
+
+    #[test]
+    fn ranking_prefers_literal_segments_then_conditions_then_order() {
+        let table = routing_table(vec![
+            block("any", "*", "/{kind}/{id}"),
+            block("first", "*", "/users/{id}"),
+            block("second", "*", "/users/{id}"),
+            block("get", "GET", "/users/{id}"),
+        ]);
+
+        assert_eq!(matched(&table, get("/users/7")).as_deref(), Some("get"));
+        assert_eq!(
+            matched(&table, request(Method::DELETE, "/users/7")).as_deref(),
+            Some("first")
+        );
+        assert_eq!(matched(&table, get("/teams/7")).as_deref(), Some("any"));
+    }
+
+    #[test]
+    fn explicit_priority_wins_and_shadowed_blocks_are_listed() {
+        let fallback = Block {
+            priority: Some(1),
+            ..block("fallback", "*", "/users/{id}")
+        };
+        let table = routing_table(vec![
+            block("me", "GET", "/users/me"),
+            fallback,
+            block("other", "POST", "/users/me"),
+        ]);
+
+        let found =
+            find_block_match(&table, Some("default"), &get("/users/me").incoming()).unwrap();
+        assert_eq!(found.block.id, "fallback");
+        assert_eq!(found.extracted_params["id"], "me");
+        let shadowed: Vec<&str> = found.shadowed.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(shadowed, ["me"]);
+    }
+
+    fn explain(table: &RoutingTable, request: Request) -> MatchExplanation {
+        explain_match(table, Some("default"), &request.incoming())
+    }
+
+    fn reasons(candidates: &[CandidateExplanation]) -> Vec<(&str, bool, &str)> {
+        candidates
+            .iter()
+            .map(|candidate| {
+                (
+                    candidate.name.as_str(),
+                    candidate.accepted,
+                    candidate.reason.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn explanations_give_the_reason_for_every_block() {
+        let table = routing_table(vec![
+            block("by-id", "GET", "/users/{id}"),
+            block("me", "GET", "/users/me"),
+            pinned("eu", "/users/me", "eu.example.com"),
+            conditional(vec![condition("x-tenant", "equals", "acme")], Vec::new()),
+            block("post", "POST", "/users/me"),
+        ]);
+
+        let explanation = explain(&table, get("/users/me").host("us.example.com"));
+        assert_eq!(explanation.outcome, "block");
+        assert_eq!(explanation.summary, "Served by block me");
+        assert_eq!(
+            explanation.matched_block.map(|block| block.id).as_deref(),
+            Some("me")
+        );
+        assert_eq!(
+            reasons(&explanation.blocks),
+            [
+                (
+                    "by-id",
+                    false,
+                    "Matched but shadowed by higher-ranked block me"
+                ),
+                ("me", true, "Matched and ranked first"),
+                (
+                    "eu",
+                    false,
+                    "Host us.example.com does not match eu.example.com"
+                ),
+                (
+                    "conditional",
+                    false,
+                    "Header x-tenant is missing, expected \"acme\""
+                ),
+                ("post", false, "Method GET does not match POST"),
+            ]
+        );
+        assert_eq!(explanation.blocks[0].params["id"], "me");
+    }
+
+    #[test]
+    fn explanations_fall_back_to_requests_and_passthrough() {
+        let mut store = store(vec![block("me", "GET", "/users/me")]);
+        let profile = &mut store.profiles[0];
+        profile.sub_profiles = vec![SubProfile {
+            name: "dev".to_string(),
+            ..Default::default()
+        }];
+        profile.requests = vec![RequestConfig {
+            name: "list".to_string(),
+            path: "/users".to_string(),
+            method: "GET".to_string(),
+            ..Default::default()
+        }];
+        let table = RoutingTable::build(store);
+
+        let explanation = explain(&table, get("/users"));
+        assert_eq!(explanation.outcome, "request");
+        assert_eq!(explanation.matched_request.as_deref(), Some("list"));
+        assert_eq!(explanation.requests[0].sub_profile.as_deref(), Some("dev"));
+        assert!(explanation.blocks[0]
+            .reason
+            .starts_with("Path /users does not match /users/me"));
+
+        let explanation = explain(&table, get("/users/me"));
+        assert_eq!(
+            reasons(&explanation.requests)[0].2,
+            "Path /users/me does not match /users"
+        );
+
+        let explanation = explain(&table, get("/teams"));
+        assert_eq!(explanation.outcome, "passthrough");
+        assert!(explanation.matched_block.is_none());
+
+        let explanation = explain_match(&table, None, &get("/users").incoming());
+        assert_eq!(explanation.outcome, "passthrough");
+        assert!(explanation.blocks.is_empty());
+    }
+
+    fn captures(template: &str, path: &str) -> Option<HashMap<String, String>> {
+        PathMatcher::compile(template, &HashMap::new()).captures(path)
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn ranking_prefers_constraints_bounded_paths_and_methods() {
+        let table = routing_table(vec![
+            block("rest", "GET", "/files/**"),
+            block("any-method", "*", "/files/{name}"),
+            block("name", "GET", "/files/{name}"),
+            block("number", "GET", "/files/{name:int}"),
+        ]);
+
+        assert_eq!(matched(&table, get("/files/42")).as_deref(), Some("number"));
+        assert_eq!(matched(&table, get("/files/a")).as_deref(), Some("name"));
+        assert_eq!(
+            matched(&table, request(Method::POST, "/files/a")).as_deref(),
+            Some("any-method")
+        );
+        assert_eq!(matched(&table, get("/files/a/b")).as_deref(), Some("rest"));
+    }
+
+    #[test]
+    fn typed_params_only_capture_matching_segments() {
+        assert_eq!(
+            captures("/orders/{id:int}", "/orders/12"),
+            Some(params(&[("id", "12")]))
+        );
+        assert_eq!(captures("/orders/{id:int}", "/orders/12a"), None);
+        let uuid = "3f2504e0-4f89-41d3-9a0c-0305e82c3301";
+        assert_eq!(
+            captures("/orders/{id:uuid}", &format!("/orders/{}", uuid)),
+            Some(params(&[("id", uuid)]))
+        );
+        assert_eq!(captures("/orders/{id:uuid}", "/orders/3f2504e0"), None);
+        assert_eq!(
+            captures("/codes/{code:regex([A-Z]{3})}", "/codes/ABC"),
+            Some(params(&[("code", "ABC")]))
+        );
+        assert_eq!(
+            captures("/codes/{code:regex([A-Z]{3})}", "/codes/ABCD"),
+            None
+        );
+    }
+
+    #[test]
+    fn segment_params_stop_at_slashes_and_literals_are_escaped() {
+        assert_eq!(
+            captures("/users/{id}/posts/{post}", "/users/a%20b/posts/9"),
+            Some(params(&[("id", "a%20b"), ("post", "9")]))
+        );
+        assert_eq!(captures("/users/{id}", "/users/a/b"), None);
+        assert_eq!(captures("/users/{id}", "/users/"), None);
+        assert_eq!(captures("/v1.0/{id}", "/v1x0/7"), None);
+        assert_eq!(
+            captures("/v1.0/{id}", "/v1.0/7"),
+            Some(params(&[("id", "7")]))
+        );
+    }
+
+    #[test]
+    fn catch_alls_capture_the_rest_of_the_path() {
+        assert_eq!(
+            captures("/static/{*path}", "/static/css/site.css"),
+            Some(params(&[("path", "css/site.css")]))
+        );
+        assert_eq!(
+            captures("/files/**", "/files/a/b"),
+            Some(params(&[("wildcard", "a/b")]))
+        );
+        assert_eq!(
+            captures("/files/**", "/files"),
+            Some(params(&[("wildcard", "")]))
+        );
+        assert_eq!(captures("/files/**", "/filesystem"), None);
+    }
+
+    #[test]
+    fn bad_constraints_never_match_and_explain_why() {
+        let matcher = PathMatcher::compile("/items/{id:float}", &HashMap::new());
+        assert_eq!(matcher.captures("/items/1.5"), None);
+        assert!(matcher
+            .describe_mismatch("/items/1.5")
+            .contains("Unknown constraint float"));
+        let matcher = PathMatcher::compile("/items/{id:regex([)}", &HashMap::new());
+        assert_eq!(matcher.captures("/items/["), None);
+        assert!(matcher
+            .describe_mismatch("/items/[")
+            .contains("Invalid regex"));
+    }
+
+    #[test]
+    fn known_param_values_are_substituted_before_matching() {
+        let matcher = PathMatcher::compile(
+            "/tenants/{tenant}/users/{id}",
+            &params(&[("tenant", "a.b")]),
+        );
+        assert_eq!(
+            matcher.captures("/tenants/a.b/users/3"),
+            Some(params(&[("tenant", "a.b"), ("id", "3")]))
+        );
+        assert_eq!(matcher.captures("/tenants/axb/users/3"), None);
+        assert_eq!(matcher.param_names().collect::<Vec<_>>(), ["id"]);
+    }
+
+    fn variant(id: &str, conditions: &[(&str, &str, &str, &str)]) -> TemplateVariant {
+        TemplateVariant {
+            id: id.to_string(),
+            name: id.to_string(),
+            conditions: conditions
+                .iter()
+                .map(|(source, name, operator, value)| VariantCondition {
+                    source: source.to_string(),
+                    condition: condition(name, operator, value),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn variants_are_picked_by_their_conditions() {
+        let orders = Block {
+            active_variant_id: Some("default".to_string()),
+            template_variants: vec![
+                variant("default", &[]),
+                variant("vip", &[("header", "X-Tier", "equals", "gold")]),
+                variant("big", &[("body", "$.items[1].qty", "regex", r"^\d{3,}$")]),
+                variant(
+                    "legacy",
+                    &[("path", "id", "regex", "^0"), ("query", "v", "equals", "1")],
+                ),
+                variant("form", &[("body", "coupon", "present", "")]),
+            ],
+            ..block("orders", "POST", "/orders/{id}")
+        };
+        let table = routing_table(vec![orders]);
+        let picked = |request: Request| {
+            find_block_match(&table, Some("default"), &request.incoming())
+                .unwrap()
+                .variant
+                .unwrap()
+        };
+        let order = |id: &str| request(Method::POST, &format!("/orders/{}", id));
+
+        assert_eq!(picked(order("1")), "default");
+        assert_eq!(picked(order("1").header("x-tier", "gold")), "vip");
+        assert_eq!(picked(order("1").header("x-tier", "silver")), "default");
+        assert_eq!(
+            picked(order("1").body(r#"{"items": [{"qty": 1}, {"qty": 250}]}"#)),
+            "big"
+        );
+        assert_eq!(
+            picked(order("1").body(r#"{"items": [{"qty": 250}]}"#)),
+            "default"
+        );
+        assert_eq!(picked(order("01").query("v", "1")), "legacy");
+        assert_eq!(picked(order("01").query("v", "2")), "default");
+        assert_eq!(picked(order("1").body("coupon=&x=1")), "form");
+        assert_eq!(
+            picked(order("1").header("x-tier", "gold").body("coupon=1")),
+            "vip"
+        );
+    }
+
+    #[test]
+    fn explanations_report_resources_when_no_block_wins() {
+        let mut store = store(vec![block("other", "GET", "/api/other")]);
+        store.profiles[0].resources = vec![crate::types::Resource {
+            id: "todos".to_string(),
+            path: "/api/todos".to_string(),
+            id_field: "id".to_string(),
+            seed: Vec::new(),
+        }];
+        let table = RoutingTable::build(store);
+
+        let explanation = explain(&table, get("/api/todos/3"));
+        assert_eq!(explanation.outcome, "resource");
+        assert_eq!(explanation.matched_resource.as_deref(), Some("/api/todos"));
+        let explanation = explain(&table, get("/api/other"));
+        assert_eq!(explanation.outcome, "block");
+        assert_eq!(explanation.matched_resource, None);
+        assert_eq!(explain(&table, get("/api/none")).outcome, "passthrough");
+    }
+}
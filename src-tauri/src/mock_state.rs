@@ -0,0 +1,232 @@
+use crate::generators::Generator;
+use crate::json_path::{lookup_json_path, value_to_plain_string};
+use crate::matching::IncomingRequest;
+use crate::state::AppState;
+use crate::template::{merged_template_values, render_template};
+use crate::types::{BlockMatch, StateEffect};
+use serde_json::{Map, Value};
+
+/// Runs the matched block's effects against its profile's mock state and hands the resulting
+/// state to the match so the response can read it.
+pub async fn apply_effects(
+    state: &AppState,
+    block_match: &mut BlockMatch,
+    incoming: &IncomingRequest<'_>,
+) {
+    let mut mock_state = state.mock_state.lock().await;
+    let profile_state = mock_state
+        .entry(block_match.profile.name.clone())
+        .or_default();
+    if !block_match.block.effects.is_empty() {
+        block_match.state = profile_state.clone();
+        let values = merged_template_values(block_match, incoming);
+        let mut generator = Generator::new(block_match.block.seed.or(block_match.profile.seed));
+        for effect in &block_match.block.effects {
+            let rendered = render_template(&effect.value, &values, &mut generator);
+            apply_effect(profile_state, effect, &rendered, incoming.body);
+        }
+    }
+    block_match.state = profile_state.clone();
+}
+
+fn apply_effect(state: &mut Map<String, Value>, effect: &StateEffect, rendered: &str, body: &[u8]) {
+    let key = effect.key.trim();
+    if key.is_empty() {
+        return;
+    }
+    let value = if rendered.trim().is_empty() {
+        None
+    } else {
+        Some(parse_value(rendered))
+    };
+    match effect.action.as_str() {
+        "set" => {
+            let value = value.unwrap_or_else(|| body_value(body));
+            state.insert(key.to_string(), value);
+        }
+        "append" => {
+            let value = value.unwrap_or_else(|| body_value(body));
+            match state.get_mut(key) {
+                Some(Value::Array(items)) => items.push(value),
+                _ => {
+                    state.insert(key.to_string(), Value::Array(vec![value]));
+                }
+            }
+        }
+        "increment" => {
+            let by = value.as_ref().and_then(Value::as_f64).unwrap_or(1.0);
+            let current = state.get(key).and_then(Value::as_f64).unwrap_or(0.0);
+            state.insert(key.to_string(), number_value(current + by));
+        }
+        "remove" => {
+            state.remove(key);
+        }
+        "removeItem" => {
+            let Some(Value::Array(items)) = state.get_mut(key) else {
+                return;
+            };
+            let field = effect.field.as_deref().unwrap_or("").trim();
+            let target = rendered.trim();
+            items.retain(|item| {
+                let compared = if field.is_empty() {
+                    Some(item)
+                } else {
+                    lookup_json_path(item, field)
+                };
+                compared.map(value_to_plain_string).as_deref() != Some(target)
+            });
+        }
+        "clear" => {
+            state.insert(key.to_string(), Value::Array(Vec::new()));
+        }
+        _ => {}
+    }
+}
+
+/// A rendered effect value: JSON when it parses, otherwise the text itself.
+fn parse_value(rendered: &str) -> Value {
+    serde_json::from_str(rendered.trim()).unwrap_or_else(|_| Value::String(rendered.to_string()))
+}
+
+fn body_value(body: &[u8]) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+/// Keeps counters integral while both sides are whole numbers.
+fn number_value(number: f64) -> Value {
+    if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+        Value::from(number as i64)
+    } else {
+        Value::from(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, block, block_match, request};
+    use crate::types::Block;
+    use axum::http::Method;
+    use serde_json::json;
+
+    fn effect(action: &str, key: &str, value: &str) -> StateEffect {
+        StateEffect {
+            action: action.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            field: None,
+        }
+    }
+
+    fn with_effects(effects: Vec<StateEffect>, params: &[(&str, &str)]) -> BlockMatch {
+        let mut found = block_match(Block {
+            effects,
+            ..block("cart", "POST", "/cart")
+        });
+        found.extracted_params = params
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        found
+    }
+
+    fn apply(state: &mut Map<String, Value>, effect: &StateEffect, body: &str) {
+        apply_effect(state, effect, &effect.value, body.as_bytes());
+    }
+
+    #[test]
+    fn set_and_append_parse_json_or_fall_back_to_the_body() {
+        let mut state = Map::new();
+        apply(&mut state, &effect("set", "user", r#"{"id": 1}"#), "");
+        apply(&mut state, &effect("set", "name", "not json"), "");
+        apply(&mut state, &effect("set", "posted", ""), r#"{"a": [1]}"#);
+        apply(&mut state, &effect("append", "items", ""), "plain text");
+        apply(&mut state, &effect("append", "items", "2"), "");
+        apply(&mut state, &effect("append", "name", "\"x\""), "");
+        assert_eq!(
+            Value::Object(state),
+            json!({
+                "user": {"id": 1},
+                "name": ["x"],
+                "posted": {"a": [1]},
+                "items": ["plain text", 2]
+            })
+        );
+    }
+
+    #[test]
+    fn increments_stay_integral_until_a_fraction_is_added() {
+        let mut state = Map::new();
+        apply(&mut state, &effect("increment", "count", ""), "");
+        apply(&mut state, &effect("increment", "count", "4"), "");
+        assert_eq!(state["count"], json!(5));
+        apply(&mut state, &effect("increment", "count", "0.5"), "");
+        assert_eq!(state["count"], json!(5.5));
+        apply(&mut state, &effect("increment", "label", "x"), "");
+        assert_eq!(state["label"], json!(1));
+    }
+
+    #[test]
+    fn remove_item_compares_the_field_as_plain_text() {
+        let mut state = Map::new();
+        state.insert(
+            "cart".to_string(),
+            json!([{"id": 1}, {"id": "2"}, {"id": 1}]),
+        );
+        state.insert("tags".to_string(), json!(["a", "b"]));
+        let mut remove_by_id = effect("removeItem", "cart", "1");
+        remove_by_id.field = Some("id".to_string());
+        apply(&mut state, &remove_by_id, "");
+        apply(&mut state, &effect("removeItem", "tags", "b"), "");
+        apply(&mut state, &effect("removeItem", "missing", "b"), "");
+        assert_eq!(state["cart"], json!([{"id": "2"}]));
+        assert_eq!(state["tags"], json!(["a"]));
+        assert!(!state.contains_key("missing"));
+
+        apply(&mut state, &effect("clear", "cart", ""), "");
+        apply(&mut state, &effect("remove", "tags", ""), "");
+        apply(&mut state, &effect("unknown", "other", "1"), "");
+        apply(&mut state, &effect("set", "  ", "1"), "");
+        assert_eq!(Value::Object(state), json!({"cart": []}));
+    }
+
+    #[tokio::test]
+    async fn effects_render_request_values_and_accumulate_across_hits() {
+        let state = app_state();
+        let effects = vec![
+            effect("append", "cart", "{{request.body}}"),
+            effect("increment", "adds", ""),
+            effect("set", "last", "{{request.body.name}}"),
+        ];
+        for body in [
+            r#"{"id": 1, "name": "pen"}"#,
+            r#"{"id": 2, "name": "ink \"blue\""}"#,
+        ] {
+            let request = request(Method::POST, "/cart").body(body);
+            apply_effects(
+                &state,
+                &mut with_effects(effects.clone(), &[]),
+                &request.incoming(),
+            )
+            .await;
+        }
+
+        let mut remove = effect("removeItem", "cart", "{{id}}");
+        remove.field = Some("id".to_string());
+        let mut removed = with_effects(vec![remove], &[("id", "1")]);
+        let request = request(Method::DELETE, "/cart/1");
+        apply_effects(&state, &mut removed, &request.incoming()).await;
+        assert_eq!(
+            Value::Object(removed.state),
+            json!({
+                "cart": [{"id": 2, "name": "ink \"blue\""}],
+                "adds": 2,
+                "last": "ink \"blue\""
+            })
+        );
+    }
+}
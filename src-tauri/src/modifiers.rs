@@ -0,0 +1,513 @@
+use crate::resources::merge_patch;
+use crate::routing::CompiledModifier;
+use crate::types::{JsonPatchOperation, ResponseModifier};
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use regex::Regex;
+use serde_json::Value;
+use std::io::{Read, Write};
+
+/// Enabled modifiers of a profile scoped to the request, in declaration order.
+pub fn matching_modifiers(
+    modifiers: &[CompiledModifier],
+    method: &Method,
+    host: Option<&str>,
+    path: &str,
+) -> Vec<CompiledModifier> {
+    modifiers
+        .iter()
+        .filter(|compiled| compiled.scope.captures(method, host, path).is_some())
+        .cloned()
+        .collect()
+}
+
+/// Rejects modifiers with regexes, statuses or header names that could never apply.
+pub fn validate_modifiers(modifiers: &[ResponseModifier]) -> Result<(), String> {
+    for modifier in modifiers {
+        let label = if modifier.name.is_empty() {
+            &modifier.id
+        } else {
+            &modifier.name
+        };
+        if let Some(status) = modifier.status {
+            StatusCode::from_u16(status)
+                .map_err(|_| format!("Modifier {}: invalid status {}", label, status))?;
+        }
+        for replacement in &modifier.replacements {
+            Regex::new(&replacement.find)
+                .map_err(|error| format!("Modifier {}: invalid regex: {}", label, error))?;
+        }
+        for name in modifier.set_headers.keys().chain(&modifier.remove_headers) {
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| format!("Modifier {}: invalid header name {}", label, name))?;
+        }
+        validate_json_patch(&modifier.json_patch)
+            .map_err(|error| format!("Modifier {}: {}", label, error))?;
+    }
+    Ok(())
+}
+
+/// Whether any modifier edits the body, which then has to be buffered.
+pub fn rewrites_body(modifiers: &[CompiledModifier]) -> bool {
+    modifiers
+        .iter()
+        .map(|compiled| &compiled.modifier)
+        .any(|modifier| {
+            modifier.merge_patch.is_some()
+                || !modifier.json_patch.is_empty()
+                || !modifier.replacements.is_empty()
+        })
+}
+
+/// Applies the status and header rules; run after `apply_to_body`.
+pub fn apply_to_head(
+    modifiers: &[CompiledModifier],
+    status: &mut StatusCode,
+    headers: &mut HeaderMap,
+) {
+    for CompiledModifier { modifier, .. } in modifiers {
+        if let Some(next) = modifier
+            .status
+            .and_then(|code| StatusCode::from_u16(code).ok())
+        {
+            *status = next;
+        }
+        for name in &modifier.remove_headers {
+            headers.remove(name.trim());
+        }
+        for (name, value) in &modifier.set_headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.trim().as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Applies the body rules, decoding and re-encoding gzip, deflate and br bodies. Bodies that
+/// cannot be decoded, or that no rule changes, are returned as they came.
+pub fn apply_to_body(
+    modifiers: &[CompiledModifier],
+    headers: &mut HeaderMap,
+    body: Bytes,
+) -> Bytes {
+    let encoding = headers
+        .get("content-encoding")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    let Some(decoded) = decode_body(&encoding, &body) else {
+        return body;
+    };
+    let modified = modifiers.iter().fold(decoded.clone(), |current, modifier| {
+        modify_body(modifier, current)
+    });
+    if modified == decoded {
+        return body;
+    }
+    let Some(encoded) = encode_body(&encoding, &modified) else {
+        return body;
+    };
+    // The length changed, and a chunked upstream body is now sent in one piece.
+    headers.remove("transfer-encoding");
+    headers.insert("content-length", HeaderValue::from(encoded.len()));
+    Bytes::from(encoded)
+}
+
+fn modify_body(compiled: &CompiledModifier, body: Vec<u8>) -> Vec<u8> {
+    let modifier = &compiled.modifier;
+    let mut body = body;
+    if modifier.merge_patch.is_some() || !modifier.json_patch.is_empty() {
+        if let Ok(original) = serde_json::from_slice::<Value>(&body) {
+            let mut json = original.clone();
+            if let Some(patch) = &modifier.merge_patch {
+                merge_patch(&mut json, patch.clone());
+            }
+            if let Ok(patched) = apply_json_patch(&json, &modifier.json_patch) {
+                json = patched;
+            }
+            // Patches that change nothing keep the body byte for byte, so it is not re-encoded.
+            if json != original {
+                if let Ok(serialized) = serde_json::to_vec(&json) {
+                    body = serialized;
+                }
+            }
+        }
+    }
+    if !modifier.replacements.is_empty() {
+        if let Ok(text) = String::from_utf8(body.clone()) {
+            let replaced = modifier
+                .replacements
+                .iter()
+                .zip(&compiled.replacements)
+                .fold(text, |text, (replacement, regex)| match regex {
+                    Some(regex) => regex
+                        .replace_all(&text, replacement.replace.as_str())
+                        .into_owned(),
+                    None => text,
+                });
+            body = replaced.into_bytes();
+        }
+    }
+    body
+}
+
+/// Decodes a `gzip`, `deflate` or `br` body; `None` for other encodings or a corrupt body.
+pub fn decode_body(encoding: &str, body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    match encoding {
+        "" | "identity" => return Some(body.to_vec()),
+        "gzip" | "x-gzip" => flate2::read::MultiGzDecoder::new(body)
+            .read_to_end(&mut decoded)
+            .ok()?,
+        "deflate" => flate2::read::ZlibDecoder::new(body)
+            .read_to_end(&mut decoded)
+            .ok()?,
+        "br" => brotli::Decompressor::new(body, 4096)
+            .read_to_end(&mut decoded)
+            .ok()?,
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+fn encode_body(encoding: &str, body: &[u8]) -> Option<Vec<u8>> {
+    match encoding {
+        "" | "identity" => Some(body.to_vec()),
+        "gzip" | "x-gzip" => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body).ok()?;
+            encoder.finish().ok()
+        }
+        "deflate" => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body).ok()?;
+            encoder.finish().ok()
+        }
+        "br" => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            encoder.write_all(body).ok()?;
+            Some(encoder.into_inner())
+        }
+        _ => None,
+    }
+}
+
+/// Applies RFC 6902 operations to a copy of `document`; any failing operation fails the patch.
+pub fn apply_json_patch(
+    document: &Value,
+    operations: &[JsonPatchOperation],
+) -> Result<Value, String> {
+    let mut document = document.clone();
+    for operation in operations {
+        match operation.op.as_str() {
+            "add" => add_value(&mut document, &operation.path, operation.value.clone())?,
+            "remove" => {
+                remove_value(&mut document, &operation.path)?;
+            }
+            "replace" => {
+                let target = document
+                    .pointer_mut(&operation.path)
+                    .ok_or_else(|| format!("{} does not exist", operation.path))?;
+                *target = operation.value.clone();
+            }
+            "move" => {
+                let from = operation.from.as_deref().ok_or("move needs from")?;
+                if operation.path.starts_with(&format!("{}/", from)) {
+                    return Err(format!("Cannot move {} into itself", from));
+                }
+                let value = remove_value(&mut document, from)?;
+                add_value(&mut document, &operation.path, value)?;
+            }
+            "copy" => {
+                let from = operation.from.as_deref().ok_or("copy needs from")?;
+                let value = document
+                    .pointer(from)
+                    .cloned()
+                    .ok_or_else(|| format!("{} does not exist", from))?;
+                add_value(&mut document, &operation.path, value)?;
+            }
+            "test" => {
+                if document.pointer(&operation.path) != Some(&operation.value) {
+                    return Err(format!("Test failed at {}", operation.path));
+                }
+            }
+            op => return Err(format!("Unknown JSON patch op {}", op)),
+        }
+    }
+    Ok(document)
+}
+
+/// Rejects unknown ops and ops missing the `from` pointer they need.
+pub fn validate_json_patch(operations: &[JsonPatchOperation]) -> Result<(), String> {
+    for operation in operations {
+        match operation.op.as_str() {
+            "add" | "remove" | "replace" | "test" => {}
+            "move" | "copy" if operation.from.is_some() => {}
+            "move" | "copy" => return Err(format!("{} needs from", operation.op)),
+            op => return Err(format!("Unknown JSON patch op {}", op)),
+        }
+    }
+    Ok(())
+}
+
+/// Splits a JSON pointer into its parent pointer and unescaped last token.
+fn split_pointer(pointer: &str) -> Result<(&str, String), String> {
+    let (parent, token) = pointer
+        .rsplit_once('/')
+        .ok_or_else(|| format!("Invalid JSON pointer {}", pointer))?;
+    Ok((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+fn add_value(document: &mut Value, pointer: &str, value: Value) -> Result<(), String> {
+    if pointer.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    let (parent, token) = split_pointer(pointer)?;
+    match document.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+        }
+        Some(Value::Array(items)) => {
+            let index = if token == "-" {
+                items.len()
+            } else {
+                token
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|index| *index <= items.len())
+                    .ok_or_else(|| format!("Invalid array index in {}", pointer))?
+            };
+            items.insert(index, value);
+        }
+        _ => return Err(format!("Parent of {} does not exist", pointer)),
+    }
+    Ok(())
+}
+
+fn remove_value(document: &mut Value, pointer: &str) -> Result<Value, String> {
+    let (parent, token) = split_pointer(pointer)?;
+    let removed = match document.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&token),
+        Some(Value::Array(items)) => token
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index < items.len())
+            .map(|index| items.remove(index)),
+        _ => None,
+    };
+    removed.ok_or_else(|| format!("{} does not exist", pointer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::compiled_modifiers;
+    use crate::types::TextReplacement;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn operation(op: &str, path: &str, from: Option<&str>, value: Value) -> JsonPatchOperation {
+        JsonPatchOperation {
+            op: op.to_string(),
+            path: path.to_string(),
+            from: from.map(|from| from.to_string()),
+            value,
+        }
+    }
+
+    fn replacement(find: &str, replace: &str) -> TextReplacement {
+        TextReplacement {
+            find: find.to_string(),
+            replace: replace.to_string(),
+        }
+    }
+
+    #[test]
+    fn json_patch_operations_apply_in_order() {
+        let document = json!({"items": [1, 2], "a/b": {"c": true}, "name": "x"});
+        let patched = apply_json_patch(
+            &document,
+            &[
+                operation("add", "/items/-", None, json!(3)),
+                operation("add", "/items/0", None, json!(0)),
+                operation("remove", "/a~1b/c", None, Value::Null),
+                operation("move", "/renamed", Some("/name"), Value::Null),
+                operation("copy", "/first", Some("/items/0"), Value::Null),
+                operation("replace", "/items/1", None, json!("one")),
+                operation("test", "/renamed", None, json!("x")),
+            ],
+        );
+        assert_eq!(
+            patched,
+            Ok(json!({"items": [0, "one", 2, 3], "a/b": {}, "renamed": "x", "first": 0}))
+        );
+    }
+
+    #[test]
+    fn a_failing_operation_fails_the_whole_patch() {
+        let document = json!({"items": [1], "a": {"b": 1}});
+        let fails = |operations: &[JsonPatchOperation]| apply_json_patch(&document, operations);
+        assert_eq!(
+            fails(&[
+                operation("add", "/added", None, json!(1)),
+                operation("test", "/items/0", None, json!(2)),
+            ]),
+            Err("Test failed at /items/0".to_string())
+        );
+        assert!(fails(&[operation("remove", "/items/1", None, Value::Null)]).is_err());
+        assert!(fails(&[operation("add", "/items/5", None, json!(1))]).is_err());
+        assert!(fails(&[operation("replace", "/missing", None, json!(1))]).is_err());
+        assert!(fails(&[operation("add", "/missing/x", None, json!(1))]).is_err());
+        assert_eq!(
+            fails(&[operation("move", "/a/b/c", Some("/a"), Value::Null)]),
+            Err("Cannot move /a into itself".to_string())
+        );
+        assert_eq!(
+            validate_json_patch(&[operation("copy", "/x", None, Value::Null)]),
+            Err("copy needs from".to_string())
+        );
+    }
+
+    #[test]
+    fn bodies_round_trip_through_every_supported_encoding() {
+        let body = br#"{"message": "hello"}"#;
+        for encoding in ["", "identity", "gzip", "x-gzip", "deflate", "br"] {
+            let encoded = encode_body(encoding, body).unwrap();
+            assert_eq!(decode_body(encoding, &encoded).as_deref(), Some(&body[..]));
+        }
+        assert_eq!(decode_body("zstd", body), None);
+        assert_eq!(decode_body("gzip", body), None);
+    }
+
+    #[test]
+    fn gzip_bodies_are_patched_and_re_encoded() {
+        let modifiers = compiled_modifiers(vec![ResponseModifier {
+            id: "patch".to_string(),
+            merge_patch: Some(json!({"debug": null, "user": {"role": "admin"}})),
+            replacements: vec![replacement(r"(\w+)@example\.com", "$1@test.invalid")],
+            ..Default::default()
+        }]);
+        let original = br#"{"debug": true, "user": {"email": "ada@example.com"}}"#;
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", HeaderValue::from_static("GZIP"));
+        headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        let body = Bytes::from(encode_body("gzip", original).unwrap());
+
+        let modified = apply_to_body(&modifiers, &mut headers, body);
+        let decoded = decode_body("gzip", &modified).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&decoded).unwrap(),
+            json!({"user": {"email": "ada@test.invalid", "role": "admin"}})
+        );
+        assert_eq!(headers["content-length"], modified.len().to_string());
+        assert!(!headers.contains_key("transfer-encoding"));
+    }
+
+    #[test]
+    fn unchanged_or_undecodable_bodies_are_returned_as_they_came() {
+        let modifiers = compiled_modifiers(vec![ResponseModifier {
+            id: "replace".to_string(),
+            replacements: vec![replacement("(", "x"), replacement("missing", "x")],
+            ..Default::default()
+        }]);
+        let mut headers = HeaderMap::new();
+        let body = Bytes::from_static(b"plain text (kept)");
+        assert_eq!(apply_to_body(&modifiers, &mut headers, body.clone()), body);
+        assert!(headers.is_empty());
+
+        headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+        let corrupt = Bytes::from_static(b"not gzip");
+        assert_eq!(
+            apply_to_body(&modifiers, &mut headers, corrupt.clone()),
+            corrupt
+        );
+
+        let no_op = compiled_modifiers(vec![ResponseModifier {
+            id: "patch".to_string(),
+            merge_patch: Some(json!({"ok": true})),
+            ..Default::default()
+        }]);
+        let mut headers = HeaderMap::new();
+        let pretty = Bytes::from_static(b"{\n  \"ok\": true\n}");
+        assert_eq!(apply_to_body(&no_op, &mut headers, pretty.clone()), pretty);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn status_and_header_rules_apply_in_order() {
+        let modifiers = compiled_modifiers(vec![
+            ResponseModifier {
+                id: "first".to_string(),
+                status: Some(503),
+                set_headers: HashMap::from([("x-mocked".to_string(), "1".to_string())]),
+                ..Default::default()
+            },
+            ResponseModifier {
+                id: "second".to_string(),
+                status: Some(1000),
+                remove_headers: vec![" Set-Cookie ".to_string()],
+                ..Default::default()
+            },
+        ]);
+        let mut status = StatusCode::OK;
+        let mut headers = HeaderMap::new();
+        headers.insert("set-cookie", HeaderValue::from_static("a=1"));
+        apply_to_head(&modifiers, &mut status, &mut headers);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["x-mocked"], "1");
+        assert!(!rewrites_body(&modifiers));
+    }
+
+    #[test]
+    fn modifiers_are_scoped_and_validated() {
+        let modifiers = compiled_modifiers(vec![ResponseModifier {
+            id: "users".to_string(),
+            method: "GET".to_string(),
+            path: "/users/{id:int}".to_string(),
+            host: Some("api.test".to_string()),
+            ..Default::default()
+        }]);
+        let scoped = |method: Method, host: Option<&str>, path: &str| {
+            matching_modifiers(&modifiers, &method, host, path).len()
+        };
+        assert_eq!(scoped(Method::GET, Some("api.test:443"), "/users/1"), 1);
+        assert_eq!(scoped(Method::GET, Some("api.test"), "/users/me"), 0);
+        assert_eq!(scoped(Method::POST, Some("api.test"), "/users/1"), 0);
+        assert_eq!(scoped(Method::GET, Some("other.test"), "/users/1"), 0);
+
+        let invalid = |modifier: ResponseModifier| validate_modifiers(&[modifier]).unwrap_err();
+        let named = ResponseModifier {
+            id: "m1".to_string(),
+            name: "Strip".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid(ResponseModifier {
+                status: Some(42),
+                ..named.clone()
+            }),
+            "Modifier Strip: invalid status 42"
+        );
+        assert!(invalid(ResponseModifier {
+            replacements: vec![replacement("[", "")],
+            ..named.clone()
+        })
+        .starts_with("Modifier Strip: invalid regex"));
+        assert_eq!(
+            invalid(ResponseModifier {
+                name: String::new(),
+                remove_headers: vec!["bad header".to_string()],
+                ..named.clone()
+            }),
+            "Modifier m1: invalid header name bad header"
+        );
+        assert!(validate_modifiers(&[named]).is_ok());
+    }
+}
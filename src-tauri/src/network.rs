@@ -0,0 +1,268 @@
+use crate::generators::Generator;
+use crate::matching::host_matches;
+use crate::state::AppState;
+use crate::types::{NetworkConditions, NetworkProfile};
+use axum::{
+    body::{Body, Bytes},
+    response::Response,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Bytes released per step; each step waits for the time they take at the link speed.
+const CHUNK_BYTES: usize = 1024;
+
+/// Link settings after applying a preset and its overrides.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throttle {
+    /// `None` leaves bandwidth unlimited.
+    pub download_kbps: Option<u64>,
+    pub latency_ms: u64,
+    pub loss: f64,
+}
+
+impl Throttle {
+    pub fn resolve(profile: &NetworkProfile) -> Self {
+        let (download_kbps, latency_ms, loss) = match profile.preset.as_str() {
+            "3g" => (Some(1_600), 150, 0.0),
+            "edge" => (Some(240), 840, 0.0),
+            "lossyWifi" => (Some(10_000), 40, 0.05),
+            _ => (None, 0, 0.0),
+        };
+        Throttle {
+            download_kbps: profile
+                .download_kbps
+                .or(download_kbps)
+                .filter(|kbps| *kbps > 0),
+            latency_ms: profile.latency_ms.unwrap_or(latency_ms),
+            loss: profile.loss.unwrap_or(loss).clamp(0.0, 1.0),
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.download_kbps.is_none() && self.latency_ms == 0 && self.loss == 0.0
+    }
+
+    fn transfer_time(&self, bytes: usize) -> Duration {
+        match self.download_kbps {
+            Some(kbps) => Duration::from_secs_f64(bytes as f64 * 8.0 / (kbps as f64 * 1000.0)),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// The throttle for responses to `host`: the first matching host rule, else the global profile.
+pub async fn throttle_for(state: &AppState, host: Option<&str>) -> Option<Throttle> {
+    let conditions = state.network.lock().await;
+    let profile = host
+        .and_then(|host| {
+            conditions
+                .hosts
+                .iter()
+                .find(|rule| !rule.host.trim().is_empty() && host_matches(&rule.host, host))
+        })
+        .map(|rule| &rule.profile)
+        .or(conditions.global.as_ref())?;
+    Some(Throttle::resolve(profile)).filter(|throttle| !throttle.is_unlimited())
+}
+
+/// Whether the conditions can be applied as given.
+pub fn validate_conditions(conditions: &NetworkConditions) -> Result<(), String> {
+    let profiles = conditions
+        .global
+        .iter()
+        .chain(conditions.hosts.iter().map(|rule| &rule.profile));
+    for profile in profiles {
+        if !matches!(
+            profile.preset.as_str(),
+            "" | "3g" | "edge" | "lossyWifi" | "custom"
+        ) {
+            return Err(format!("Unknown network preset \"{}\"", profile.preset));
+        }
+        if profile
+            .loss
+            .is_some_and(|loss| !(0.0..=1.0).contains(&loss))
+        {
+            return Err("Network loss must be between 0 and 1".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Re-times a body stream: waits out the latency, then releases it in small chunks at the link
+/// speed, stalling for a retransmit whenever a chunk is lost.
+pub fn throttle_stream<S, E>(body: S, throttle: Throttle) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>> + 'static,
+{
+    struct Link<S> {
+        body: Pin<Box<S>>,
+        pending: Bytes,
+        started: bool,
+        generator: Generator,
+    }
+    let link = Link {
+        body: Box::pin(body),
+        pending: Bytes::new(),
+        started: false,
+        generator: Generator::new(None),
+    };
+    stream::unfold(link, move |mut link| async move {
+        if !link.started {
+            link.started = true;
+            tokio::time::sleep(Duration::from_millis(throttle.latency_ms)).await;
+        }
+        while link.pending.is_empty() {
+            match link.body.next().await? {
+                Ok(bytes) => link.pending = bytes,
+                Err(error) => return Some((Err(error), link)),
+            }
+        }
+        let chunk = link.pending.split_to(link.pending.len().min(CHUNK_BYTES));
+        let mut wait = throttle.transfer_time(chunk.len());
+        if throttle.loss > 0.0 && link.generator.next_f64() < throttle.loss {
+            wait += Duration::from_millis(throttle.latency_ms.max(200));
+        }
+        tokio::time::sleep(wait).await;
+        Some((Ok(chunk), link))
+    })
+}
+
+/// `throttle_stream` for an axum response.
+pub fn throttle_response(response: Response, throttle: Throttle) -> Response {
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(throttle_stream(body.into_data_stream(), throttle));
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::app_state;
+    use crate::types::HostNetworkProfile;
+    use std::convert::Infallible;
+    use std::time::Instant;
+
+    fn preset(name: &str) -> NetworkProfile {
+        NetworkProfile {
+            preset: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn link(download_kbps: Option<u64>, latency_ms: u64) -> Throttle {
+        Throttle {
+            download_kbps,
+            latency_ms,
+            loss: 0.0,
+        }
+    }
+
+    async fn chunks(body: Vec<Result<Bytes, Infallible>>, throttle: Throttle) -> Vec<usize> {
+        throttle_stream(stream::iter(body), throttle)
+            .map(|chunk| chunk.unwrap().len())
+            .collect()
+            .await
+    }
+
+    #[test]
+    fn presets_resolve_with_their_overrides() {
+        assert_eq!(Throttle::resolve(&preset("3g")), link(Some(1_600), 150));
+        let custom = NetworkProfile {
+            download_kbps: Some(0),
+            latency_ms: Some(20),
+            loss: Some(3.0),
+            ..preset("edge")
+        };
+        assert_eq!(
+            Throttle::resolve(&custom),
+            Throttle {
+                download_kbps: None,
+                latency_ms: 20,
+                loss: 1.0,
+            }
+        );
+        assert!(Throttle::resolve(&preset("custom")).is_unlimited());
+    }
+
+    #[tokio::test]
+    async fn host_rules_take_precedence_over_the_global_profile() {
+        let state = app_state();
+        *state.network.lock().await = NetworkConditions {
+            global: Some(preset("3g")),
+            hosts: vec![
+                HostNetworkProfile {
+                    host: "*.slow.test".to_string(),
+                    profile: preset("edge"),
+                },
+                HostNetworkProfile {
+                    host: "fast.test".to_string(),
+                    profile: preset("custom"),
+                },
+            ],
+        };
+
+        let edge = throttle_for(&state, Some("api.slow.test")).await.unwrap();
+        assert_eq!(edge.latency_ms, 840);
+        let global = throttle_for(&state, Some("other.test")).await.unwrap();
+        assert_eq!(global.latency_ms, 150);
+        assert_eq!(throttle_for(&state, None).await, Some(global));
+        assert_eq!(throttle_for(&state, Some("fast.test")).await, None);
+    }
+
+    #[test]
+    fn unknown_presets_and_out_of_range_loss_are_rejected() {
+        let mut conditions = NetworkConditions {
+            global: Some(preset("lossyWifi")),
+            hosts: Vec::new(),
+        };
+        assert!(validate_conditions(&conditions).is_ok());
+        conditions.hosts.push(HostNetworkProfile {
+            host: "a.test".to_string(),
+            profile: preset("5g"),
+        });
+        assert_eq!(
+            validate_conditions(&conditions).unwrap_err(),
+            "Unknown network preset \"5g\""
+        );
+        conditions.hosts[0].profile = NetworkProfile {
+            loss: Some(-0.1),
+            ..preset("custom")
+        };
+        assert!(validate_conditions(&conditions).is_err());
+    }
+
+    #[tokio::test]
+    async fn bodies_are_released_in_fixed_size_chunks() {
+        let body = vec![
+            Ok(Bytes::from(vec![b'a'; 2_500])),
+            Ok(Bytes::new()),
+            Ok(Bytes::from_static(b"tail")),
+        ];
+        let sizes = chunks(body, link(Some(1_000_000), 0)).await;
+        assert_eq!(sizes, vec![1_024, 1_024, 452, 4]);
+    }
+
+    #[tokio::test]
+    async fn latency_and_bandwidth_delay_the_body() {
+        let started = Instant::now();
+        let sizes = chunks(vec![Ok(Bytes::from(vec![b'a'; 2_048]))], link(None, 30)).await;
+        assert_eq!(sizes, vec![1_024, 1_024]);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        // 2 KiB at 800 kbps takes about 20ms.
+        let started = Instant::now();
+        chunks(vec![Ok(Bytes::from(vec![b'a'; 2_048]))], link(Some(800), 0)).await;
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn body_errors_are_passed_on() {
+        let body = vec![Ok(Bytes::from_static(b"ok")), Err("broken")];
+        let items: Vec<_> = throttle_stream(stream::iter(body), link(None, 0))
+            .collect()
+            .await;
+        assert_eq!(items, vec![Ok(Bytes::from_static(b"ok")), Err("broken")]);
+    }
+}
@@ -1,4 +1,8 @@
+use crate::modifiers;
+use crate::recorder;
 use crate::response::{header_map_to_string_map, json_error_response};
+use crate::rewrites;
+use crate::routing::RoutingTable;
 use crate::state::AppState;
 use crate::types::{Profile, Store};
 use axum::{
@@ -10,13 +14,14 @@ use axum::body::Bytes;
 
 pub async fn proxy_request(
     state: &AppState,
-    store: &Store,
+    table: &RoutingTable,
     active_profile: Option<&str>,
     method: &Method,
     uri: Uri,
     headers: HeaderMap,
     body: Bytes,
 ) -> (Response, crate::state::LoggedResponse) {
+    let store = &table.store;
     let profile = resolve_active_profile(store, active_profile);
     let Some(profile) = profile else {
         let (response, logged_response) = json_error_response(
@@ -37,7 +42,27 @@ pub async fn proxy_request(
     let url = build_proxy_url(&profile.base_url, &uri);
     let upstream_method =
         reqwest::Method::from_bytes(method.as_str().as_bytes()).unwrap_or(reqwest::Method::GET);
-    let proxy_headers = build_proxy_request_headers(&headers);
+    let host = upstream_host(store, active_profile);
+    let record_target = recorder::record_target(state, method, host.as_deref(), uri.path()).await;
+    let mut proxy_headers = build_proxy_request_headers(&headers);
+    if record_target.is_some() {
+        // Ask for an unencoded body so it can become a response template.
+        proxy_headers.remove("accept-encoding");
+    }
+    let compiled = table.profile(&profile.name);
+    let request_rewrites = compiled
+        .map(|compiled| {
+            rewrites::matching_rewrites(&compiled.rewrites, method, host.as_deref(), uri.path())
+        })
+        .unwrap_or_default();
+    let (url, body) = match reqwest::Url::parse(&url) {
+        Ok(mut parsed) if !request_rewrites.is_empty() => {
+            let body =
+                rewrites::apply_rewrites(&request_rewrites, &mut parsed, &mut proxy_headers, body);
+            (parsed.to_string(), body)
+        }
+        _ => (url, body),
+    };
 
     let upstream = state
         .http_client
@@ -57,10 +82,10 @@ pub async fn proxy_request(
         }
     };
 
-    let status =
+    let mut status =
         StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
-    let response_headers = filter_proxy_response_headers(upstream.headers());
-    let body_bytes = match upstream.bytes().await {
+    let mut response_headers = filter_proxy_response_headers(upstream.headers());
+    let mut body_bytes = match upstream.bytes().await {
         Ok(bytes) => bytes,
         Err(error) => {
             return json_error_response(
@@ -70,6 +95,29 @@ pub async fn proxy_request(
         }
     };
 
+    if let Some(target) = &record_target {
+        // A response that cannot be recorded is still served as usual.
+        let _ = recorder::record_response(
+            state,
+            target,
+            status.as_u16(),
+            &response_headers,
+            &body_bytes,
+        )
+        .await;
+    }
+
+    let response_modifiers = compiled
+        .map(|compiled| {
+            modifiers::matching_modifiers(&compiled.modifiers, method, host.as_deref(), uri.path())
+        })
+        .unwrap_or_default();
+    if !response_modifiers.is_empty() {
+        body_bytes =
+            modifiers::apply_to_body(&response_modifiers, &mut response_headers, body_bytes);
+        modifiers::apply_to_head(&response_modifiers, &mut status, &mut response_headers);
+    }
+
     let body_text = String::from_utf8_lossy(&body_bytes).to_string();
     let mut response = Response::new(Body::from(body_bytes));
     *response.status_mut() = status;
@@ -92,6 +140,17 @@ pub fn resolve_active_profile(store: &Store, active_profile: Option<&str>) -> Op
     store.profiles.first().cloned()
 }
 
+/// Host (and explicit port) of the active profile's `baseUrl`, if it has one.
+pub fn upstream_host(store: &Store, active_profile: Option<&str>) -> Option<String> {
+    let profile = resolve_active_profile(store, active_profile)?;
+    let url = reqwest::Url::parse(profile.base_url.trim()).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
 fn build_proxy_url(base_url: &str, uri: &Uri) -> String {
     let base = base_url.trim_end_matches('/');
     let path = uri.path();
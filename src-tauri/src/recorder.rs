@@ -0,0 +1,418 @@
+use crate::blocks;
+use crate::matching::{host_matches, strip_port};
+use crate::state::AppState;
+use crate::store;
+use crate::types::{Block, RecordConfig};
+use axum::http::{HeaderMap, Method};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Responses larger than this are passed through without being recorded.
+pub const MAX_RECORDED_BODY_BYTES: usize = 1024 * 1024;
+
+/// A passthrough request whose upstream response record mode will snapshot.
+#[derive(Debug, Clone)]
+pub struct RecordTarget {
+    pub method: String,
+    pub path: String,
+    pub host: Option<String>,
+}
+
+/// The request as a record target when record mode is on and its host and path are selected.
+pub async fn record_target(
+    state: &AppState,
+    method: &Method,
+    host: Option<&str>,
+    path: &str,
+) -> Option<RecordTarget> {
+    let config = state.record.lock().await;
+    if !config.enabled {
+        return None;
+    }
+    let host_selected = config.hosts.iter().all(|pattern| pattern.trim().is_empty())
+        || host.is_some_and(|host| {
+            config
+                .hosts
+                .iter()
+                .any(|pattern| !pattern.trim().is_empty() && host_matches(pattern, host))
+        });
+    let path_selected = config.paths.iter().all(|prefix| prefix.trim().is_empty())
+        || config
+            .paths
+            .iter()
+            .any(|prefix| !prefix.trim().is_empty() && path.starts_with(prefix.trim()));
+    if !host_selected || !path_selected {
+        return None;
+    }
+    Some(RecordTarget {
+        method: method.as_str().to_string(),
+        path: path.to_string(),
+        host: host.map(|host| host.to_string()),
+    })
+}
+
+/// Snapshots an upstream response into a block of the configured library of the active profile.
+/// A block already recorded for the same method and path is kept unless `overwrite` is set.
+pub async fn record_response(
+    state: &AppState,
+    target: &RecordTarget,
+    status: u16,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), String> {
+    let config = state.record.lock().await.clone();
+    if let Some(encoding) = headers
+        .get("content-encoding")
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.eq_ignore_ascii_case("identity"))
+    {
+        return Err(format!("{} encoded bodies are not recorded", encoding));
+    }
+    if body.len() > MAX_RECORDED_BODY_BYTES {
+        return Err("Response body is too large to record".to_string());
+    }
+    let response_template = std::str::from_utf8(body)
+        .map_err(|_| "Binary response bodies are not recorded".to_string())?
+        .to_string();
+
+    // Held for the whole read-modify-write so recordings and API edits do not drop each other.
+    let _guard = state.store_lock.lock().await;
+    let mut store = store::read_store(state).await;
+    let active_profile = state.active_profile.lock().await.clone();
+    let Some(profile) = store
+        .profiles
+        .iter_mut()
+        .find(|profile| Some(&profile.name) == active_profile.as_ref())
+    else {
+        return Err("No active profile to record into".to_string());
+    };
+    let Some(library) = profile
+        .libraries
+        .iter()
+        .find(|library| library.id == config.library_id)
+        .cloned()
+    else {
+        return Err(format!("Library \"{}\" not found", config.library_id));
+    };
+
+    let path = if config.parameterize {
+        parameterize_path(&target.path)
+    } else {
+        target.path.clone()
+    };
+    let block = recorded_block(&config, target, &path, status, headers, response_template);
+
+    if library.lib_type == "remote" {
+        let folder_path = library
+            .folder_path
+            .as_deref()
+            .ok_or_else(|| format!("Library \"{}\" has no folder", library.name))?;
+        let folder = Path::new(folder_path);
+        let mut library_blocks = blocks::read_blocks_from_path(folder, &library.id).await;
+        if upsert_block(&mut library_blocks, block, config.overwrite) {
+            blocks::write_blocks_to_path(folder, &library_blocks).await?;
+        }
+        return Ok(());
+    }
+    let local_blocks = &mut profile.library_blocks;
+    if upsert_block(local_blocks, block, config.overwrite) {
+        store::write_store(state, &store).await?;
+    }
+    Ok(())
+}
+
+/// Validates a record config against the active profile before it is applied.
+pub async fn validate_config(state: &AppState, config: &RecordConfig) -> Result<(), String> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let store = store::read_store(state).await;
+    let active_profile = state.active_profile.lock().await.clone();
+    let profile = store
+        .profiles
+        .iter()
+        .find(|profile| Some(&profile.name) == active_profile.as_ref())
+        .ok_or_else(|| "No active profile to record into".to_string())?;
+    if !profile
+        .libraries
+        .iter()
+        .any(|library| library.id == config.library_id)
+    {
+        return Err(format!("Library \"{}\" not found", config.library_id));
+    }
+    Ok(())
+}
+
+fn recorded_block(
+    config: &RecordConfig,
+    target: &RecordTarget,
+    path: &str,
+    status: u16,
+    headers: &HeaderMap,
+    response_template: String,
+) -> Block {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_nanos())
+        .unwrap_or_default();
+    let response_headers = headers
+        .iter()
+        .filter(|(name, _)| {
+            !matches!(
+                name.as_str(),
+                "content-length" | "transfer-encoding" | "connection" | "date" | "keep-alive"
+            )
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    Block {
+        id: format!("block-{}", nanos),
+        name: format!("{} {}", target.method, path),
+        method: target.method.clone(),
+        path: path.to_string(),
+        host: target
+            .host
+            .as_deref()
+            .map(|host| strip_port(host).to_string()),
+        description: match target.host.as_deref() {
+            Some(host) => format!("Recorded from {}{}", host, target.path),
+            None => format!("Recorded from {}", target.path),
+        },
+        status: (status != 200).then(|| status.to_string()),
+        response_template,
+        response_headers,
+        source_library_id: Some(config.library_id.clone()),
+        ..Default::default()
+    }
+}
+
+/// Adds `block`, or replaces the block with the same method and path when `overwrite` is set.
+/// Returns whether the list changed.
+fn upsert_block(blocks: &mut Vec<Block>, block: Block, overwrite: bool) -> bool {
+    let existing = blocks.iter().position(|item| {
+        item.method.eq_ignore_ascii_case(&block.method) && item.path == block.path
+    });
+    match existing {
+        Some(_) if !overwrite => false,
+        Some(index) => {
+            let previous = &blocks[index];
+            blocks[index] = Block {
+                id: previous.id.clone(),
+                name: previous.name.clone(),
+                category: previous.category.clone(),
+                ..block
+            };
+            true
+        }
+        None => {
+            blocks.push(block);
+            true
+        }
+    }
+}
+
+/// Replaces numeric and UUID segments with typed params: `/users/42/orders/<uuid>` becomes
+/// `/users/{id:int}/orders/{id2:uuid}`.
+pub fn parameterize_path(path: &str) -> String {
+    let mut count = 0;
+    path.split('/')
+        .map(|segment| {
+            let kind = if !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit()) {
+                "int"
+            } else if is_uuid(segment) {
+                "uuid"
+            } else {
+                return segment.to_string();
+            };
+            count += 1;
+            match count {
+                1 => format!("{{id:{}}}", kind),
+                n => format!("{{id{}:{}}}", n, kind),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_uuid(segment: &str) -> bool {
+    segment.len() == 36
+        && segment.char_indices().all(|(index, ch)| match index {
+            8 | 13 | 18 | 23 => ch == '-',
+            _ => ch.is_ascii_hexdigit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching::PathMatcher;
+    use crate::test_support::{app_state, block, store};
+    use axum::http::HeaderValue;
+    use std::collections::HashMap;
+
+    const UUID: &str = "3F2504E0-4F89-41D3-9A0C-0305E82C3301";
+
+    fn target(method: &str, host: Option<&str>, path: &str) -> RecordTarget {
+        RecordTarget {
+            method: method.to_string(),
+            path: path.to_string(),
+            host: host.map(|host| host.to_string()),
+        }
+    }
+
+    fn recorded(path: &str, template: &str) -> Block {
+        Block {
+            response_template: template.to_string(),
+            ..block(&format!("recorded {}", path), "GET", path)
+        }
+    }
+
+    #[test]
+    fn ids_become_typed_params_that_match_the_recorded_path() {
+        let path = format!("/users/42/orders/{}/items/", UUID);
+        let template = parameterize_path(&path);
+        assert_eq!(template, "/users/{id:int}/orders/{id2:uuid}/items/");
+        assert!(PathMatcher::compile(&template, &HashMap::new())
+            .captures(&path)
+            .is_some());
+        assert_eq!(parameterize_path("/v2/users/me"), "/v2/users/me");
+        assert_eq!(parameterize_path("/a/1e3/-1"), "/a/1e3/-1");
+        assert_eq!(parameterize_path(&UUID[1..]), &UUID[1..]);
+    }
+
+    #[test]
+    fn recorded_blocks_keep_the_host_without_its_port() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.insert("content-length", HeaderValue::from_static("2"));
+        headers.insert("date", HeaderValue::from_static("today"));
+        let config = RecordConfig::default();
+        let ipv6 = target("GET", Some("[::1]:8080"), "/users/1");
+        let block = recorded_block(
+            &config,
+            &ipv6,
+            "/users/{id:int}",
+            404,
+            &headers,
+            "{}".into(),
+        );
+        assert_eq!(block.host.as_deref(), Some("[::1]"));
+        assert_eq!(block.name, "GET /users/{id:int}");
+        assert_eq!(block.description, "Recorded from [::1]:8080/users/1");
+        assert_eq!(block.status.as_deref(), Some("404"));
+        assert_eq!(
+            block.response_headers,
+            HashMap::from([("content-type".to_string(), "application/json".to_string())])
+        );
+
+        let plain = target("GET", Some("api.test:443"), "/");
+        let block = recorded_block(&config, &plain, "/", 200, &HeaderMap::new(), String::new());
+        assert_eq!(block.host.as_deref(), Some("api.test"));
+
+        let hostless = target("GET", None, "/");
+        let block = recorded_block(
+            &config,
+            &hostless,
+            "/",
+            200,
+            &HeaderMap::new(),
+            String::new(),
+        );
+        assert_eq!(block.host, None);
+        assert_eq!(block.description, "Recorded from /");
+        assert_eq!(block.status, None);
+    }
+
+    #[test]
+    fn existing_blocks_are_only_replaced_when_overwriting() {
+        let mut blocks = vec![recorded("/a", "old")];
+        assert!(!upsert_block(&mut blocks, recorded("/a", "new"), false));
+        assert_eq!(blocks[0].response_template, "old");
+        let mut lower = recorded("/a", "new");
+        lower.method = "get".to_string();
+        lower.id = "other".to_string();
+        assert!(upsert_block(&mut blocks, lower, true));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].response_template, "new");
+        assert_eq!(blocks[0].id, "recorded /a");
+        assert!(upsert_block(&mut blocks, recorded("/b", "b"), false));
+        assert_eq!(blocks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn targets_follow_the_host_and_path_selection() {
+        let state = app_state();
+        let selected = |host: Option<&'static str>, path: &'static str| {
+            let state = state.clone();
+            async move {
+                record_target(&state, &Method::GET, host, path)
+                    .await
+                    .is_some()
+            }
+        };
+        assert!(!selected(Some("api.test"), "/").await);
+
+        *state.record.lock().await = RecordConfig {
+            enabled: true,
+            hosts: vec!["*.test".to_string(), " ".to_string()],
+            paths: vec!["/api/".to_string()],
+            ..Default::default()
+        };
+        assert!(selected(Some("api.test:8443"), "/api/users").await);
+        assert!(!selected(Some("api.test"), "/other").await);
+        assert!(!selected(Some("example.com"), "/api/users").await);
+        assert!(!selected(None, "/api/users").await);
+
+        state.record.lock().await.hosts.clear();
+        assert!(selected(None, "/api/users").await);
+    }
+
+    #[tokio::test]
+    async fn concurrent_recordings_all_reach_the_store() {
+        let state = app_state();
+        store::write_store(&state, &store(Vec::new()))
+            .await
+            .unwrap();
+        *state.active_profile.lock().await = Some("default".to_string());
+        *state.record.lock().await = RecordConfig {
+            enabled: true,
+            parameterize: true,
+            ..Default::default()
+        };
+
+        let headers = HeaderMap::new();
+        let paths = ["/users/1", "/orders/2", "/items/3", "/users/4"];
+        let targets: Vec<RecordTarget> = paths
+            .iter()
+            .map(|path| target("GET", Some("api.test"), path))
+            .collect();
+        let results = futures_util::future::join_all(
+            targets
+                .iter()
+                .map(|target| record_response(&state, target, 200, &headers, b"{}")),
+        )
+        .await;
+        assert!(results.iter().all(Result::is_ok));
+
+        let mut gzip = HeaderMap::new();
+        gzip.insert("content-encoding", HeaderValue::from_static("gzip"));
+        let rejected = record_response(&state, &targets[0], 200, &gzip, b"\x1f\x8b").await;
+        assert_eq!(
+            rejected,
+            Err("gzip encoded bodies are not recorded".to_string())
+        );
+
+        let store = store::read_store(&state).await;
+        let mut recorded: Vec<&str> = store.profiles[0]
+            .library_blocks
+            .iter()
+            .map(|block| block.path.as_str())
+            .collect();
+        recorded.sort_unstable();
+        assert_eq!(
+            recorded,
+            ["/items/{id:int}", "/orders/{id:int}", "/users/{id:int}"]
+        );
+        let _ = tokio::fs::remove_dir_all(&*state.data_dir).await;
+    }
+}
@@ -0,0 +1,379 @@
+use crate::generators::Generator;
+use crate::json_path::value_to_plain_string;
+use crate::matching::IncomingRequest;
+use crate::response::{header_map_to_string_map, json_error_response};
+use crate::routing::RoutingTable;
+use crate::state::{AppState, LoggedResponse, ProfileKey};
+use crate::types::{Profile, Resource};
+use axum::{
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+
+/// A request addressed to a resource collection or to one of its items.
+#[derive(Debug)]
+pub struct ResourceMatch<'a> {
+    pub profile: &'a Profile,
+    pub resource: &'a Resource,
+    /// Last path segment when the request targets a single item.
+    pub item_id: Option<String>,
+}
+
+/// Key for a resource's items in `AppState::resources`.
+pub fn data_key(profile: &str, resource_id: &str) -> ProfileKey {
+    (profile.to_string(), resource_id.to_string())
+}
+
+/// The active profile's resource whose path is the request path or its parent.
+pub fn find_resource<'a>(
+    table: &'a RoutingTable,
+    active_profile: Option<&str>,
+    incoming: &IncomingRequest,
+) -> Option<ResourceMatch<'a>> {
+    let profile = &table.profile(active_profile?)?.profile;
+    let path = incoming.path.trim_end_matches('/');
+    profile.resources.iter().find_map(|resource| {
+        let base = base_path(resource);
+        if base.is_empty() {
+            return None;
+        }
+        if path == base {
+            return Some(ResourceMatch {
+                profile,
+                resource,
+                item_id: None,
+            });
+        }
+        let item_id = path.strip_prefix(base)?.strip_prefix('/')?;
+        if item_id.is_empty() || item_id.contains('/') {
+            return None;
+        }
+        Some(ResourceMatch {
+            profile,
+            resource,
+            item_id: Some(item_id.to_string()),
+        })
+    })
+}
+
+/// Answers the request from the resource's collection, seeding it on first use.
+pub async fn handle_resource(
+    state: &AppState,
+    resource_match: &ResourceMatch<'_>,
+    incoming: &IncomingRequest<'_>,
+) -> (Response, LoggedResponse) {
+    let resource = resource_match.resource;
+    let mut collections = state.resources.lock().await;
+    let items = collections
+        .entry(data_key(&resource_match.profile.name, &resource.id))
+        .or_insert_with(|| resource.seed.clone());
+    let id_field = resource.id_field.as_str();
+    let position = resource_match.item_id.as_deref().and_then(|item_id| {
+        items
+            .iter()
+            .position(|item| item_id_of(item, id_field).as_deref() == Some(item_id))
+    });
+
+    match (incoming.method, resource_match.item_id.as_deref()) {
+        (&Method::GET, None) => json_response(StatusCode::OK, Some(Value::Array(items.clone()))),
+        (&Method::POST, None) => {
+            let Some(mut item) = body_object(incoming.body) else {
+                return json_error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Request body must be a JSON object".to_string(),
+                );
+            };
+            match item_id_of(&item, id_field) {
+                Some(item_id) => {
+                    if items
+                        .iter()
+                        .any(|existing| item_id_of(existing, id_field).as_deref() == Some(&item_id))
+                    {
+                        return json_error_response(
+                            StatusCode::CONFLICT,
+                            format!("{}/{} already exists", base_path(resource), item_id),
+                        );
+                    }
+                }
+                None => {
+                    item[id_field] = next_id(items, id_field);
+                }
+            }
+            items.push(item.clone());
+            json_response(StatusCode::CREATED, Some(item))
+        }
+        (_, None) => method_not_allowed(resource, "GET, POST"),
+        (method, Some(item_id)) => {
+            let allowed = [Method::GET, Method::PUT, Method::PATCH, Method::DELETE];
+            if !allowed.contains(method) {
+                return method_not_allowed(resource, "GET, PUT, PATCH, DELETE");
+            }
+            let Some(position) = position else {
+                return json_error_response(
+                    StatusCode::NOT_FOUND,
+                    format!("{}/{} not found", base_path(resource), item_id),
+                );
+            };
+            match *method {
+                Method::GET => json_response(StatusCode::OK, Some(items[position].clone())),
+                Method::DELETE => {
+                    items.remove(position);
+                    json_response(StatusCode::NO_CONTENT, None)
+                }
+                _ => {
+                    let Some(body) = body_object(incoming.body) else {
+                        return json_error_response(
+                            StatusCode::BAD_REQUEST,
+                            "Request body must be a JSON object".to_string(),
+                        );
+                    };
+                    let existing_id = items[position][id_field].clone();
+                    let mut item = if *method == Method::PATCH {
+                        let mut merged = items[position].clone();
+                        merge_patch(&mut merged, body);
+                        merged
+                    } else {
+                        body
+                    };
+                    // The path decides which item this is, whatever the body says.
+                    item[id_field] = existing_id;
+                    items[position] = item.clone();
+                    json_response(StatusCode::OK, Some(item))
+                }
+            }
+        }
+    }
+}
+
+fn base_path(resource: &Resource) -> &str {
+    resource.path.trim().trim_end_matches('/')
+}
+
+fn item_id_of(item: &Value, id_field: &str) -> Option<String> {
+    match item.get(id_field)? {
+        Value::Null => None,
+        value => Some(value_to_plain_string(value)),
+    }
+}
+
+fn body_object(body: &[u8]) -> Option<Value> {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .filter(Value::is_object)
+}
+
+/// One past the largest numeric id, or a UUID when the collection uses non-numeric ids.
+fn next_id(items: &[Value], id_field: &str) -> Value {
+    let ids: Vec<&Value> = items.iter().filter_map(|item| item.get(id_field)).collect();
+    if ids.iter().all(|id| id.is_u64()) {
+        let max = ids.iter().filter_map(|id| id.as_u64()).max().unwrap_or(0);
+        Value::from(max + 1)
+    } else {
+        Value::String(Generator::new(None).uuid())
+    }
+}
+
+/// RFC 7396 merge: objects merge key by key, `null` removes a key, anything else replaces.
+pub fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: Option<Value>) -> (Response, LoggedResponse) {
+    let mut response = match &body {
+        Some(body) => Json(body.clone()).into_response(),
+        None => Response::default(),
+    };
+    *response.status_mut() = status;
+    let logged_response = LoggedResponse {
+        status: Some(status.as_u16()),
+        headers: header_map_to_string_map(response.headers()),
+        body: body.and_then(|body| serde_json::to_string_pretty(&body).ok()),
+    };
+    (response, logged_response)
+}
+
+fn method_not_allowed(resource: &Resource, allowed: &str) -> (Response, LoggedResponse) {
+    let (mut response, logged_response) = json_error_response(
+        StatusCode::METHOD_NOT_ALLOWED,
+        format!("{} supports {}", base_path(resource), allowed),
+    );
+    if let Ok(value) = allowed.parse() {
+        response.headers_mut().insert("allow", value);
+    }
+    (response, logged_response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, request, store};
+    use serde_json::json;
+
+    fn table(seed: Vec<Value>) -> RoutingTable {
+        let mut store = store(Vec::new());
+        store.profiles[0].resources = vec![Resource {
+            id: "todos".to_string(),
+            path: "/api/todos/".to_string(),
+            id_field: "id".to_string(),
+            seed,
+        }];
+        RoutingTable::build(store)
+    }
+
+    fn item_id(table: &RoutingTable, path: &str) -> Option<Option<String>> {
+        let request = request(Method::GET, path);
+        find_resource(table, Some("default"), &request.incoming()).map(|found| found.item_id)
+    }
+
+    /// Status and JSON body of one request against the `todos` resource.
+    async fn call(
+        state: &AppState,
+        table: &RoutingTable,
+        method: Method,
+        path: &str,
+        body: &str,
+    ) -> (u16, Value) {
+        let request = request(method, path).body(body);
+        let incoming = request.incoming();
+        let resource_match = find_resource(table, Some("default"), &incoming).unwrap();
+        let (response, logged) = handle_resource(state, &resource_match, &incoming).await;
+        let body = logged
+            .body
+            .map(|body| serde_json::from_str(&body).unwrap())
+            .unwrap_or(Value::Null);
+        (response.status().as_u16(), body)
+    }
+
+    #[test]
+    fn requests_address_the_collection_or_one_item() {
+        let table = table(Vec::new());
+        assert_eq!(item_id(&table, "/api/todos"), Some(None));
+        assert_eq!(item_id(&table, "/api/todos/"), Some(None));
+        assert_eq!(item_id(&table, "/api/todos/7"), Some(Some("7".to_string())));
+        assert_eq!(item_id(&table, "/api/todos/7/tags"), None);
+        assert_eq!(item_id(&table, "/api/todosx"), None);
+        assert_eq!(item_id(&table, "/api"), None);
+    }
+
+    #[tokio::test]
+    async fn items_are_created_read_updated_and_deleted() {
+        let state = app_state();
+        let table = table(vec![json!({"id": 1, "title": "seeded"})]);
+        let todos = "/api/todos";
+
+        let created = call(&state, &table, Method::POST, todos, r#"{"title": "new"}"#).await;
+        assert_eq!(created, (201, json!({"title": "new", "id": 2})));
+        let duplicate = call(&state, &table, Method::POST, todos, r#"{"id": 1}"#).await;
+        assert_eq!(duplicate.0, 409);
+        assert_eq!(duplicate.1["error"], json!("/api/todos/1 already exists"));
+
+        let patched = call(
+            &state,
+            &table,
+            Method::PATCH,
+            "/api/todos/2",
+            r#"{"id": 9, "done": true, "title": null}"#,
+        )
+        .await;
+        assert_eq!(patched, (200, json!({"id": 2, "done": true})));
+        let replaced = call(&state, &table, Method::PUT, "/api/todos/1", r#"{"x": 1}"#).await;
+        assert_eq!(replaced, (200, json!({"x": 1, "id": 1})));
+
+        assert_eq!(
+            call(&state, &table, Method::DELETE, "/api/todos/1", "")
+                .await
+                .0,
+            204
+        );
+        assert_eq!(
+            call(&state, &table, Method::GET, "/api/todos/1", "")
+                .await
+                .0,
+            404
+        );
+        assert_eq!(
+            call(&state, &table, Method::GET, todos, "").await,
+            (200, json!([{"id": 2, "done": true}]))
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_bodies_and_methods_are_rejected() {
+        let state = app_state();
+        let table = table(vec![json!({"id": "a"})]);
+        assert_eq!(
+            call(&state, &table, Method::POST, "/api/todos", "[1]")
+                .await
+                .0,
+            400
+        );
+        assert_eq!(
+            call(&state, &table, Method::PUT, "/api/todos/a", "oops")
+                .await
+                .0,
+            400
+        );
+        assert_eq!(
+            call(&state, &table, Method::DELETE, "/api/todos", "")
+                .await
+                .0,
+            405
+        );
+        assert_eq!(
+            call(&state, &table, Method::POST, "/api/todos/a", "{}")
+                .await
+                .0,
+            405
+        );
+
+        let (status, created) = call(&state, &table, Method::POST, "/api/todos", "{}").await;
+        assert_eq!(status, 201);
+        assert_eq!(created["id"].as_str().map(str::len), Some(36));
+    }
+
+    #[tokio::test]
+    async fn collections_are_seeded_only_on_first_use() {
+        let state = app_state();
+        let table = table(vec![json!({"id": 1})]);
+        assert_eq!(
+            call(&state, &table, Method::DELETE, "/api/todos/1", "")
+                .await
+                .0,
+            204
+        );
+        assert_eq!(
+            call(&state, &table, Method::GET, "/api/todos", "").await,
+            (200, json!([]))
+        );
+        assert!(state.resources.lock().await[&data_key("default", "todos")].is_empty());
+    }
+
+    #[test]
+    fn merge_patch_follows_rfc_7396() {
+        let mut target = json!({"a": {"b": 1, "c": 2}, "d": [1], "e": 1});
+        merge_patch(
+            &mut target,
+            json!({"a": {"b": null, "x": 3}, "d": {"y": 1}, "e": null}),
+        );
+        assert_eq!(target, json!({"a": {"c": 2, "x": 3}, "d": {"y": 1}}));
+        merge_patch(&mut target, json!([1]));
+        assert_eq!(target, json!([1]));
+    }
+}
@@ -1,5 +1,10 @@
+use crate::generators::Generator;
+use crate::matching::IncomingRequest;
 use crate::state::LoggedResponse;
-use crate::template::{merged_template_values, normalize_json_quotes, render_template};
+use crate::template::{
+    active_status_template, looks_like_json, merged_template_values, normalize_json_quotes,
+    render_body_template, render_template,
+};
 use crate::types::{BlockMatch, MatchResult};
 use axum::{
     body::Body,
@@ -56,17 +61,78 @@ pub fn build_response(
     (response, logged_response)
 }
 
-pub fn build_block_response(block_match: &BlockMatch) -> (Response, LoggedResponse) {
-    let template_values = merged_template_values(block_match);
-    let rendered = render_template(&block_match.block.response_template, &template_values);
-    let normalized = normalize_json_quotes(&rendered);
+/// A block's response after template rendering, before it becomes an HTTP response.
+#[derive(Debug, Clone)]
+pub struct RenderedBlock {
+    pub body: String,
+    /// Rendered status template, if the block or its active variant has one.
+    pub status: Option<String>,
+    /// Non-empty header names with their rendered values; not yet checked for validity.
+    pub headers: Vec<(String, String)>,
+}
+
+pub fn render_block(block_match: &BlockMatch, request: &IncomingRequest) -> RenderedBlock {
+    let template_values = merged_template_values(block_match, request);
+    let mut generator = Generator::new(block_match.block.seed.or(block_match.profile.seed));
+    let body_template = &block_match.block.response_template;
+    let unrendered = generator.clone();
+    let mut body = render_body_template(body_template, &template_values, &mut generator);
+    // Smart quotes that keep a JSON body from parsing are straightened in the template, so typed
+    // values are never rewritten, and the body rendered again from the same generator state.
+    if looks_like_json(body_template)
+        && body_template.contains(['\u{201C}', '\u{201D}'])
+        && serde_json::from_str::<Value>(&body).is_err()
+    {
+        let mut retry = unrendered;
+        let straightened = render_body_template(
+            &normalize_json_quotes(body_template),
+            &template_values,
+            &mut retry,
+        );
+        if serde_json::from_str::<Value>(&straightened).is_ok() {
+            body = straightened;
+            generator = retry;
+        }
+    }
+    let status = active_status_template(&block_match.block)
+        .map(|value| render_template(value, &template_values, &mut generator));
+    let headers = block_match
+        .block
+        .response_headers
+        .iter()
+        .filter(|(key, _)| !key.trim().is_empty())
+        .map(|(key, value)| {
+            (
+                key.trim().to_string(),
+                render_template(value, &template_values, &mut generator),
+            )
+        })
+        .collect();
+    RenderedBlock {
+        body,
+        status,
+        headers,
+    }
+}
+
+pub fn build_block_response(
+    block_match: &BlockMatch,
+    request: &IncomingRequest,
+) -> (Response, LoggedResponse) {
+    let RenderedBlock {
+        body: rendered,
+        status,
+        headers,
+    } = render_block(block_match, request);
+    let status = status
+        .and_then(|value| value.trim().parse::<u16>().ok())
+        .and_then(|value| StatusCode::from_u16(value).ok())
+        .unwrap_or(StatusCode::OK);
 
     let parsed_json = if rendered.trim().is_empty() {
         None
-    } else if let Ok(value) = serde_json::from_str::<Value>(&rendered) {
-        Some(value)
     } else {
-        serde_json::from_str::<Value>(&normalized).ok()
+        serde_json::from_str::<Value>(&rendered).ok()
     };
 
     let mut response = if rendered.trim().is_empty() {
@@ -74,7 +140,7 @@ pub fn build_block_response(block_match: &BlockMatch) -> (Response, LoggedRespon
     } else if let Some(value) = parsed_json.clone() {
         Json(value).into_response()
     } else {
-        let mut response = Response::new(Body::from(normalized.clone()));
+        let mut response = Response::new(Body::from(rendered.clone()));
         if let Ok(header_value) = HeaderValue::from_str("text/plain; charset=utf-8") {
             response
                 .headers_mut()
@@ -82,18 +148,14 @@ pub fn build_block_response(block_match: &BlockMatch) -> (Response, LoggedRespon
         }
         response
     };
+    *response.status_mut() = status;
 
     let mut rendered_headers = HashMap::new();
-    for (key, value) in block_match.block.response_headers.iter() {
-        let trimmed = key.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-        let rendered_value = render_template(value, &template_values);
-        if let Ok(header_name) = HeaderName::from_bytes(trimmed.as_bytes()) {
+    for (key, rendered_value) in headers {
+        if let Ok(header_name) = HeaderName::from_bytes(key.as_bytes()) {
             if let Ok(header_value) = HeaderValue::from_str(&rendered_value) {
                 response.headers_mut().insert(header_name, header_value);
-                rendered_headers.insert(trimmed.to_string(), rendered_value);
+                rendered_headers.insert(key, rendered_value);
             }
         }
     }
@@ -103,11 +165,11 @@ pub fn build_block_response(block_match: &BlockMatch) -> (Response, LoggedRespon
     } else if let Some(value) = parsed_json {
         serde_json::to_string_pretty(&value).ok()
     } else {
-        Some(normalized)
+        Some(rendered)
     };
 
     let logged_response = LoggedResponse {
-        status: Some(StatusCode::OK.as_u16()),
+        status: Some(status.as_u16()),
         headers: rendered_headers,
         body,
     };
@@ -115,11 +177,30 @@ pub fn build_block_response(block_match: &BlockMatch) -> (Response, LoggedRespon
     (response, logged_response)
 }
 
+/// Repeated headers such as `set-cookie` are joined with `, ` rather than dropped.
 pub fn header_map_to_string_map(headers: &HeaderMap) -> HashMap<String, String> {
-    let mut next = HashMap::new();
+    let mut next: HashMap<String, String> = HashMap::new();
     for (name, value) in headers.iter() {
         if let Ok(value) = value.to_str() {
-            next.insert(name.to_string(), value.to_string());
+            next.entry(name.to_string())
+                .and_modify(|joined| {
+                    joined.push_str(", ");
+                    joined.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    next
+}
+
+pub fn string_map_to_header_map(headers: &HashMap<String, String>) -> HeaderMap {
+    let mut next = HeaderMap::new();
+    for (key, value) in headers.iter() {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.trim().as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            next.append(name, value);
         }
     }
     next
@@ -136,3 +217,138 @@ pub fn json_error_response(status: StatusCode, message: String) -> (Response, Lo
     };
     (response, logged_response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block, block_match, request};
+    use crate::types::{Block, TemplateValue, TemplateVariant};
+    use axum::http::Method;
+
+    fn status_of(block: Block) -> u16 {
+        let (response, logged) = build_block_response(
+            &block_match(block),
+            &request(Method::GET, "/users").incoming(),
+        );
+        assert_eq!(logged.status, Some(response.status().as_u16()));
+        response.status().as_u16()
+    }
+
+    fn with_status(status: &str) -> Block {
+        Block {
+            status: Some(status.to_string()),
+            ..block("b", "GET", "/users")
+        }
+    }
+
+    fn variant(id: &str, status: Option<&str>) -> TemplateVariant {
+        TemplateVariant {
+            id: id.to_string(),
+            name: id.to_string(),
+            status: status.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn blocks_answer_200_unless_they_set_a_status() {
+        assert_eq!(status_of(block("b", "GET", "/users")), 200);
+        assert_eq!(status_of(with_status("404")), 404);
+        assert_eq!(status_of(with_status(" 503 ")), 503);
+    }
+
+    #[test]
+    fn templated_statuses_are_rendered_with_the_block_values() {
+        let block = Block {
+            template_values: vec![TemplateValue {
+                id: "1".to_string(),
+                key: "code".to_string(),
+                value: "409".to_string(),
+                value_type: "number".to_string(),
+            }],
+            ..with_status("{{code}}")
+        };
+
+        assert_eq!(status_of(block), 409);
+    }
+
+    #[test]
+    fn unusable_statuses_fall_back_to_200() {
+        assert_eq!(status_of(with_status("")), 200);
+        assert_eq!(status_of(with_status("teapot")), 200);
+        assert_eq!(status_of(with_status("{{missing}}")), 200);
+        assert_eq!(status_of(with_status("42")), 200);
+    }
+
+    #[test]
+    fn the_active_variant_status_overrides_the_block() {
+        let block = Block {
+            template_variants: vec![variant("ok", None), variant("down", Some("503"))],
+            active_variant_id: Some("down".to_string()),
+            ..with_status("201")
+        };
+        assert_eq!(status_of(block.clone()), 503);
+
+        let block = Block {
+            active_variant_id: Some("ok".to_string()),
+            ..block
+        };
+        assert_eq!(status_of(block), 201);
+    }
+
+    #[test]
+    fn statuses_deserialize_from_numbers_or_strings() {
+        let parse = |status: Value| {
+            serde_json::from_value::<Block>(json!({
+                "id": "b",
+                "name": "b",
+                "method": "GET",
+                "description": "",
+                "responseTemplate": "",
+                "status": status,
+            }))
+            .unwrap()
+            .status
+        };
+
+        assert_eq!(parse(json!(401)).as_deref(), Some("401"));
+        assert_eq!(parse(json!("{{status}}")).as_deref(), Some("{{status}}"));
+        assert_eq!(parse(Value::Null), None);
+    }
+
+    #[test]
+    fn smart_quotes_are_straightened_only_when_the_json_would_not_parse() {
+        let body_of = |template: &str, value: &str| {
+            let block = Block {
+                response_template: template.to_string(),
+                template_values: vec![TemplateValue {
+                    id: "quote".to_string(),
+                    key: "quote".to_string(),
+                    value: value.to_string(),
+                    value_type: "string".to_string(),
+                }],
+                ..block("b", "GET", "/quotes")
+            };
+            render_block(
+                &block_match(block),
+                &request(Method::GET, "/quotes").incoming(),
+            )
+            .body
+        };
+        assert_eq!(
+            body_of(
+                "{\u{201C}quote\u{201D}: \"{{quote}}\"}",
+                "\u{201C}hi\u{201D}"
+            ),
+            "{\"quote\": \"\u{201C}hi\u{201D}\"}"
+        );
+        assert_eq!(
+            body_of("{\"text\": \"\u{201C}{{quote}}\u{201D}\"}", "hi"),
+            "{\"text\": \"\u{201C}hi\u{201D}\"}"
+        );
+        assert_eq!(
+            body_of("Say \u{201C}{{quote}}\u{201D}", "hi"),
+            "Say \u{201C}hi\u{201D}"
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::matching::strip_port;
+use crate::modifiers::{apply_json_patch, validate_json_patch};
+use crate::resources::merge_patch;
+use crate::routing::CompiledRewrite;
+use crate::types::{RedirectTarget, RequestRewrite};
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A request rewrite in scope, with the params its path template captured.
+#[derive(Debug)]
+pub struct MatchedRewrite<'a> {
+    pub rule: &'a RequestRewrite,
+    pub params: HashMap<String, String>,
+}
+
+/// Enabled rewrites of a profile scoped to the request, in declaration order.
+pub fn matching_rewrites<'a>(
+    rewrites: &'a [CompiledRewrite],
+    method: &Method,
+    host: Option<&str>,
+    path: &str,
+) -> Vec<MatchedRewrite<'a>> {
+    rewrites
+        .iter()
+        .filter_map(|compiled| {
+            let params = compiled.scope.captures(method, host, path)?;
+            Some(MatchedRewrite {
+                rule: &compiled.rule,
+                params,
+            })
+        })
+        .collect()
+}
+
+/// Rejects rewrites with header names, patches or redirects that could never apply.
+pub fn validate_rewrites(rewrites: &[RequestRewrite]) -> Result<(), String> {
+    for rule in rewrites {
+        let label = if rule.name.is_empty() {
+            &rule.id
+        } else {
+            &rule.name
+        };
+        for name in rule.set_headers.keys().chain(&rule.remove_headers) {
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| format!("Rewrite {}: invalid header name {}", label, name))?;
+        }
+        validate_json_patch(&rule.json_patch)
+            .map_err(|error| format!("Rewrite {}: {}", label, error))?;
+        let Some(redirect) = &rule.redirect else {
+            continue;
+        };
+        if let Some(scheme) = non_blank(&redirect.scheme) {
+            if !matches!(scheme, "http" | "https") {
+                return Err(format!(
+                    "Rewrite {}: redirect scheme must be http or https",
+                    label
+                ));
+            }
+        }
+        if let Some(host) = non_blank(&redirect.host) {
+            Url::parse(&format!("http://{}/", host))
+                .map_err(|_| format!("Rewrite {}: invalid redirect host {}", label, host))?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies the rewrites to an outgoing request and returns its body. A `host` header is kept in
+/// step with a redirected URL, and `content-length` with a patched body.
+pub fn apply_rewrites(
+    rewrites: &[MatchedRewrite],
+    url: &mut Url,
+    headers: &mut HeaderMap,
+    body: Bytes,
+) -> Bytes {
+    let original_authority = authority(url);
+    let mut next_body = body.clone();
+    for MatchedRewrite { rule, params } in rewrites {
+        if !rule.set_query.is_empty() || !rule.remove_query.is_empty() {
+            rewrite_query(rule, url);
+        }
+        for name in &rule.remove_headers {
+            headers.remove(name.trim());
+        }
+        for (name, value) in &rule.set_headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.trim().as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+        next_body = patch_body(rule, next_body);
+        if let Some(redirect) = &rule.redirect {
+            redirect_url(redirect, params, url);
+        }
+    }
+
+    let next_authority = authority(url);
+    if next_authority != original_authority && headers.contains_key("host") {
+        if let Ok(value) = HeaderValue::from_str(&next_authority) {
+            headers.insert("host", value);
+        }
+    }
+    if next_body != body {
+        headers.remove("transfer-encoding");
+        if headers.contains_key("content-length") {
+            headers.insert("content-length", HeaderValue::from(next_body.len()));
+        }
+    }
+    next_body
+}
+
+fn rewrite_query(rule: &RequestRewrite, url: &mut Url) {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .into_owned()
+        .filter(|(name, _)| !rule.remove_query.contains(name) && !rule.set_query.contains_key(name))
+        .collect();
+    let mut added: Vec<(&String, &String)> = rule.set_query.iter().collect();
+    added.sort();
+    if pairs.is_empty() && added.is_empty() {
+        url.set_query(None);
+        return;
+    }
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .extend_pairs(added);
+}
+
+fn patch_body(rule: &RequestRewrite, body: Bytes) -> Bytes {
+    if rule.merge_patch.is_none() && rule.json_patch.is_empty() {
+        return body;
+    }
+    let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+    if let Some(patch) = &rule.merge_patch {
+        merge_patch(&mut json, patch.clone());
+    }
+    if let Ok(patched) = apply_json_patch(&json, &rule.json_patch) {
+        json = patched;
+    }
+    serde_json::to_vec(&json).map(Bytes::from).unwrap_or(body)
+}
+
+fn redirect_url(redirect: &RedirectTarget, params: &HashMap<String, String>, url: &mut Url) {
+    if let Some(scheme) = non_blank(&redirect.scheme) {
+        let _ = url.set_scheme(scheme);
+    }
+    if let Some(host) = non_blank(&redirect.host) {
+        let name = strip_port(host);
+        let port = host[name.len()..]
+            .strip_prefix(':')
+            .and_then(|port| port.parse::<u16>().ok());
+        if url.set_host(Some(name)).is_ok() {
+            let _ = url.set_port(port);
+        }
+    }
+    if let Some(port) = redirect.port {
+        let _ = url.set_port(Some(port));
+    }
+    if let Some(path) = non_blank(&redirect.path) {
+        let path = params.iter().fold(path.to_string(), |path, (name, value)| {
+            path.replace(&format!("{{{}}}", name), value)
+        });
+        url.set_path(&path);
+    }
+}
+
+/// Host with its explicit port, as sent in a `host` header.
+fn authority(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+fn non_blank(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::RoutingTable;
+    use crate::test_support::store;
+    use serde_json::json;
+
+    fn routing_table(rewrites: Vec<RequestRewrite>) -> RoutingTable {
+        let mut store = store(Vec::new());
+        store.profiles[0].rewrites = rewrites;
+        RoutingTable::build(store)
+    }
+
+    /// URL, headers and body after the in-scope rewrites ran on a request.
+    fn rewrite(
+        table: &RoutingTable,
+        url: &str,
+        headers: &[(&'static str, &'static str)],
+        body: &str,
+    ) -> (String, HeaderMap, String) {
+        let mut url = Url::parse(url).unwrap();
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(*name, HeaderValue::from_static(value));
+        }
+        let host = authority(&url);
+        let rewrites = &table.profile("default").unwrap().rewrites;
+        let matched = matching_rewrites(rewrites, &Method::POST, Some(&host), url.path());
+        let body = apply_rewrites(
+            &matched,
+            &mut url,
+            &mut header_map,
+            Bytes::from(body.to_string()),
+        );
+        (
+            url.to_string(),
+            header_map,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn query_params_are_set_and_removed() {
+        let table = routing_table(vec![RequestRewrite {
+            id: "query".to_string(),
+            set_query: HashMap::from([
+                ("page".to_string(), "2".to_string()),
+                ("q".to_string(), "a b&c".to_string()),
+            ]),
+            remove_query: vec!["drop".to_string()],
+            ..Default::default()
+        }]);
+        let (url, _, _) = rewrite(&table, "http://api.test/s?keep=1&drop=x&page=1", &[], "");
+        assert_eq!(url, "http://api.test/s?keep=1&page=2&q=a+b%26c");
+
+        let clear = routing_table(vec![RequestRewrite {
+            id: "clear".to_string(),
+            remove_query: vec!["drop".to_string()],
+            ..Default::default()
+        }]);
+        let (url, _, _) = rewrite(&clear, "http://api.test/s?drop=1", &[], "");
+        assert_eq!(url, "http://api.test/s");
+    }
+
+    #[test]
+    fn json_bodies_are_patched_and_their_length_updated() {
+        let table = routing_table(vec![RequestRewrite {
+            id: "body".to_string(),
+            merge_patch: Some(json!({"debug": null, "env": "test"})),
+            set_headers: HashMap::from([("X-Env".to_string(), "test".to_string())]),
+            remove_headers: vec!["authorization".to_string()],
+            ..Default::default()
+        }]);
+        let headers = [
+            ("content-length", "14"),
+            ("transfer-encoding", "chunked"),
+            ("authorization", "Bearer x"),
+        ];
+        let (_, headers, body) =
+            rewrite(&table, "http://api.test/", &headers, r#"{"debug": true}"#);
+        assert_eq!(body, r#"{"env":"test"}"#);
+        assert_eq!(headers["content-length"], "14");
+        assert_eq!(headers["x-env"], "test");
+        assert!(!headers.contains_key("transfer-encoding"));
+        assert!(!headers.contains_key("authorization"));
+
+        let (_, headers, body) = rewrite(
+            &table,
+            "http://api.test/",
+            &[("content-length", "4")],
+            "text",
+        );
+        assert_eq!(body, "text");
+        assert_eq!(headers["content-length"], "4");
+    }
+
+    #[test]
+    fn redirects_fill_path_params_and_keep_the_host_header_in_step() {
+        let redirect = |host: &str| RequestRewrite {
+            id: host.to_string(),
+            path: "/users/{id:int}".to_string(),
+            redirect: Some(RedirectTarget {
+                scheme: Some("http".to_string()),
+                host: Some(host.to_string()),
+                path: Some("/v2/people/{id}".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let table = routing_table(vec![redirect("localhost:8080")]);
+        let (url, headers, _) = rewrite(
+            &table,
+            "https://api.test/users/7?x=1",
+            &[("host", "api.test")],
+            "",
+        );
+        assert_eq!(url, "http://localhost:8080/v2/people/7?x=1");
+        assert_eq!(headers["host"], "localhost:8080");
+
+        let (url, _, _) = rewrite(&table, "https://api.test/users/me", &[], "");
+        assert_eq!(url, "https://api.test/users/me");
+
+        let table = routing_table(vec![redirect("[::1]:9000")]);
+        let (url, headers, _) = rewrite(
+            &table,
+            "https://api.test/users/7",
+            &[("host", "api.test")],
+            "",
+        );
+        assert_eq!(url, "http://[::1]:9000/v2/people/7");
+        assert_eq!(headers["host"], "[::1]:9000");
+    }
+
+    #[test]
+    fn rewrites_that_could_never_apply_are_rejected() {
+        let redirect = |scheme: &str, host: &str| RequestRewrite {
+            id: "r1".to_string(),
+            redirect: Some(RedirectTarget {
+                scheme: Some(scheme.to_string()),
+                host: Some(host.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(validate_rewrites(&[redirect("https", "[::1]:9000")]).is_ok());
+        assert_eq!(
+            validate_rewrites(&[redirect("ftp", "")]),
+            Err("Rewrite r1: redirect scheme must be http or https".to_string())
+        );
+        assert_eq!(
+            validate_rewrites(&[redirect("", "bad host")]),
+            Err("Rewrite r1: invalid redirect host bad host".to_string())
+        );
+        let header = RequestRewrite {
+            id: "r2".to_string(),
+            name: "Headers".to_string(),
+            set_headers: HashMap::from([("bad:name".to_string(), "x".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_rewrites(&[header]),
+            Err("Rewrite Headers: invalid header name bad:name".to_string())
+        );
+    }
+}
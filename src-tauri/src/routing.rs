@@ -0,0 +1,487 @@
+use crate::matching::{
+    build_request_path, derive_block_path, HostPattern, PathMatcher, Patterns, RuleScope,
+};
+use crate::state::AppState;
+use crate::store;
+use crate::types::{
+    Block, BodyMatcher, Profile, RequestConfig, RequestRewrite, ResponseModifier, Store, SubProfile,
+};
+use axum::http::Method;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Bucket key for templates whose method or first segment is a wildcard or a parameter.
+const ANY: &str = "*";
+/// How often `current` looks at `profiles.json` for edits made outside the app.
+const EXTERNAL_EDIT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Age below which a modification time is not trusted, as filesystems with coarse timestamps
+/// give a second edit within the same tick the same time.
+const MTIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+/// In-memory snapshot of the store with every path and host matcher, and every regex, compiled
+/// up front. Shared by the reverse and forward proxies and rebuilt only when the store changes.
+#[derive(Debug)]
+pub struct RoutingTable {
+    pub store: Store,
+    profiles: HashMap<String, CompiledProfile>,
+}
+
+/// The current table with what `current` needs to tell that it went stale.
+#[derive(Debug, Default)]
+pub struct RoutingCache {
+    /// `None` until first use or after the store is written.
+    table: Option<Arc<RoutingTable>>,
+    /// Modification time and length of `profiles.json` when the table was built; `None` when
+    /// the file was missing or modified too recently to rely on them.
+    stamp: Option<(SystemTime, u64)>,
+    checked_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct CompiledProfile {
+    pub profile: Profile,
+    pub blocks: Vec<CompiledBlock>,
+    /// One entry per (sub-profile, request) pair, in the order `find_match` tries them.
+    pub requests: Vec<CompiledRequest>,
+    pub modifiers: Vec<CompiledModifier>,
+    pub rewrites: Vec<CompiledRewrite>,
+    block_index: RouteIndex,
+    request_index: RouteIndex,
+}
+
+#[derive(Debug)]
+pub struct CompiledBlock {
+    pub block: Block,
+    pub path: Option<PathMatcher>,
+    pub host: Option<HostPattern>,
+    /// Regexes of the block's conditions, its variants' conditions and its body matchers.
+    pub patterns: Patterns,
+}
+
+#[derive(Debug)]
+pub struct CompiledRequest {
+    pub sub_profile: SubProfile,
+    pub request: RequestConfig,
+    pub path: PathMatcher,
+    pub patterns: Patterns,
+}
+
+/// An enabled response modifier with its scope and replacement regexes compiled.
+#[derive(Debug, Clone)]
+pub struct CompiledModifier {
+    pub modifier: ResponseModifier,
+    pub scope: RuleScope,
+    /// One per entry of `modifier.replacements`; `None` where the regex does not compile.
+    pub replacements: Vec<Option<Regex>>,
+}
+
+/// An enabled request rewrite with its scope compiled.
+#[derive(Debug)]
+pub struct CompiledRewrite {
+    pub rule: RequestRewrite,
+    pub scope: RuleScope,
+}
+
+/// Positions of compiled entries keyed by method, then by first path segment.
+#[derive(Debug, Default)]
+struct RouteIndex {
+    buckets: HashMap<String, HashMap<String, Vec<usize>>>,
+}
+
+impl RouteIndex {
+    fn insert(&mut self, method: &str, template: &str, position: usize) {
+        let method = match method.trim() {
+            "" | ANY => ANY.to_string(),
+            method => method.to_uppercase(),
+        };
+        let segment = first_segment(template);
+        let segment = if segment.contains('{') || segment.contains('*') {
+            ANY
+        } else {
+            segment
+        };
+        self.buckets
+            .entry(method)
+            .or_default()
+            .entry(segment.to_string())
+            .or_default()
+            .push(position);
+    }
+
+    /// Positions that could match, in insertion order.
+    fn candidates(&self, method: &Method, path: &str) -> Vec<usize> {
+        let segment = first_segment(path);
+        let mut found = Vec::new();
+        for method in [method.as_str(), ANY] {
+            let Some(by_segment) = self.buckets.get(method) else {
+                continue;
+            };
+            for segment in [segment, ANY] {
+                if let Some(positions) = by_segment.get(segment) {
+                    found.extend_from_slice(positions);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
+
+fn body_matcher_patterns(matchers: &[BodyMatcher]) -> impl Iterator<Item = &str> {
+    matchers
+        .iter()
+        .filter(|matcher| matcher.matcher_type == "regex")
+        .map(|matcher| matcher.value.as_str())
+}
+
+fn first_segment(path: &str) -> &str {
+    path.trim_start_matches('/').split('/').next().unwrap_or("")
+}
+
+impl RoutingTable {
+    pub fn build(store: Store) -> Self {
+        let profiles = store
+            .profiles
+            .iter()
+            .map(|profile| (profile.name.clone(), CompiledProfile::build(profile)))
+            .collect();
+        RoutingTable { store, profiles }
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&CompiledProfile> {
+        self.profiles.get(name)
+    }
+}
+
+impl CompiledProfile {
+    fn build(profile: &Profile) -> Self {
+        let mut block_index = RouteIndex::default();
+        let blocks = profile
+            .active_blocks
+            .iter()
+            .enumerate()
+            .map(|(position, block)| {
+                let path = derive_block_path(block)
+                    .map(|template| PathMatcher::compile(&template, &HashMap::new()));
+                if let Some(path) = path.as_ref() {
+                    block_index.insert(&block.method, path.template(), position);
+                }
+                let conditions = block
+                    .header_conditions
+                    .iter()
+                    .chain(&block.query_conditions)
+                    .chain(block.template_variants.iter().flat_map(|variant| {
+                        variant
+                            .conditions
+                            .iter()
+                            .map(|variant_condition| &variant_condition.condition)
+                    }))
+                    .filter(|condition| condition.operator == "regex")
+                    .map(|condition| condition.value.as_str());
+                CompiledBlock {
+                    block: block.clone(),
+                    path,
+                    host: block.host.as_deref().and_then(HostPattern::parse),
+                    patterns: Patterns::compile(
+                        conditions.chain(body_matcher_patterns(&block.body_matchers)),
+                    ),
+                }
+            })
+            .collect();
+
+        let mut request_index = RouteIndex::default();
+        let mut requests = Vec::new();
+        for sub_profile in &profile.sub_profiles {
+            for request in &profile.requests {
+                let mut params = sub_profile.params.clone();
+                params.extend(request.params.clone());
+                let template = build_request_path(profile, request);
+                let path = PathMatcher::compile(&template, &params);
+                request_index.insert(&request.method, &template, requests.len());
+                requests.push(CompiledRequest {
+                    sub_profile: sub_profile.clone(),
+                    request: request.clone(),
+                    path,
+                    patterns: Patterns::compile(body_matcher_patterns(&request.body_matchers)),
+                });
+            }
+        }
+
+        let modifiers = profile
+            .modifiers
+            .iter()
+            .filter(|modifier| !modifier.disabled)
+            .map(|modifier| CompiledModifier {
+                modifier: modifier.clone(),
+                scope: RuleScope::compile(
+                    &modifier.method,
+                    &modifier.path,
+                    modifier.host.as_deref(),
+                ),
+                replacements: modifier
+                    .replacements
+                    .iter()
+                    .map(|replacement| Regex::new(&replacement.find).ok())
+                    .collect(),
+            })
+            .collect();
+        let rewrites = profile
+            .rewrites
+            .iter()
+            .filter(|rule| !rule.disabled)
+            .map(|rule| CompiledRewrite {
+                rule: rule.clone(),
+                scope: RuleScope::compile(&rule.method, &rule.path, rule.host.as_deref()),
+            })
+            .collect();
+
+        CompiledProfile {
+            profile: profile.clone(),
+            blocks,
+            requests,
+            modifiers,
+            rewrites,
+            block_index,
+            request_index,
+        }
+    }
+
+    /// Positions in `blocks` whose method and first path segment could match.
+    pub fn block_candidates(&self, method: &Method, path: &str) -> Vec<usize> {
+        self.block_index.candidates(method, path)
+    }
+
+    /// Positions in `requests` whose method and first path segment could match.
+    pub fn request_candidates(&self, method: &Method, path: &str) -> Vec<usize> {
+        self.request_index.candidates(method, path)
+    }
+}
+
+/// Returns the current routing table, rebuilding it when the store has been written through
+/// `store::write_store`. Edits made to `profiles.json` outside the app are picked up within
+/// `EXTERNAL_EDIT_CHECK_INTERVAL`.
+pub async fn current(state: &AppState) -> Arc<RoutingTable> {
+    let mut cache = state.routing.lock().await;
+    if let (Some(table), Some(checked_at)) = (&cache.table, cache.checked_at) {
+        if checked_at.elapsed() < EXTERNAL_EDIT_CHECK_INTERVAL {
+            return table.clone();
+        }
+    }
+
+    let stamp = settled_stamp(&state.data_file).await;
+    cache.checked_at = Some(Instant::now());
+    if let Some(table) = &cache.table {
+        if stamp.is_some() && stamp == cache.stamp {
+            return table.clone();
+        }
+    }
+    let table = Arc::new(RoutingTable::build(store::read_store(state).await));
+    cache.table = Some(table.clone());
+    cache.stamp = stamp;
+    table
+}
+
+/// Drops the cached table so the next request rebuilds it.
+pub async fn invalidate(state: &AppState) {
+    state.routing.lock().await.table = None;
+}
+
+/// Modification time and length of `path`, once its modification time is old enough that a
+/// later edit is bound to change it.
+async fn settled_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    let modified = metadata.modified().ok()?;
+    let age = SystemTime::now().duration_since(modified).ok()?;
+    (age >= MTIME_GRANULARITY).then_some((modified, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching::find_block_match;
+    use crate::test_support::{app_state, block, request, routing_table, store};
+    use crate::types::{FieldCondition, TextReplacement};
+    use std::time::Duration;
+
+    #[test]
+    fn candidates_are_bucketed_by_method_and_first_segment() {
+        let table = routing_table(vec![
+            block("user", "GET", "/users/{id}"),
+            block("me", "*", "/users/me"),
+            block("orders", "POST", "/orders"),
+            block("tenant", "get", "/{tenant}/settings"),
+            block("no-path", "GET", ""),
+        ]);
+        let compiled = table.profile("default").unwrap();
+
+        assert_eq!(
+            compiled.block_candidates(&Method::GET, "/users/7"),
+            [0, 1, 3]
+        );
+        assert_eq!(compiled.block_candidates(&Method::POST, "/orders/1"), [2]);
+        assert_eq!(compiled.block_candidates(&Method::DELETE, "/users/1"), [1]);
+        assert!(compiled.block_candidates(&Method::PUT, "/other").is_empty());
+        assert!(table.profile("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn the_table_is_reused_until_the_store_changes() {
+        let state = app_state();
+        store::write_store(&state, &store(vec![block("a", "GET", "/a")]))
+            .await
+            .unwrap();
+
+        let first = current(&state).await;
+        assert!(Arc::ptr_eq(&first, &current(&state).await));
+
+        store::write_store(&state, &store(vec![block("b", "GET", "/b")]))
+            .await
+            .unwrap();
+        let second = current(&state).await;
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(second.profile("default").unwrap().blocks[0].block.id, "b");
+
+        let edit_externally = |id: &str, modified: SystemTime| {
+            let external = serde_json::to_string(&store(vec![block(id, "GET", "/x")])).unwrap();
+            std::fs::write(&*state.data_file, external).unwrap();
+            let file = std::fs::File::options()
+                .write(true)
+                .open(&*state.data_file)
+                .unwrap();
+            file.set_modified(modified).unwrap();
+        };
+        let block_id = |table: &Arc<RoutingTable>| {
+            table.profile("default").unwrap().blocks[0].block.id.clone()
+        };
+        let settled = SystemTime::now() - Duration::from_secs(60);
+
+        // External edits are only looked for once per check interval.
+        edit_externally("c", settled);
+        assert!(Arc::ptr_eq(&second, &current(&state).await));
+        state.routing.lock().await.checked_at = None;
+        let third = current(&state).await;
+        assert_eq!(block_id(&third), "c");
+        state.routing.lock().await.checked_at = None;
+        assert!(Arc::ptr_eq(&third, &current(&state).await));
+
+        // A file modified within the timestamp granularity is read again on every check, so
+        // a second edit in the same tick is not missed.
+        let recent = SystemTime::now();
+        edit_externally("d", recent);
+        state.routing.lock().await.checked_at = None;
+        assert_eq!(block_id(&current(&state).await), "d");
+        edit_externally("e", recent);
+        state.routing.lock().await.checked_at = None;
+        assert_eq!(block_id(&current(&state).await), "e");
+
+        std::fs::remove_dir_all(&*state.data_dir).unwrap();
+    }
+
+    #[test]
+    fn condition_regexes_are_compiled_once_and_bad_ones_never_match() {
+        let condition = |value: &str| FieldCondition {
+            name: "Authorization".to_string(),
+            operator: "regex".to_string(),
+            value: value.to_string(),
+        };
+        let mut broken = block("broken", "GET", "/secure");
+        broken.header_conditions = vec![condition("(")];
+        let mut bearer = block("bearer", "GET", "/secure");
+        bearer.header_conditions = vec![condition("^Bearer \\w+$")];
+        let table = routing_table(vec![broken, bearer]);
+
+        let matched = |authorization: &str| {
+            let request = request(Method::GET, "/secure").header("authorization", authorization);
+            find_block_match(&table, Some("default"), &request.incoming())
+                .map(|found| found.block.id)
+        };
+        assert_eq!(matched("Bearer abc").as_deref(), Some("bearer"));
+        assert_eq!(matched("Basic abc"), None);
+        assert_eq!(matched("("), None);
+    }
+
+    #[test]
+    fn only_enabled_modifiers_and_rewrites_are_compiled() {
+        let modifier = |id: &str, disabled: bool| ResponseModifier {
+            id: id.to_string(),
+            disabled,
+            path: "/users/{id:int}".to_string(),
+            host: Some("*.example.com".to_string()),
+            replacements: vec![
+                TextReplacement {
+                    find: "a(b)".to_string(),
+                    ..Default::default()
+                },
+                TextReplacement {
+                    find: "(".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let rewrite = |id: &str, disabled: bool| RequestRewrite {
+            id: id.to_string(),
+            disabled,
+            method: "post".to_string(),
+            ..Default::default()
+        };
+        let mut store = store(Vec::new());
+        store.profiles[0].modifiers = vec![modifier("on", false), modifier("off", true)];
+        store.profiles[0].rewrites = vec![rewrite("off", true), rewrite("on", false)];
+        let table = RoutingTable::build(store);
+        let compiled = table.profile("default").unwrap();
+
+        assert_eq!(compiled.modifiers.len(), 1);
+        let modifier = &compiled.modifiers[0];
+        assert_eq!(modifier.modifier.id, "on");
+        assert!(modifier.replacements[0].is_some());
+        assert!(modifier.replacements[1].is_none());
+        assert_eq!(
+            modifier
+                .scope
+                .captures(&Method::GET, Some("api.example.com:443"), "/users/5"),
+            Some(HashMap::from([("id".to_string(), "5".to_string())]))
+        );
+        assert!(modifier
+            .scope
+            .captures(&Method::GET, Some("example.com"), "/users/5")
+            .is_none());
+        assert!(modifier
+            .scope
+            .captures(&Method::GET, None, "/users/5")
+            .is_none());
+
+        assert_eq!(compiled.rewrites.len(), 1);
+        let rewrite = &compiled.rewrites[0];
+        assert_eq!(rewrite.rule.id, "on");
+        assert!(rewrite
+            .scope
+            .captures(&Method::POST, None, "/any/path")
+            .is_some());
+        assert!(rewrite
+            .scope
+            .captures(&Method::GET, None, "/any/path")
+            .is_none());
+    }
+
+    #[test]
+    fn host_patterns_ignore_the_port_unless_they_name_one() {
+        let matches =
+            |pattern: &str, host: &str| HostPattern::parse(pattern).unwrap().matches(host);
+        assert!(matches("API.example.com", "api.example.com:8443"));
+        assert!(matches("localhost:8080", "localhost:8080"));
+        assert!(!matches("localhost:8080", "localhost:9090"));
+        assert!(matches("*.example.com", "a.b.example.com"));
+        assert!(!matches("*.example.com", "example.com"));
+        assert!(!matches("*.example.com", "evil-example.com"));
+        assert!(matches("regex:^api\\d+\\.test$", "api12.test:80"));
+        assert!(!matches("regex:(", "anything"));
+        assert!(matches("[::1]", "[::1]:3000"));
+        assert!(!matches("[::1]:3000", "[::1]:4000"));
+        assert!(HostPattern::parse("  ").is_none());
+    }
+}
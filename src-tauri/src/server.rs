@@ -1,12 +1,16 @@
 use crate::ca;
 use crate::forward_proxy;
 use crate::handlers;
+use crate::routing::RoutingCache;
 use crate::state::{AppState, LogStore};
 use crate::store;
 use crate::system_proxy;
+use crate::types::{NetworkConditions, RecordConfig};
 use axum::http::Method;
-use axum::routing::{any, get, post, put};
+use axum::middleware;
+use axum::routing::{any, delete, get, post, put};
 use axum::Router;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tauri::Manager;
@@ -33,6 +37,13 @@ pub async fn run_server(app_handle: tauri::AppHandle) -> Result<(), String> {
         http_client: reqwest::Client::new(),
         ca_cert_pem: Arc::new(ca_files.cert_pem.clone()),
         recording: Arc::new(AtomicBool::new(false)),
+        routing: Arc::new(Mutex::new(RoutingCache::default())),
+        block_hits: Arc::new(Mutex::new(HashMap::new())),
+        mock_state: Arc::new(Mutex::new(HashMap::new())),
+        resources: Arc::new(Mutex::new(HashMap::new())),
+        network: Arc::new(Mutex::new(NetworkConditions::default())),
+        record: Arc::new(Mutex::new(RecordConfig::default())),
+        store_lock: Arc::new(Mutex::new(())),
     };
     let store = store::read_store(&state).await;
     *state.active_profile.lock().await = store.active_profile.clone();
@@ -64,6 +75,10 @@ pub async fn run_server(app_handle: tauri::AppHandle) -> Result<(), String> {
             "/api/profiles/:profile_name/subprofiles",
             post(handlers::create_sub_profile),
         )
+        .route(
+            "/api/profiles/:profile_name/active-subprofile",
+            put(handlers::set_active_sub_profile),
+        )
         .route(
             "/api/profiles/:profile_name/subprofiles/:subprofile_name",
             put(handlers::update_sub_profile).delete(handlers::delete_sub_profile),
@@ -84,11 +99,42 @@ pub async fn run_server(app_handle: tauri::AppHandle) -> Result<(), String> {
             "/api/profiles/:profile_name/blocks",
             get(handlers::get_blocks).put(handlers::update_blocks),
         )
+        .route(
+            "/api/profiles/:profile_name/blocks/validate",
+            post(handlers::validate_block),
+        )
+        .route(
+            "/api/profiles/:profile_name/blocks/hits",
+            get(handlers::get_block_hits).delete(handlers::reset_block_hits),
+        )
+        .route(
+            "/api/profiles/:profile_name/blocks/:block_id/hits",
+            delete(handlers::reset_block_hit),
+        )
+        .route(
+            "/api/profiles/:profile_name/state",
+            get(handlers::get_mock_state)
+                .put(handlers::replace_mock_state)
+                .delete(handlers::reset_mock_state),
+        )
+        .route(
+            "/api/profiles/:profile_name/state/:key",
+            delete(handlers::delete_mock_state_key),
+        )
+        .route(
+            "/api/profiles/:profile_name/resources/:resource_id/items",
+            get(handlers::get_resource_items).delete(handlers::reset_resource_items),
+        )
         .route(
             "/api/active-profile",
             get(handlers::get_active_profile).put(handlers::set_active_profile),
         )
         .route("/api/logs", get(handlers::get_logs))
+        .route(
+            "/api/logs/:log_id/explain",
+            get(handlers::explain_logged_request),
+        )
+        .route("/api/match/explain", post(handlers::explain_match))
         .route("/api/request-counts", get(handlers::get_request_counts))
         // Proxy management endpoints
         .route("/api/proxy/status", get(handlers::proxy_status))
@@ -117,7 +163,21 @@ pub async fn run_server(app_handle: tauri::AppHandle) -> Result<(), String> {
             "/api/proxy/recording/status",
             get(handlers::recording_status),
         )
-        .route("/*path", any(handlers::proxy_handler))
+        .route(
+            "/api/proxy/record",
+            get(handlers::get_record_config).put(handlers::set_record_config),
+        )
+        .route(
+            "/api/network",
+            get(handlers::get_network_conditions).put(handlers::set_network_conditions),
+        )
+        .route(
+            "/*path",
+            any(handlers::proxy_handler).layer(middleware::from_fn_with_state(
+                state.clone(),
+                handlers::throttle_proxy_response,
+            )),
+        )
         .with_state(state.clone())
         .layer(cors);
 
@@ -1,4 +1,7 @@
+use crate::routing::RoutingCache;
+use crate::types::{BlockRef, NetworkConditions, RecordConfig};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
@@ -15,20 +18,54 @@ pub struct AppState {
     pub http_client: reqwest::Client,
     pub ca_cert_pem: Arc<String>,
     pub recording: Arc<AtomicBool>,
+    /// Compiled matcher snapshot, read through `routing::current`.
+    pub routing: Arc<Mutex<RoutingCache>>,
+    /// Times each block has matched, keyed by `variants::hit_key`; drives variant sequencing.
+    pub block_hits: Arc<Mutex<HashMap<ProfileKey, u64>>>,
+    /// Mock state written by block effects, one JSON object per profile name.
+    pub mock_state: Arc<Mutex<HashMap<String, Map<String, Value>>>>,
+    /// Items of each resource collection, keyed by `resources::data_key`; seeded on first use.
+    pub resources: Arc<Mutex<HashMap<ProfileKey, Vec<Value>>>>,
+    /// Network profiles throttling responses of both proxies; changed at runtime.
+    pub network: Arc<Mutex<NetworkConditions>>,
+    /// Record mode settings; while enabled, selected passthrough responses become blocks.
+    pub record: Arc<Mutex<RecordConfig>>,
+    /// Held from `read_store` until `write_store` returns by everything that changes the store,
+    /// so concurrent writers such as the recorder and the API never drop each other's changes.
+    pub store_lock: Arc<Mutex<()>>,
 }
 
+/// Key of runtime state kept per profile: the profile name and a block or resource id.
+pub type ProfileKey = (String, String);
+
 #[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RequestLogEntry {
+    pub id: u64,
     pub timestamp_ms: u128,
     pub method: String,
     pub path: String,
     pub query: HashMap<String, String>,
+    pub request_headers: HashMap<String, String>,
+    pub request_body: Option<String>,
+    /// Whether the logged headers and body are the ones matched on, so the entry can be
+    /// explained again. Redacted, repeated or unreadable headers and cut or non-UTF-8 bodies
+    /// are not.
+    pub explainable: bool,
     pub matched: bool,
     pub profile: Option<String>,
     pub sub_profile: Option<String>,
     pub request: Option<String>,
     pub block: Option<String>,
+    /// Template variant the block response was rendered with.
+    pub variant: Option<String>,
+    /// Resource collection that answered the request.
+    pub resource: Option<String>,
+    /// Simulated latency applied before the response was sent.
+    pub delay_ms: Option<u64>,
+    /// Fault served instead of the logged response.
+    pub fault: Option<String>,
+    pub shadowed_blocks: Vec<BlockRef>,
     pub response: Option<LoggedResponse>,
     pub source_app: Option<String>,
     pub host: Option<String>,
@@ -63,6 +100,9 @@ pub struct LoggedResponse {
 pub struct LogStore {
     pub entries: VecDeque<RequestLogEntry>,
     pub counts: HashMap<MatchKey, u64>,
+    pub next_id: u64,
 }
 
 pub const MAX_LOG_ENTRIES: usize = 500;
+/// Request bodies longer than this are truncated in the log.
+pub const MAX_LOGGED_BODY_BYTES: usize = 64 * 1024;
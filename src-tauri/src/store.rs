@@ -1,3 +1,4 @@
+use crate::routing;
 use crate::state::AppState;
 use crate::types::{Library, Profile, Store};
 use std::path::PathBuf;
@@ -11,6 +12,7 @@ pub fn ensure_profile_libraries(profile: &mut Profile) {
             name: "Local".to_string(),
             lib_type: "local".to_string(),
             folder_path: None,
+            variables: Vec::new(),
         }];
     }
 }
@@ -54,9 +56,11 @@ pub async fn write_store(state: &AppState, store: &Store) -> Result<(), String>
             .map_err(|error| error.to_string())?;
     }
     let payload = serde_json::to_string_pretty(store).map_err(|error| error.to_string())?;
-    tokio::fs::write(&*state.data_file, payload)
+    let result = tokio::fs::write(&*state.data_file, payload)
         .await
-        .map_err(|error| error.to_string())
+        .map_err(|error| error.to_string());
+    routing::invalidate(state).await;
+    result
 }
 
 pub fn default_store() -> Store {
@@ -1,20 +1,42 @@
-use crate::types::{Block, BlockMatch, TemplateValue};
-use serde_json::Value;
+use crate::generators::{render_generators, Generator};
+use crate::matching::{parse_form_body, IncomingRequest};
+use crate::response::header_map_to_string_map;
+use crate::template_engine::Template;
+use crate::types::{Block, BlockMatch, Profile, TemplateValue, TemplateVariant};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
 
-/// For array-type template values: substitute only enabled items as JSON array.
-fn substitution_string_for_value(value: &TemplateValue) -> String {
-    if value.value_type != "array" {
-        return value.value.clone();
-    }
+/// Parses a template value as its declared `value_type`: `string`, `number`, `boolean`,
+/// `object` (any JSON), `null`, or `array` (editor items, of which only enabled ones are kept).
+pub fn typed_value(value: &TemplateValue) -> Result<Value, String> {
     let trimmed = value.value.trim();
-    if trimmed.is_empty() {
-        return "[]".to_string();
+    match value.value_type.as_str() {
+        "string" => Ok(Value::String(value.value.clone())),
+        "number" => match serde_json::from_str::<Value>(trimmed) {
+            Ok(number @ Value::Number(_)) => Ok(number),
+            _ => Err(format!("\"{}\" is not a number", value.value)),
+        },
+        "boolean" => match trimmed {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(format!("\"{}\" is not true or false", value.value)),
+        },
+        "object" => serde_json::from_str::<Value>(trimmed)
+            .map_err(|error| format!("is not valid JSON ({})", error)),
+        "null" => Ok(Value::Null),
+        "array" => enabled_array_items(trimmed).map(Value::Array),
+        other => Err(format!("has unknown type \"{}\"", other)),
     }
-    let parsed: Option<Value> = serde_json::from_str(trimmed).ok();
-    let Some(Value::Array(arr)) = parsed else {
-        return value.value.clone();
+}
+
+fn enabled_array_items(value: &str) -> Result<Vec<Value>, String> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Ok(Value::Array(arr)) = serde_json::from_str::<Value>(value) else {
+        return Err("is not a JSON array".to_string());
     };
-    let mut enabled: Vec<String> = Vec::new();
+    let mut enabled: Vec<Value> = Vec::new();
     for item in arr {
         if let Some(obj) = item.as_object() {
             let v = obj
@@ -24,60 +46,494 @@ fn substitution_string_for_value(value: &TemplateValue) -> String {
                 .to_string();
             let e = obj.get("e").and_then(Value::as_bool).unwrap_or(true);
             if e {
-                enabled.push(v);
+                enabled.push(Value::String(v));
             }
         } else if let Some(s) = item.as_str() {
-            enabled.push(s.to_string());
+            enabled.push(Value::String(s.to_string()));
         }
     }
-    serde_json::to_string(&enabled).unwrap_or_else(|_| value.value.clone())
+    Ok(enabled)
+}
+
+/// Problems with the typed values of a block and its variants, one message per bad value.
+pub fn validate_block_values(block: &Block) -> Vec<String> {
+    let variant_values = block.template_variants.iter().flat_map(|variant| {
+        variant
+            .values
+            .iter()
+            .map(move |value| (Some(variant), value))
+    });
+    block
+        .template_values
+        .iter()
+        .map(|value| (None, value))
+        .chain(variant_values)
+        .filter_map(|(variant, value)| {
+            let error = typed_value(value).err()?;
+            let location = match variant {
+                Some(variant) => format!("{} / {}", block.name, variant.name),
+                None => block.name.clone(),
+            };
+            Some(format!(
+                "{}: {} value \"{}\" {}",
+                location, value.value_type, value.key, error
+            ))
+        })
+        .collect()
+}
+
+/// A value that fails validation is used as a plain string rather than dropped.
+fn context_value(value: &TemplateValue) -> Value {
+    typed_value(value).unwrap_or_else(|_| Value::String(value.value.clone()))
 }
 
-pub fn render_template(template: &str, values: &[TemplateValue]) -> String {
-    let mut output = template.to_string();
+/// Keyed context for the template engine; the first value wins when keys repeat.
+pub fn template_context(values: &[TemplateValue]) -> Map<String, Value> {
+    let mut context = Map::new();
     for value in values {
-        if value.key.is_empty() {
+        if value.key.is_empty() || context.contains_key(&value.key) {
             continue;
         }
-        let needle = format!("{{{{{}}}}}", value.key);
-        let replacement = substitution_string_for_value(value);
-        output = output.replace(&needle, &replacement);
+        context.insert(value.key.clone(), context_value(value));
     }
-    output
+    context
 }
 
-pub fn active_template_values(block: &Block) -> &[TemplateValue] {
+/// Renders `template` with the template language in `template_engine`; `{{$...}}` generator tags
+/// draw from `generator`. A template that does not parse falls back to plain `{{key}}`
+/// replacement so it renders exactly as it used to.
+pub fn render_template(
+    template: &str,
+    values: &[TemplateValue],
+    generator: &mut Generator,
+) -> String {
+    render(template, values, false, generator)
+}
+
+/// `render_template` for response bodies. When the template looks like JSON, values are encoded
+/// for where they land: escaped inside string literals, as JSON literals outside them.
+pub fn render_body_template(
+    template: &str,
+    values: &[TemplateValue],
+    generator: &mut Generator,
+) -> String {
+    render(template, values, looks_like_json(template), generator)
+}
+
+/// Whether a body template is meant to produce JSON: it opens with `[` or with a `{` that is not
+/// the start of a placeholder.
+pub fn looks_like_json(template: &str) -> bool {
+    let trimmed = template.trim_start();
+    trimmed.starts_with('[') || (trimmed.starts_with('{') && !trimmed.starts_with("{{"))
+}
+
+fn render(
+    template: &str,
+    values: &[TemplateValue],
+    json: bool,
+    generator: &mut Generator,
+) -> String {
+    let context = template_context(values);
+    match Template::parse(template) {
+        Ok(parsed) => {
+            parsed.render_with_fallback(&context, json, &mut |tag| unresolved_value(tag, generator))
+        }
+        Err(_) => {
+            // Generators run before values are substituted so they never expand request data.
+            let mut output = render_generators(template, generator);
+            for (key, value) in &context {
+                let needle = format!("{{{{{}}}}}", key);
+                let replacement = match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                output = output.replace(&needle, &replacement);
+            }
+            output
+        }
+    }
+}
+
+/// Text for a placeholder the context leaves unresolved: a `{{$...}}` generator, or an empty
+/// string for a `request.*` field the request did not send.
+fn unresolved_value(tag: &str, generator: &mut Generator) -> Option<String> {
+    if tag.starts_with('$') {
+        return generator.generate_tag(tag);
+    }
+    (tag == "request" || tag.starts_with("request.")).then(String::new)
+}
+
+/// The request being answered, as the `request` object templates read: `method`, `host`,
+/// `path`, `params`, `query`, `header` (lowercase names, also as `headers`), `body` and
+/// `rawBody`. `body` is the parsed JSON, the fields of a form post, or else the raw text.
+pub fn request_context(request: &IncomingRequest, params: &HashMap<String, String>) -> Value {
+    let raw_body = String::from_utf8_lossy(request.body).to_string();
+    let is_form = request
+        .headers
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    let body = match serde_json::from_str::<Value>(&raw_body) {
+        Ok(json) => json,
+        Err(_) if is_form => json!(parse_form_body(&raw_body)),
+        Err(_) => Value::String(raw_body.clone()),
+    };
+    let headers = json!(header_map_to_string_map(request.headers));
+    json!({
+        "method": request.method.as_str(),
+        "host": request.host.unwrap_or_default(),
+        "path": request.path,
+        "params": params,
+        "query": request.query,
+        "header": headers,
+        "headers": headers,
+        "body": body,
+        "rawBody": raw_body,
+    })
+}
+
+pub fn active_variant(block: &Block) -> Option<&TemplateVariant> {
     if let Some(active_id) = block.active_variant_id.as_deref() {
         if let Some(variant) = block
             .template_variants
             .iter()
             .find(|variant| variant.id == active_id)
         {
-            return &variant.values;
+            return Some(variant);
         }
     }
-    if let Some(variant) = block.template_variants.first() {
-        return &variant.values;
+    block.template_variants.first()
+}
+
+pub fn active_template_values(block: &Block) -> &[TemplateValue] {
+    match active_variant(block) {
+        Some(variant) => &variant.values,
+        None => &block.template_values,
     }
-    &block.template_values
 }
 
-pub fn merged_template_values(block_match: &BlockMatch) -> Vec<TemplateValue> {
-    let mut values = active_template_values(&block_match.block).to_vec();
+/// Unrendered status for a block: the active variant's status wins over the block's own.
+pub fn active_status_template(block: &Block) -> Option<&str> {
+    let non_empty = |value: &String| !value.trim().is_empty();
+    active_variant(block)
+        .and_then(|variant| variant.status.as_ref().filter(|value| non_empty(value)))
+        .or_else(|| block.status.as_ref().filter(|value| non_empty(value)))
+        .map(String::as_str)
+}
+
+/// Values a block's templates can read, in precedence order: the request being answered as
+/// `request`, the active variant (or block) values, path params, `shared_template_values`, then
+/// the mock state as `state`. The first value for a key wins.
+pub fn merged_template_values(
+    block_match: &BlockMatch,
+    request: &IncomingRequest,
+) -> Vec<TemplateValue> {
+    let mut values = vec![TemplateValue {
+        id: "request".to_string(),
+        key: "request".to_string(),
+        value: request_context(request, &block_match.extracted_params).to_string(),
+        value_type: "object".to_string(),
+    }];
+    values.extend(active_template_values(&block_match.block).iter().cloned());
     for (key, value) in &block_match.extracted_params {
         if values.iter().any(|item| item.key == *key) {
             continue;
         }
+        // Numeric params are numbers, so an unquoted `{{id}}` still renders as one.
+        let value_type = match serde_json::from_str::<Value>(value) {
+            Ok(Value::Number(_)) => "number",
+            _ => "string",
+        };
         values.push(TemplateValue {
             id: format!("path-param-{}", key),
             key: key.clone(),
             value: value.clone(),
-            value_type: "string".to_string(),
+            value_type: value_type.to_string(),
         });
     }
+    values.extend(shared_template_values(
+        &block_match.profile,
+        &block_match.block,
+    ));
+    values.push(TemplateValue {
+        id: "mock-state".to_string(),
+        key: "state".to_string(),
+        value: Value::Object(block_match.state.clone()).to_string(),
+        value_type: "object".to_string(),
+    });
     values
 }
 
+/// Fallback values shared across blocks: the active sub-profile's params, then the variables
+/// of the block's library, then the profile variables.
+pub fn shared_template_values(profile: &Profile, block: &Block) -> Vec<TemplateValue> {
+    let mut values: Vec<TemplateValue> = profile
+        .active_sub_profile
+        .as_deref()
+        .and_then(|name| profile.sub_profiles.iter().find(|sub| sub.name == name))
+        .map(|sub_profile| {
+            sub_profile
+                .params
+                .iter()
+                .map(|(key, value)| TemplateValue {
+                    id: format!("sub-profile-param-{}", key),
+                    key: key.clone(),
+                    value: value.clone(),
+                    value_type: "string".to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let library_id = block.source_library_id.as_deref().unwrap_or("local");
+    if let Some(library) = profile
+        .libraries
+        .iter()
+        .find(|library| library.id == library_id)
+    {
+        values.extend(library.variables.iter().cloned());
+    }
+    values.extend(profile.variables.iter().cloned());
+    values
+}
+
+/// Rejects a variable set when any value does not parse as its declared type.
+pub fn validate_variables(variables: &[TemplateValue]) -> Result<(), String> {
+    let errors: Vec<String> = variables
+        .iter()
+        .filter_map(|value| {
+            let error = typed_value(value).err()?;
+            Some(format!(
+                "{} variable \"{}\" {}",
+                value.value_type, value.key, error
+            ))
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
 pub fn normalize_json_quotes(value: &str) -> String {
     value.replace('\u{201C}', "\"").replace('\u{201D}', "\"")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block, block_match, request};
+    use crate::types::{Library, SubProfile};
+    use axum::http::Method;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn request_values_are_escaped_or_typed_where_they_land() {
+        let request = request(Method::POST, "/users/7")
+            .host("api.test")
+            .header("X-Trace", "a\"b")
+            .query("q", "x \\ y")
+            .body(r#"{"name": "O\"Brien", "tags": ["a"], "age": 40, "echo": "{{$uuid}}"}"#);
+        let mut found = block_match(Block {
+            template_values: vec![value("name", "string", "fixed")],
+            ..block("b", "POST", "/users/{id}")
+        });
+        found.extracted_params = HashMap::from([("id".to_string(), "7".to_string())]);
+        let values = merged_template_values(&found, &request.incoming());
+        let body = render_body(
+            r#"{"id": "{{id}}", "number": {{id}}, "method": "{{request.method}}", "name": "{{request.body.name}}",
+                "age": {{request.body.age}}, "tags": {{request.body.tags}},
+                "trace": "{{request.header.x-trace}}", "q": "{{request.query.q}}",
+                "fixed": "{{name}}", "echo": "{{request.body.echo}}", "missing": "{{request.body.nope}}"}"#,
+            &values,
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({
+                "id": "7", "number": 7, "method": "POST", "name": "O\"Brien", "age": 40, "tags": ["a"],
+                "trace": "a\"b", "q": "x \\ y", "fixed": "fixed", "echo": "{{$uuid}}",
+                "missing": ""
+            })
+        );
+    }
+
+    #[test]
+    fn form_and_text_bodies_are_exposed_as_request_body() {
+        let form = request(Method::POST, "/login")
+            .header(
+                "content-type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .body("user=al%69ce&note=a+b");
+        let context = request_context(&form.incoming(), &HashMap::new());
+        assert_eq!(context["body"], json!({"user": "alice", "note": "a b"}));
+        assert_eq!(context["rawBody"], json!("user=al%69ce&note=a+b"));
+        assert_eq!(context["host"], json!(""));
+
+        let text = request(Method::POST, "/login").body("user=al%69ce&note=a+b");
+        let context = request_context(&text.incoming(), &HashMap::new());
+        assert_eq!(context["body"], json!("user=al%69ce&note=a+b"));
+    }
+
+    fn value(key: &str, value_type: &str, value: &str) -> TemplateValue {
+        TemplateValue {
+            id: key.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            value_type: value_type.to_string(),
+        }
+    }
+
+    fn render_body(template: &str, values: &[TemplateValue]) -> String {
+        render_body_template(template, values, &mut Generator::new(Some(1)))
+    }
+
+    fn render_text(template: &str, values: &[TemplateValue]) -> String {
+        render_template(template, values, &mut Generator::new(Some(1)))
+    }
+
+    #[test]
+    fn typed_values_parse_as_their_declared_type() {
+        assert_eq!(typed_value(&value("n", "number", " 4.5 ")), Ok(json!(4.5)));
+        assert_eq!(typed_value(&value("b", "boolean", "true")), Ok(json!(true)));
+        assert_eq!(typed_value(&value("s", "string", " 7 ")), Ok(json!(" 7 ")));
+        assert_eq!(typed_value(&value("z", "null", "ignored")), Ok(Value::Null));
+        assert_eq!(
+            typed_value(&value("o", "object", r#"{"a": [1, "x"]}"#)),
+            Ok(json!({"a": [1, "x"]}))
+        );
+        assert_eq!(
+            typed_value(&value(
+                "a",
+                "array",
+                r#"[{"v": "a"}, {"v": "b", "e": false}, "c"]"#
+            )),
+            Ok(json!(["a", "c"]))
+        );
+        assert_eq!(typed_value(&value("a", "array", "")), Ok(json!([])));
+    }
+
+    #[test]
+    fn bad_typed_values_are_reported_and_rendered_as_strings() {
+        assert_eq!(
+            typed_value(&value("n", "number", "\"4\"")),
+            Err("\"\"4\"\" is not a number".to_string())
+        );
+        assert!(typed_value(&value("b", "boolean", "yes")).is_err());
+        assert!(typed_value(&value("a", "array", "{}")).is_err());
+        assert!(typed_value(&value("x", "date", "today")).is_err());
+
+        let block = Block {
+            name: "Users".to_string(),
+            template_values: vec![value("count", "number", "many")],
+            template_variants: vec![TemplateVariant {
+                name: "Empty".to_string(),
+                values: vec![value("ok", "boolean", "maybe")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_block_values(&block),
+            [
+                "Users: number value \"count\" \"many\" is not a number",
+                "Users / Empty: boolean value \"ok\" \"maybe\" is not true or false",
+            ]
+        );
+        assert_eq!(
+            render_body(r#"{"count": {{count}}}"#, &block.template_values),
+            r#"{"count": "many"}"#
+        );
+    }
+
+    #[test]
+    fn json_bodies_get_typed_literals_and_escaped_strings() {
+        let values = [
+            value("count", "number", "3"),
+            value("active", "boolean", "false"),
+            value("tags", "array", r#"["a","b"]"#),
+            value("note", "string", "line\n\"quoted\""),
+        ];
+        assert_eq!(
+            render_body(
+                r#"{"count": {{count}}, "active": {{active}}, "tags": {{tags}}, "note": "{{note}}"}"#,
+                &values
+            ),
+            r#"{"count": 3, "active": false, "tags": ["a","b"], "note": "line\n\"quoted\""}"#
+        );
+        assert_eq!(
+            render_body("note: {{note}} ({{count}})", &values),
+            "note: line\n\"quoted\" (3)"
+        );
+    }
+
+    #[test]
+    fn first_value_wins_and_unparsable_templates_fall_back_to_replacement() {
+        let values = [
+            value("name", "string", "Ada"),
+            value("name", "string", "Alan"),
+            value("count", "number", "2"),
+        ];
+        assert_eq!(
+            render_text("{{name}} {{#if}} {{count}}", &values),
+            "Ada {{#if}} 2"
+        );
+    }
+
+    #[test]
+    fn block_values_win_over_params_sub_profile_library_and_profile_variables() {
+        let mut found = block_match(Block {
+            template_values: vec![value("a", "string", "block")],
+            source_library_id: Some("lib".to_string()),
+            ..block("b", "GET", "/users/{b}")
+        });
+        found.extracted_params = HashMap::from([
+            ("a".to_string(), "param".to_string()),
+            ("b".to_string(), "param".to_string()),
+        ]);
+        let profile = &mut found.profile;
+        profile.sub_profiles = vec![SubProfile {
+            name: "dev".to_string(),
+            params: HashMap::from([
+                ("b".to_string(), "sub".to_string()),
+                ("c".to_string(), "sub".to_string()),
+            ]),
+        }];
+        profile.active_sub_profile = Some("dev".to_string());
+        profile.libraries = vec![Library {
+            id: "lib".to_string(),
+            variables: vec![
+                value("c", "string", "library"),
+                value("d", "string", "library"),
+            ],
+            ..Default::default()
+        }];
+        profile.variables = vec![value("d", "string", "profile"), value("e", "number", "5")];
+
+        let values =
+            merged_template_values(&found, &request(Method::GET, "/users/param").incoming());
+        assert_eq!(
+            render_body(r#"["{{a}}", "{{b}}", "{{c}}", "{{d}}", {{e}}]"#, &values),
+            r#"["block", "param", "sub", "library", 5]"#
+        );
+
+        found.profile.active_sub_profile = None;
+        found.block.source_library_id = None;
+        let values =
+            merged_template_values(&found, &request(Method::GET, "/users/param").incoming());
+        assert_eq!(render_text("{{c}} {{d}}", &values), "{{c}} profile");
+    }
+
+    #[test]
+    fn variables_must_parse_as_their_type() {
+        assert_eq!(validate_variables(&[value("a", "number", "1")]), Ok(()));
+        assert_eq!(
+            validate_variables(&[value("a", "number", "x"), value("b", "boolean", "no")]),
+            Err(
+                "number variable \"a\" \"x\" is not a number; boolean variable \"b\" \"no\" is not true or false"
+                    .to_string()
+            )
+        );
+    }
+}
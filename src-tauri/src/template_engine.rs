@@ -0,0 +1,810 @@
+use crate::json_path::lookup_json_path;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// A parsed response template. Besides plain `{{key}}` placeholders it understands
+/// `{{#if expr}}`, `{{#unless expr}}`, `{{#each expr}}` (each with an optional `{{else}}`),
+/// and filters chained with `|`: `upper`, `lower`, `json`, `length` and `default <value>`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+/// A context path read by a template, with the 1-based position of its tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateReference {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    /// Inside an `{{#each}}` body, where the path may name a field of the current item.
+    pub in_loop: bool,
+}
+
+/// A template syntax error with a 1-based position in the source.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Expr(Placeholder),
+    If {
+        condition: Expression,
+        negate: bool,
+        then_branch: Vec<Node>,
+        else_branch: Vec<Node>,
+    },
+    Each {
+        items: Expression,
+        body: Vec<Node>,
+        else_branch: Vec<Node>,
+    },
+}
+
+/// A `{{...}}` output tag, kept with its source so unresolved tags can be written back as-is.
+#[derive(Debug, Clone)]
+struct Placeholder {
+    raw: String,
+    inner: String,
+    expression: Expression,
+}
+
+#[derive(Debug, Clone)]
+struct Expression {
+    path: String,
+    filters: Vec<Filter>,
+    /// 1-based line and column of the enclosing tag.
+    position: (usize, usize),
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    name: String,
+    argument: Option<String>,
+}
+
+const FILTERS: &[&str] = &["upper", "lower", "json", "length", "default"];
+
+/// An open `#if`/`#unless`/`#each` waiting for its closing tag.
+struct OpenBlock {
+    kind: &'static str,
+    expression: Expression,
+    offset: usize,
+    nodes: Vec<Node>,
+    else_nodes: Option<Vec<Node>>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut root: Vec<Node> = Vec::new();
+        let mut stack: Vec<OpenBlock> = Vec::new();
+        let mut cursor = 0;
+
+        while let Some(start) = source[cursor..].find("{{").map(|index| cursor + index) {
+            let Some(end) = source[start + 2..]
+                .find("}}")
+                .map(|index| start + 2 + index)
+            else {
+                break;
+            };
+            push_text(&mut root, &mut stack, &source[cursor..start]);
+            let inner = &source[start + 2..end];
+            let raw = &source[start..end + 2];
+            cursor = end + 2;
+            let tag = inner.trim();
+
+            if let Some(rest) = tag.strip_prefix('#') {
+                let (kind, argument) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let kind = match kind {
+                    "if" => "if",
+                    "unless" => "unless",
+                    "each" => "each",
+                    _ => {
+                        return Err(error_at(
+                            source,
+                            start,
+                            format!("Unknown block tag {{{{#{}}}}}", kind),
+                        ))
+                    }
+                };
+                if argument.trim().is_empty() {
+                    return Err(error_at(
+                        source,
+                        start,
+                        format!("{{{{#{}}}}} needs an expression", kind),
+                    ));
+                }
+                let expression = parse_expression(argument, line_column(source, start))
+                    .map_err(|message| error_at(source, start, message))?;
+                stack.push(OpenBlock {
+                    kind,
+                    expression,
+                    offset: start,
+                    nodes: Vec::new(),
+                    else_nodes: None,
+                });
+            } else if tag == "else" {
+                let Some(open) = stack.last_mut() else {
+                    return Err(error_at(
+                        source,
+                        start,
+                        "{{else}} outside of a block".into(),
+                    ));
+                };
+                if open.else_nodes.is_some() {
+                    return Err(error_at(
+                        source,
+                        start,
+                        format!("Duplicate {{{{else}}}} in {{{{#{}}}}}", open.kind),
+                    ));
+                }
+                open.else_nodes = Some(Vec::new());
+            } else if let Some(kind) = tag.strip_prefix('/') {
+                let kind = kind.trim();
+                let Some(open) = stack.pop() else {
+                    return Err(error_at(
+                        source,
+                        start,
+                        format!("{{{{/{}}}}} has no matching opening tag", kind),
+                    ));
+                };
+                if open.kind != kind {
+                    return Err(error_at(
+                        source,
+                        start,
+                        format!(
+                            "Expected {{{{/{}}}}} but found {{{{/{}}}}}",
+                            open.kind, kind
+                        ),
+                    ));
+                }
+                let (nodes, else_branch) = match open.else_nodes {
+                    Some(else_nodes) => (open.nodes, else_nodes),
+                    None => (open.nodes, Vec::new()),
+                };
+                let node = match open.kind {
+                    "each" => Node::Each {
+                        items: open.expression,
+                        body: nodes,
+                        else_branch,
+                    },
+                    kind => Node::If {
+                        condition: open.expression,
+                        negate: kind == "unless",
+                        then_branch: nodes,
+                        else_branch,
+                    },
+                };
+                current_nodes(&mut root, &mut stack).push(node);
+            } else {
+                let expression = parse_expression(inner, line_column(source, start))
+                    .map_err(|message| error_at(source, start, message))?;
+                current_nodes(&mut root, &mut stack).push(Node::Expr(Placeholder {
+                    raw: raw.to_string(),
+                    inner: inner.to_string(),
+                    expression,
+                }));
+            }
+        }
+
+        push_text(&mut root, &mut stack, &source[cursor..]);
+        if let Some(open) = stack.pop() {
+            return Err(error_at(
+                source,
+                open.offset,
+                format!("{{{{#{}}}}} is never closed", open.kind),
+            ));
+        }
+        Ok(Template { nodes: root })
+    }
+
+    /// Renders against `context`. Placeholders that resolve to nothing are written back
+    /// unchanged.
+    pub fn render(&self, context: &Map<String, Value>) -> String {
+        self.render_with_fallback(context, false, &mut |_| None)
+    }
+
+    /// Like `render`, for JSON output: values placed inside a string literal are JSON-escaped,
+    /// and typed values outside one are written as JSON (`5`, `true`, `{"a":1}`, `null`).
+    pub fn render_json(&self, context: &Map<String, Value>) -> String {
+        self.render_with_fallback(context, true, &mut |_| None)
+    }
+
+    /// `render` or, with `json`, `render_json`, asking `fallback` for the text of each placeholder
+    /// the context leaves unresolved as it is reached. Its answer is written like a string value;
+    /// `None` writes the placeholder back unchanged.
+    pub fn render_with_fallback(
+        &self,
+        context: &Map<String, Value>,
+        json: bool,
+        fallback: &mut dyn FnMut(&str) -> Option<String>,
+    ) -> String {
+        let root = Value::Object(context.clone());
+        let scope = Scope {
+            value: &root,
+            parent: None,
+            index: None,
+            last: false,
+        };
+        let mut output = Output {
+            text: String::new(),
+            json,
+            in_string: false,
+            escaped: false,
+        };
+        render_nodes(&self.nodes, &scope, &mut output, fallback);
+        output.text
+    }
+
+    /// Every path the template reads, in source order, including `#if` and `#each` expressions.
+    pub fn references(&self) -> Vec<TemplateReference> {
+        let mut references = Vec::new();
+        collect_references(&self.nodes, false, &mut references);
+        references
+    }
+}
+
+/// Rendered text plus, in JSON mode, whether the write position is inside a string literal.
+struct Output {
+    text: String,
+    json: bool,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Output {
+    fn push_text(&mut self, text: &str) {
+        if self.json {
+            for character in text.chars() {
+                if self.escaped {
+                    self.escaped = false;
+                } else if self.in_string && character == '\\' {
+                    self.escaped = true;
+                } else if character == '"' {
+                    self.in_string = !self.in_string;
+                }
+            }
+        }
+        self.text.push_str(text);
+    }
+
+    fn push_value(&mut self, value: &Value) {
+        if !self.json {
+            self.push_text(&to_output(value));
+        } else if self.in_string {
+            let encoded = serde_json::to_string(&to_output(value)).unwrap_or_default();
+            self.text.push_str(&encoded[1..encoded.len() - 1]);
+        } else {
+            match value {
+                // A value its author already wrapped in quotes is a string literal as it is.
+                Value::String(text) if is_string_literal(text) => self.push_text(text),
+                other => self.push_text(&other.to_string()),
+            }
+        }
+    }
+
+    /// Generator output is written as it is outside strings, so `{{$int}}` stays a number.
+    fn push_generated(&mut self, text: String) {
+        if self.json && self.in_string {
+            self.push_value(&Value::String(text));
+        } else {
+            self.push_text(&text);
+        }
+    }
+}
+
+fn current_nodes<'a>(root: &'a mut Vec<Node>, stack: &'a mut [OpenBlock]) -> &'a mut Vec<Node> {
+    match stack.last_mut() {
+        Some(open) => open.else_nodes.as_mut().unwrap_or(&mut open.nodes),
+        None => root,
+    }
+}
+
+fn push_text(root: &mut Vec<Node>, stack: &mut [OpenBlock], text: &str) {
+    if !text.is_empty() {
+        current_nodes(root, stack).push(Node::Text(text.to_string()));
+    }
+}
+
+fn error_at(source: &str, offset: usize, message: String) -> TemplateError {
+    let (line, column) = line_column(source, offset);
+    TemplateError {
+        message,
+        line,
+        column,
+    }
+}
+
+/// 1-based line and column (in characters) of a byte offset.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+fn parse_expression(text: &str, position: (usize, usize)) -> Result<Expression, String> {
+    let mut parts = split_filters(text).into_iter();
+    let path = parts.next().unwrap_or_default().trim().to_string();
+    let mut filters = Vec::new();
+    for part in parts {
+        let part = part.trim();
+        let (name, argument) = part.split_once(char::is_whitespace).unwrap_or((part, ""));
+        if !FILTERS.contains(&name) {
+            return Err(format!("Unknown filter {}", name));
+        }
+        let argument = argument.trim();
+        let argument = (!argument.is_empty()).then(|| unquote(argument).to_string());
+        if name == "default" && argument.is_none() {
+            return Err("The default filter needs a value".to_string());
+        }
+        filters.push(Filter {
+            name: name.to_string(),
+            argument,
+        });
+    }
+    Ok(Expression {
+        path,
+        filters,
+        position,
+    })
+}
+
+/// Splits on `|` outside of quoted filter arguments.
+fn split_filters(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, character) in text.char_indices() {
+        match (quote, character) {
+            (Some(open), _) if character == open => quote = None,
+            (None, '"' | '\'') => quote = Some(character),
+            (None, '|') => {
+                parts.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn unquote(text: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = text
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    text
+}
+
+/// One level of lookup: the root context, or the current item of an enclosing `#each`.
+struct Scope<'a> {
+    value: &'a Value,
+    parent: Option<&'a Scope<'a>>,
+    index: Option<usize>,
+    last: bool,
+}
+
+impl<'a> Scope<'a> {
+    fn ancestors(&'a self) -> impl Iterator<Item = &'a Scope<'a>> {
+        std::iter::successors(Some(self), |scope| scope.parent)
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    scope: &Scope,
+    output: &mut Output,
+    fallback: &mut dyn FnMut(&str) -> Option<String>,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_text(text),
+            Node::Expr(placeholder) => {
+                if let Some(value) = lookup(placeholder, scope) {
+                    output.push_value(&value);
+                } else if let Some(text) = fallback(placeholder.inner.trim()) {
+                    output.push_generated(text);
+                } else {
+                    output.push_text(&placeholder.raw);
+                }
+            }
+            Node::If {
+                condition,
+                negate,
+                then_branch,
+                else_branch,
+            } => {
+                let truthy = evaluate(condition, scope).is_some_and(|value| is_truthy(&value));
+                let branch = if truthy != *negate {
+                    then_branch
+                } else {
+                    else_branch
+                };
+                render_nodes(branch, scope, output, fallback);
+            }
+            Node::Each {
+                items,
+                body,
+                else_branch,
+            } => {
+                let values = match evaluate(items, scope) {
+                    Some(Value::Array(values)) if !values.is_empty() => values,
+                    _ => {
+                        render_nodes(else_branch, scope, output, fallback);
+                        continue;
+                    }
+                };
+                for (index, value) in values.iter().enumerate() {
+                    let item = Scope {
+                        value,
+                        parent: Some(scope),
+                        index: Some(index),
+                        last: index + 1 == values.len(),
+                    };
+                    render_nodes(body, &item, output, fallback);
+                }
+            }
+        }
+    }
+}
+
+/// Value of a placeholder. Inside a loop the innermost scope wins, and the raw root key is
+/// only a fallback.
+fn lookup(placeholder: &Placeholder, scope: &Scope) -> Option<Value> {
+    if scope.parent.is_none() {
+        lookup_raw(&placeholder.inner, scope).or_else(|| evaluate(&placeholder.expression, scope))
+    } else {
+        evaluate(&placeholder.expression, scope).or_else(|| lookup_raw(&placeholder.inner, scope))
+    }
+}
+
+/// Exact-key lookup of the untrimmed tag text in the root scope, so keys that happen to contain
+/// spaces, dots or `|` keep working as they did before the template language existed.
+fn lookup_raw(inner: &str, scope: &Scope) -> Option<Value> {
+    match scope.ancestors().last()?.value {
+        Value::Object(map) => map.get(inner).cloned(),
+        _ => None,
+    }
+}
+
+/// Value of an expression, or `None` when its path is missing and no `default` filter
+/// supplies a fallback.
+fn evaluate(expression: &Expression, scope: &Scope) -> Option<Value> {
+    let value = resolve(&expression.path, scope)
+        .cloned()
+        .or_else(|| loop_variable(&expression.path, scope));
+    let has_default = expression
+        .filters
+        .iter()
+        .any(|filter| filter.name == "default");
+    if value.is_none() && !has_default {
+        return None;
+    }
+    Some(apply_filters(value, &expression.filters))
+}
+
+/// Resolves `this`, `this.<path>`, or a key or dotted/bracketed path against the innermost
+/// scope that has it.
+fn resolve<'a>(path: &str, scope: &'a Scope<'a>) -> Option<&'a Value> {
+    match path {
+        "" => return None,
+        "this" | "." => return Some(scope.value),
+        _ => {}
+    }
+    if let Some(rest) = path.strip_prefix("this.") {
+        return lookup_json_path(scope.value, rest);
+    }
+    scope.ancestors().find_map(|scope| match scope.value {
+        Value::Object(map) => map
+            .get(path)
+            .or_else(|| lookup_json_path(scope.value, path)),
+        _ => None,
+    })
+}
+
+/// `@index`, `@first` and `@last` of the innermost `#each`.
+fn loop_variable(path: &str, scope: &Scope) -> Option<Value> {
+    let scope = scope.ancestors().find(|scope| scope.index.is_some())?;
+    let index = scope.index?;
+    match path {
+        "@index" => Some(Value::from(index)),
+        "@first" => Some(Value::Bool(index == 0)),
+        "@last" => Some(Value::Bool(scope.last)),
+        _ => None,
+    }
+}
+
+fn apply_filters(value: Option<Value>, filters: &[Filter]) -> Value {
+    let mut current = value.unwrap_or(Value::Null);
+    for filter in filters {
+        current = match filter.name.as_str() {
+            "upper" => Value::String(to_output(&current).to_uppercase()),
+            "lower" => Value::String(to_output(&current).to_lowercase()),
+            "json" => Value::String(serde_json::to_string(&current).unwrap_or_default()),
+            "length" => Value::from(match &current {
+                Value::Array(items) => items.len(),
+                Value::Object(map) => map.len(),
+                Value::String(text) => text.chars().count(),
+                Value::Null => 0,
+                other => other.to_string().chars().count(),
+            }),
+            "default" if !is_truthy(&current) => {
+                Value::String(filter.argument.clone().unwrap_or_default())
+            }
+            _ => current,
+        };
+    }
+    current
+}
+
+/// Falsy values: missing, `null`, `false`, `0`, empty strings, arrays and objects, and the
+/// strings `"false"` and `"0"` (string-typed flags are common in template values).
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        Value::String(text) => !matches!(text.trim(), "" | "false" | "0"),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Text written for a value: strings as-is, everything else as JSON.
+fn is_string_literal(text: &str) -> bool {
+    matches!(
+        serde_json::from_str::<Value>(text.trim()),
+        Ok(Value::String(_))
+    )
+}
+
+fn to_output(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn collect_references(nodes: &[Node], in_loop: bool, references: &mut Vec<TemplateReference>) {
+    let reference = |expression: &Expression| TemplateReference {
+        path: expression.path.clone(),
+        line: expression.position.0,
+        column: expression.position.1,
+        in_loop,
+    };
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Expr(placeholder) => references.push(reference(&placeholder.expression)),
+            Node::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                references.push(reference(condition));
+                collect_references(then_branch, in_loop, references);
+                collect_references(else_branch, in_loop, references);
+            }
+            Node::Each {
+                items,
+                body,
+                else_branch,
+            } => {
+                references.push(reference(items));
+                collect_references(body, true, references);
+                collect_references(else_branch, in_loop, references);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(source: &str, context: Value) -> String {
+        let Value::Object(context) = context else {
+            panic!("context must be an object");
+        };
+        Template::parse(source).unwrap().render(&context)
+    }
+
+    fn parse_error(source: &str) -> TemplateError {
+        Template::parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn if_and_unless_pick_a_branch_by_truthiness() {
+        let source = "{{#if user.admin}}admin{{else}}guest{{/if}}";
+        assert_eq!(render(source, json!({"user": {"admin": true}})), "admin");
+        assert_eq!(render(source, json!({"user": {"admin": "false"}})), "guest");
+        assert_eq!(render(source, json!({})), "guest");
+        let source = "{{#unless items}}empty{{/unless}}";
+        assert_eq!(render(source, json!({"items": []})), "empty");
+        assert_eq!(render(source, json!({"items": [0]})), "");
+    }
+
+    #[test]
+    fn each_exposes_the_item_and_loop_variables() {
+        let source = "{{#each users}}{{@index}}:{{name}}{{#unless @last}}, {{/unless}}{{/each}}";
+        let context = json!({"users": [{"name": "Ada"}, {"name": "Alan"}, {"name": "Grace"}]});
+        assert_eq!(render(source, context), "0:Ada, 1:Alan, 2:Grace");
+
+        let source = "{{#each tags}}[{{this}}{{#if @first}}*{{/if}}]{{else}}none{{/each}}";
+        assert_eq!(render(source, json!({"tags": ["a", "b"]})), "[a*][b]");
+        assert_eq!(render(source, json!({"tags": []})), "none");
+        assert_eq!(render(source, json!({"tags": "a"})), "none");
+    }
+
+    #[test]
+    fn nested_loops_fall_back_to_outer_scopes() {
+        let source =
+            "{{#each groups}}{{name}}({{#each members}}{{this}}@{{name}}{{/each}}){{/each}}";
+        let context =
+            json!({"groups": [{"name": "x", "members": [1, 2]}, {"name": "y", "members": []}]});
+        assert_eq!(render(source, context), "x(1@x2@x)y()");
+        let source = "{{name}}: {{#each users}}{{name}} {{/each}}";
+        let context = json!({"name": "Team", "users": [{"name": "Ada"}, {"id": 2}]});
+        assert_eq!(render(source, context), "Team: Ada Team ");
+        let source = "{{#each items}}{{prefix}}{{this.id}}{{/each}}";
+        assert_eq!(
+            render(
+                source,
+                json!({"prefix": "#", "items": [{"id": 1}, {"id": 2}]})
+            ),
+            "#1#2"
+        );
+    }
+
+    #[test]
+    fn filters_chain_and_default_fills_missing_values() {
+        let context = json!({"name": "Ada", "tags": ["a", "b"], "empty": ""});
+        assert_eq!(render("{{name | upper}}", context.clone()), "ADA");
+        assert_eq!(render("{{tags | length}}", context.clone()), "2");
+        assert_eq!(render("{{tags | json}}", context.clone()), r#"["a","b"]"#);
+        assert_eq!(
+            render("{{missing | default \"n/a\"}}", context.clone()),
+            "n/a"
+        );
+        assert_eq!(
+            render("{{empty | default none | upper}}", context.clone()),
+            "NONE"
+        );
+        assert_eq!(render("{{name | default none}}", context), "Ada");
+    }
+
+    #[test]
+    fn unresolved_placeholders_are_kept_unless_the_fallback_answers() {
+        assert_eq!(
+            render("id={{ id }} and {{a.b}}", json!({})),
+            "id={{ id }} and {{a.b}}"
+        );
+        let template = Template::parse("{{known}} {{$uuid}} {{other}}").unwrap();
+        let context = Map::from_iter([("known".to_string(), json!("k"))]);
+        let rendered = template.render_with_fallback(&context, false, &mut |tag| {
+            (tag == "$uuid").then(|| "generated".to_string())
+        });
+        assert_eq!(rendered, "k generated {{other}}");
+    }
+
+    #[test]
+    fn keys_with_spaces_and_dots_still_resolve_exactly() {
+        let context = json!({"first name": "Ada", "a.b": "dotted", "a": {"b": "nested"}});
+        assert_eq!(render("{{first name}}", context.clone()), "Ada");
+        assert_eq!(render("{{a.b}}", context), "dotted");
+    }
+
+    #[test]
+    fn malformed_blocks_are_reported_with_their_position() {
+        let error = parse_error("ok\n  {{#if a}}never closed");
+        assert_eq!((error.line, error.column), (2, 3));
+        assert!(error.message.contains("never closed"));
+        assert!(parse_error("{{#if a}}x{{/each}}")
+            .message
+            .contains("Expected {{/if}}"));
+        assert!(parse_error("{{else}}")
+            .message
+            .contains("outside of a block"));
+        assert!(parse_error("{{#with a}}{{/with}}")
+            .message
+            .contains("Unknown block tag"));
+        assert!(parse_error("{{#each}}{{/each}}")
+            .message
+            .contains("needs an expression"));
+    }
+
+    fn render_json(source: &str, context: Value) -> String {
+        let Value::Object(context) = context else {
+            panic!("context must be an object");
+        };
+        Template::parse(source).unwrap().render_json(&context)
+    }
+
+    #[test]
+    fn json_strings_are_escaped_inside_string_literals() {
+        let context = json!({"text": "He said \"hi\"\n\\o/", "count": 5, "obj": {"a": "b"}});
+        assert_eq!(
+            render_json(r#"{"msg": "{{text}}"}"#, context.clone()),
+            r#"{"msg": "He said \"hi\"\n\\o/"}"#
+        );
+        assert_eq!(
+            render_json(
+                r#"{"count": "{{count}}", "obj": "{{obj}}"}"#,
+                context.clone()
+            ),
+            r#"{"count": "5", "obj": "{\"a\":\"b\"}"}"#
+        );
+        assert_eq!(
+            render_json(
+                r#"{"quoted": "say \"{{text | upper}}\""}"#,
+                json!({"text": "a\"b"})
+            ),
+            r#"{"quoted": "say \"A\"B\""}"#
+        );
+        assert_eq!(render(r#""{{text}}""#, context), "\"He said \"hi\"\n\\o/\"");
+    }
+
+    #[test]
+    fn typed_values_are_json_literals_outside_strings() {
+        let context = json!({
+            "count": 5, "ratio": 0.5, "flag": false, "obj": {"a": [1]}, "none": null, "name": "Ada"
+        });
+        assert_eq!(
+            render_json(
+                r#"{"count": {{count}}, "ratio": {{ratio}}, "flag": {{flag}}, "obj": {{obj}}, "none": {{none}}}"#,
+                context.clone()
+            ),
+            r#"{"count": 5, "ratio": 0.5, "flag": false, "obj": {"a":[1]}, "none": null}"#
+        );
+        assert_eq!(
+            render_json(r#"{"name": {{name}}}"#, context.clone()),
+            r#"{"name": "Ada"}"#
+        );
+        assert_eq!(
+            render_json(r#"{"name": {{name}}}"#, json!({"name": "\"Ada\""})),
+            r#"{"name": "Ada"}"#
+        );
+        assert_eq!(
+            render_json(r#"{"name": {{name}}}"#, json!({"name": "a\"b"})),
+            r#"{"name": "a\"b"}"#
+        );
+        assert_eq!(
+            render_json(
+                r#"[{{#each items}}"{{this}}"{{#unless @last}},{{/unless}}{{/each}}]"#,
+                json!({"items": ["a\"", 2]})
+            ),
+            r#"["a\"","2"]"#
+        );
+    }
+
+    #[test]
+    fn references_list_paths_and_mark_loop_bodies() {
+        let template = Template::parse("{{#each items}}{{name}}{{/each}}\n{{total}}").unwrap();
+        let references: Vec<(String, usize, bool)> = template
+            .references()
+            .into_iter()
+            .map(|reference| (reference.path, reference.line, reference.in_loop))
+            .collect();
+        assert_eq!(
+            references,
+            [
+                ("items".to_string(), 1, false),
+                ("name".to_string(), 1, true),
+                ("total".to_string(), 2, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Builders shared by the unit tests.
+
+use crate::matching::IncomingRequest;
+use crate::routing::{CompiledModifier, RoutingCache, RoutingTable};
+use crate::state::{AppState, LogStore};
+use crate::types::{
+    Block, BlockMatch, NetworkConditions, Profile, RecordConfig, ResponseModifier, Store,
+};
+use axum::body::to_bytes;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use axum::response::Response;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Block `id` answering `method` requests on `path`.
+pub fn block(id: &str, method: &str, path: &str) -> Block {
+    Block {
+        id: id.to_string(),
+        name: id.to_string(),
+        method: method.to_string(),
+        path: path.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Store holding a single profile, `default`, with `blocks` active.
+pub fn store(blocks: Vec<Block>) -> Store {
+    Store {
+        profiles: vec![Profile {
+            name: "default".to_string(),
+            active_blocks: blocks,
+            ..Default::default()
+        }],
+        active_profile: Some("default".to_string()),
+    }
+}
+
+/// Routing table compiled from `store(blocks)`.
+pub fn routing_table(blocks: Vec<Block>) -> RoutingTable {
+    RoutingTable::build(store(blocks))
+}
+
+/// `modifiers` as compiled into the `default` profile of a routing table.
+pub fn compiled_modifiers(modifiers: Vec<ResponseModifier>) -> Vec<CompiledModifier> {
+    let mut store = store(Vec::new());
+    store.profiles[0].modifiers = modifiers;
+    let table = RoutingTable::build(store);
+    table.profile("default").unwrap().modifiers.clone()
+}
+
+/// Match of `block` in the `default` profile with no extracted params.
+pub fn block_match(block: Block) -> BlockMatch {
+    BlockMatch {
+        profile: store(Vec::new()).profiles.remove(0),
+        block,
+        extracted_params: HashMap::new(),
+        shadowed: Vec::new(),
+        variant: None,
+        conditional_variant: false,
+        state: Map::new(),
+        delay_ms: None,
+    }
+}
+
+/// Owned parts of an incoming request; `incoming` borrows them for matching.
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub host: Option<String>,
+    pub headers: HeaderMap,
+    pub query: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Request for `path` without host, headers, query or body.
+pub fn request(method: Method, path: &str) -> Request {
+    Request {
+        method,
+        path: path.to_string(),
+        host: None,
+        headers: HeaderMap::new(),
+        query: HashMap::new(),
+        body: Vec::new(),
+    }
+}
+
+impl Request {
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        self
+    }
+
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = body.as_bytes().to_vec();
+        self
+    }
+
+    pub fn incoming(&self) -> IncomingRequest<'_> {
+        IncomingRequest {
+            method: &self.method,
+            path: &self.path,
+            host: self.host.as_deref(),
+            headers: &self.headers,
+            query: &self.query,
+            body: &self.body,
+        }
+    }
+}
+
+/// Fresh state whose `profiles.json` lives in its own temporary directory.
+pub fn app_state() -> AppState {
+    static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+    let data_dir = std::env::temp_dir().join(format!(
+        "mapy-test-{}-{}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    AppState {
+        data_file: Arc::new(data_dir.join("profiles.json")),
+        data_dir: Arc::new(data_dir),
+        write_lock: Arc::new(Mutex::new(())),
+        log_store: Arc::new(Mutex::new(LogStore::default())),
+        active_profile: Arc::new(Mutex::new(None)),
+        http_client: reqwest::Client::new(),
+        ca_cert_pem: Arc::new(String::new()),
+        recording: Arc::new(AtomicBool::new(false)),
+        routing: Arc::new(Mutex::new(RoutingCache::default())),
+        block_hits: Arc::new(Mutex::new(HashMap::new())),
+        mock_state: Arc::new(Mutex::new(HashMap::new())),
+        resources: Arc::new(Mutex::new(HashMap::new())),
+        network: Arc::new(Mutex::new(NetworkConditions::default())),
+        record: Arc::new(Mutex::new(RecordConfig::default())),
+        store_lock: Arc::new(Mutex::new(())),
+    }
+}
+
+/// JSON body of a handler response.
+pub async fn json_body(response: Response) -> Value {
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
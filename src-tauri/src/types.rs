@@ -1,11 +1,27 @@
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 
 fn default_value_type() -> String {
     "string".to_string()
 }
 
+fn default_condition_operator() -> String {
+    "equals".to_string()
+}
+
+/// Accepts a status written either as a number (`401`) or as a template string (`"{{status}}"`).
+fn deserialize_status<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(value)) => Some(value),
+        Some(Value::Number(value)) => Some(value.to_string()),
+        _ => None,
+    })
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Store {
@@ -35,6 +51,230 @@ pub struct Profile {
     pub categories: Vec<String>,
     #[serde(default)]
     pub libraries: Vec<Library>,
+    /// Seed for `{{$...}}` generators in blocks that do not set their own.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Template values shared by every block in the profile; block values take precedence.
+    #[serde(default)]
+    pub variables: Vec<TemplateValue>,
+    /// Sub-profile whose params are exposed to block templates.
+    #[serde(default)]
+    pub active_sub_profile: Option<String>,
+    /// In-memory collections answered with list, get, create, update and delete routes.
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    /// Rewrites applied to real upstream responses of passthrough traffic.
+    #[serde(default)]
+    pub modifiers: Vec<ResponseModifier>,
+    /// Rewrites applied to passthrough requests before they are forwarded.
+    #[serde(default)]
+    pub rewrites: Vec<RequestRewrite>,
+}
+
+/// A mocked REST collection: `GET|POST <path>` and `GET|PUT|PATCH|DELETE <path>/<id>`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub id: String,
+    /// Collection path, e.g. `/api/todos`.
+    pub path: String,
+    /// Item field holding the id; defaults to `id`.
+    #[serde(default = "default_id_field")]
+    pub id_field: String,
+    /// Items the collection starts with and returns to when reset.
+    #[serde(default)]
+    pub seed: Vec<Value>,
+}
+
+fn default_id_field() -> String {
+    "id".to_string()
+}
+
+/// Edits the upstream response of passthrough requests in its scope. Body rules run in order
+/// (merge patch, JSON patch, replacements), then the status and header rules.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseModifier {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Method to rewrite; empty or `*` for any.
+    #[serde(default)]
+    pub method: String,
+    /// Path template as in blocks; empty for any path.
+    #[serde(default)]
+    pub path: String,
+    /// Host pattern as in blocks; empty for any host.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// RFC 7396 merge patch for JSON bodies.
+    #[serde(default)]
+    pub merge_patch: Option<Value>,
+    /// RFC 6902 operations for JSON bodies, applied all or nothing.
+    #[serde(default)]
+    pub json_patch: Vec<JsonPatchOperation>,
+    /// Regex find and replace on text bodies; `$1` refers to capture groups.
+    #[serde(default)]
+    pub replacements: Vec<TextReplacement>,
+    #[serde(default)]
+    pub set_headers: HashMap<String, String>,
+    #[serde(default)]
+    pub remove_headers: Vec<String>,
+    #[serde(default)]
+    pub status: Option<u16>,
+}
+
+/// Edits a passthrough request in its scope before it is forwarded, in the order: query, headers,
+/// body, redirect. Rules are matched against the request as the client sent it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestRewrite {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Method to rewrite; empty or `*` for any.
+    #[serde(default)]
+    pub method: String,
+    /// Path template as in blocks; empty for any path. Its params can be used in `redirect.path`.
+    #[serde(default)]
+    pub path: String,
+    /// Host pattern as in blocks; empty for any host.
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub set_headers: HashMap<String, String>,
+    #[serde(default)]
+    pub remove_headers: Vec<String>,
+    /// Query params to add, replacing any with the same name.
+    #[serde(default)]
+    pub set_query: HashMap<String, String>,
+    #[serde(default)]
+    pub remove_query: Vec<String>,
+    /// RFC 7396 merge patch for JSON bodies.
+    #[serde(default)]
+    pub merge_patch: Option<Value>,
+    /// RFC 6902 operations for JSON bodies, applied all or nothing.
+    #[serde(default)]
+    pub json_patch: Vec<JsonPatchOperation>,
+    #[serde(default)]
+    pub redirect: Option<RedirectTarget>,
+}
+
+/// Where a rewritten request goes instead; unset parts keep their original value.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectTarget {
+    /// `http` or `https`.
+    #[serde(default)]
+    pub scheme: Option<String>,
+    /// Hostname, optionally with a port, e.g. `localhost:8080`.
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Replacement path; `{name}` is filled from the rule's path params.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// One RFC 6902 operation: `add`, `remove`, `replace`, `move`, `copy` or `test`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonPatchOperation {
+    pub op: String,
+    pub path: String,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub value: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TextReplacement {
+    pub find: String,
+    #[serde(default)]
+    pub replace: String,
+}
+
+/// Which passthrough traffic is snapshotted into blocks, and where they go.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Library of the active profile that receives the blocks: `local` or a remote library id.
+    #[serde(default = "default_record_library")]
+    pub library_id: String,
+    /// Host patterns to record; empty records every host.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Path prefixes to record; empty records every path.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Replace numeric and UUID path segments with `{id:int}` / `{id:uuid}` params.
+    #[serde(default)]
+    pub parameterize: bool,
+    /// Refresh a block already recorded for the method and path instead of keeping it.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        RecordConfig {
+            enabled: false,
+            library_id: default_record_library(),
+            hosts: Vec::new(),
+            paths: Vec::new(),
+            parameterize: false,
+            overwrite: false,
+        }
+    }
+}
+
+fn default_record_library() -> String {
+    "local".to_string()
+}
+
+/// Simulated link speed for responses leaving the proxy.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkProfile {
+    /// `3g`, `edge`, `lossyWifi` or `custom`; the fields below override the preset.
+    #[serde(default)]
+    pub preset: String,
+    #[serde(default)]
+    pub download_kbps: Option<u64>,
+    /// Added before the first byte of each response.
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+    /// Chance (0 to 1) that a chunk is "lost" and stalls for a retransmit.
+    #[serde(default)]
+    pub loss: Option<f64>,
+}
+
+/// Network profiles in force: per-host rules first, then the global one.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkConditions {
+    #[serde(default)]
+    pub global: Option<NetworkProfile>,
+    #[serde(default)]
+    pub hosts: Vec<HostNetworkProfile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HostNetworkProfile {
+    /// Host pattern: exact, wildcard (`*.example.com`) or `regex:` prefixed.
+    pub host: String,
+    #[serde(flatten)]
+    pub profile: NetworkProfile,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -58,11 +298,26 @@ pub struct RequestConfig {
     #[serde(default)]
     pub body: HashMap<String, String>,
     #[serde(default)]
+    pub body_matchers: Vec<BodyMatcher>,
+    #[serde(default)]
     pub params: HashMap<String, String>,
     #[serde(default)]
     pub response: Option<ResponseConfig>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyMatcher {
+    /// `json` (exact JSON equality), `jsonPath` (value at `path` equals `value`),
+    /// `contains` (body includes `value` as a JSON subset, or as text) or `regex` (raw body).
+    #[serde(rename = "type")]
+    pub matcher_type: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Library {
@@ -73,6 +328,9 @@ pub struct Library {
     /// For remote (folder) libraries, absolute path to the folder. Legacy: deserialize from "clonePath".
     #[serde(default, alias = "clonePath")]
     pub folder_path: Option<String>,
+    /// Template values shared by the library's blocks; profile variables are the next fallback.
+    #[serde(default)]
+    pub variables: Vec<TemplateValue>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -83,11 +341,24 @@ pub struct Block {
     pub method: String,
     #[serde(default)]
     pub path: String,
+    /// Optional host pattern: exact (`api.example.com`), wildcard (`*.example.com`) or
+    /// regex (`regex:^api\d+\.example\.com$`). Blocks without one match every host.
+    #[serde(default)]
+    pub host: Option<String>,
     pub description: String,
+    /// Response status; may contain `{{placeholders}}`. Defaults to 200.
+    #[serde(default, deserialize_with = "deserialize_status")]
+    pub status: Option<String>,
     pub response_template: String,
     #[serde(default)]
     pub response_headers: HashMap<String, String>,
     #[serde(default)]
+    pub body_matchers: Vec<BodyMatcher>,
+    #[serde(default)]
+    pub header_conditions: Vec<FieldCondition>,
+    #[serde(default)]
+    pub query_conditions: Vec<FieldCondition>,
+    #[serde(default)]
     pub template_values: Vec<TemplateValue>,
     #[serde(default)]
     pub template_variants: Vec<TemplateVariant>,
@@ -97,6 +368,76 @@ pub struct Block {
     pub category: String,
     #[serde(default)]
     pub source_library_id: Option<String>,
+    /// Explicit match priority; higher wins before any specificity ranking applies.
+    #[serde(default)]
+    pub priority: Option<i32>,
+    /// Seed for `{{$...}}` generators; overrides the profile seed.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// How variants are picked per request: `static` (the active variant, default),
+    /// `sequence`, `roundRobin` or `weighted`.
+    #[serde(default)]
+    pub selection_mode: String,
+    /// Changes to the profile's mock state made each time the block answers a request,
+    /// applied in order before the response is rendered.
+    #[serde(default)]
+    pub effects: Vec<StateEffect>,
+    /// Latency added before the response is sent; a variant's delay takes precedence.
+    #[serde(default)]
+    pub delay: Option<DelayConfig>,
+    /// Network failure served instead of a well-formed response: `closeConnection`, `hang`,
+    /// `resetAfterHeaders` or `truncatedBody`.
+    #[serde(default)]
+    pub fault: Option<String>,
+}
+
+/// Simulated response latency.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DelayConfig {
+    /// `fixed` (default), `uniform` or `normal`.
+    #[serde(default)]
+    pub distribution: String,
+    /// The delay for `fixed`, the mean for `normal`.
+    #[serde(default)]
+    pub ms: u64,
+    /// Range for `uniform`; bounds for `normal` when `max_ms` is set.
+    #[serde(default)]
+    pub min_ms: u64,
+    #[serde(default)]
+    pub max_ms: u64,
+    /// Standard deviation for `normal`.
+    #[serde(default)]
+    pub std_dev_ms: u64,
+}
+
+/// A write to the profile's mock state made when a block matches.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StateEffect {
+    /// `set`, `append`, `increment`, `remove`, `removeItem` or `clear`.
+    pub action: String,
+    /// State key; `append`, `removeItem` and `clear` treat it as a collection.
+    pub key: String,
+    /// Template for the value, read as JSON when it parses. Empty means the request body for
+    /// `set` and `append`, and 1 for `increment`.
+    #[serde(default)]
+    pub value: String,
+    /// For `removeItem`: JSON path in each item compared with the rendered value.
+    #[serde(default)]
+    pub field: Option<String>,
+}
+
+/// A condition on a named header or query parameter.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldCondition {
+    pub name: String,
+    /// `equals`, `regex`, `present` or `absent`.
+    #[serde(default = "default_condition_operator")]
+    pub operator: String,
+    #[serde(default)]
+    pub value: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -116,6 +457,32 @@ pub struct TemplateVariant {
     pub name: String,
     #[serde(default)]
     pub values: Vec<TemplateValue>,
+    /// Overrides the block status while this variant is active.
+    #[serde(default, deserialize_with = "deserialize_status")]
+    pub status: Option<String>,
+    /// When set, the variant is picked for any request meeting all of these conditions,
+    /// regardless of the block's active variant.
+    #[serde(default)]
+    pub conditions: Vec<VariantCondition>,
+    /// Relative weight in the `weighted` selection mode; defaults to 1.
+    #[serde(default)]
+    pub weight: Option<f64>,
+    /// Overrides the block delay while this variant is active.
+    #[serde(default)]
+    pub delay: Option<DelayConfig>,
+    /// Overrides the block fault while this variant is active; an empty string disables it.
+    #[serde(default)]
+    pub fault: Option<String>,
+}
+
+/// A request condition that selects a template variant.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantCondition {
+    /// `query`, `header`, `path` (a path param) or `body` (a JSON path or form field).
+    pub source: String,
+    #[serde(flatten)]
+    pub condition: FieldCondition,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -153,6 +520,114 @@ pub struct BlockMatch {
     pub profile: Profile,
     pub block: Block,
     pub extracted_params: HashMap<String, String>,
+    /// Other active blocks that also matched but ranked lower.
+    pub shadowed: Vec<BlockRef>,
+    /// Name of the variant the response is rendered with, if the block has variants.
+    pub variant: Option<String>,
+    /// Whether that variant was picked by its conditions, which a selection mode never overrides.
+    pub conditional_variant: bool,
+    /// The profile's mock state once the block's effects ran; templates read it as `state`.
+    pub state: Map<String, Value>,
+    /// Latency applied before responding, once sampled from the block or variant delay.
+    pub delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockRef {
+    pub id: String,
+    pub name: String,
+}
+
+/// Findings from rendering a block with its active variant outside of a real request.
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockValidation {
+    pub block_id: String,
+    pub block_name: String,
+    pub variant: Option<String>,
+    /// Template syntax errors in the body, status or header templates.
+    pub template_errors: Vec<TemplateDiagnostic>,
+    /// Set when the body template looks like JSON but the rendered body does not parse.
+    pub json_error: Option<TemplateDiagnostic>,
+    pub missing_placeholders: Vec<TemplateDiagnostic>,
+    /// Template value keys that no template reads.
+    pub unused_values: Vec<String>,
+    /// Template values that do not parse as their declared type.
+    pub invalid_values: Vec<String>,
+    /// Headers that would be dropped from the response.
+    pub invalid_headers: Vec<HeaderDiagnostic>,
+    pub invalid_status: Option<String>,
+    pub rendered: String,
+}
+
+/// A problem at a 1-based position in one of a block's templates. `source` is `body`,
+/// `status` or `header <name>`; for `jsonError` the position is in the rendered body.
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateDiagnostic {
+    pub source: String,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HeaderDiagnostic {
+    pub name: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// Response of `update_blocks`: the saved blocks plus validation findings for blocks that
+/// have any.
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateBlocksResponse {
+    #[serde(flatten)]
+    pub blocks: BlocksPayload,
+    pub warnings: Vec<BlockValidation>,
+}
+
+/// Result of running a request through the matchers with every decision recorded.
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchExplanation {
+    pub profile: Option<String>,
+    /// `block`, `resource`, `request` or `passthrough`.
+    pub outcome: String,
+    pub summary: String,
+    pub matched_block: Option<BlockRef>,
+    /// Path of the resource collection that answers the request.
+    pub matched_resource: Option<String>,
+    pub matched_request: Option<String>,
+    pub blocks: Vec<CandidateExplanation>,
+    pub requests: Vec<CandidateExplanation>,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidateExplanation {
+    pub id: Option<String>,
+    pub name: String,
+    pub sub_profile: Option<String>,
+    pub accepted: bool,
+    pub reason: String,
+    pub params: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainMatchInput {
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Absolute URL or path with optional query string.
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -169,6 +644,11 @@ pub struct UpdateProfileInput {
     pub name: Option<String>,
     pub base_url: Option<String>,
     pub params: Option<Vec<String>>,
+    pub seed: Option<u64>,
+    pub variables: Option<Vec<TemplateValue>>,
+    pub resources: Option<Vec<Resource>>,
+    pub modifiers: Option<Vec<ResponseModifier>>,
+    pub rewrites: Option<Vec<RequestRewrite>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -192,6 +672,7 @@ pub struct CreateRequestInput {
     pub query_parameters: Option<HashMap<String, String>>,
     pub headers: Option<HashMap<String, String>>,
     pub body: Option<HashMap<String, String>>,
+    pub body_matchers: Option<Vec<BodyMatcher>>,
     pub params: Option<HashMap<String, String>>,
     pub response: Option<ResponseConfig>,
 }
@@ -208,6 +689,12 @@ pub struct SetActiveProfileInput {
     pub name: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SetActiveSubProfileInput {
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AddLibraryInput {
@@ -223,4 +710,6 @@ pub struct AddLibraryInput {
 pub struct UpdateLibraryInput {
     #[serde(default)]
     pub name: Option<String>,
+    #[serde(default)]
+    pub variables: Option<Vec<TemplateValue>>,
 }
@@ -0,0 +1,290 @@
+use crate::matching::{derive_block_path, IncomingRequest, PathMatcher};
+use crate::response::render_block;
+use crate::template::{
+    active_status_template, active_template_values, active_variant, looks_like_json,
+    shared_template_values, validate_block_values,
+};
+use crate::template_engine::Template;
+use crate::types::{
+    Block, BlockMatch, BlockValidation, HeaderDiagnostic, Profile, TemplateDiagnostic,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use serde_json::Map;
+use std::collections::{HashMap, HashSet};
+
+/// Path params have no request to come from during validation, so they render as this.
+const SAMPLE_PATH_PARAM: &str = "1";
+
+/// Renders `block` with its active variant against an empty request and reports everything
+/// that would make the real response differ from what the author wrote.
+pub fn validate_block(profile: &Profile, block: &Block) -> BlockValidation {
+    let path_params: Vec<String> = derive_block_path(block)
+        .map(|path| {
+            PathMatcher::compile(&path, &HashMap::new())
+                .param_names()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    let value_keys: Vec<&str> = active_template_values(block)
+        .iter()
+        .filter(|value| !value.key.is_empty())
+        .map(|value| value.key.as_str())
+        .collect();
+    let shared_values = shared_template_values(profile, block);
+    let known_keys: Vec<&str> = value_keys
+        .iter()
+        .copied()
+        .chain(shared_values.iter().map(|value| value.key.as_str()))
+        .chain(path_params.iter().map(String::as_str))
+        .collect();
+
+    let mut templates = vec![("body".to_string(), block.response_template.clone())];
+    if let Some(status) = active_status_template(block) {
+        templates.push(("status".to_string(), status.to_string()));
+    }
+    for (name, value) in &block.response_headers {
+        templates.push((format!("header {}", name.trim()), value.clone()));
+    }
+
+    let mut validation = BlockValidation {
+        block_id: block.id.clone(),
+        block_name: block.name.clone(),
+        variant: active_variant(block).map(|variant| variant.name.clone()),
+        invalid_values: validate_block_values(block),
+        ..Default::default()
+    };
+    let mut used_keys = HashSet::new();
+    for (source, template) in &templates {
+        let parsed = match Template::parse(template) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                validation.template_errors.push(TemplateDiagnostic {
+                    source: source.clone(),
+                    message: error.message,
+                    line: error.line,
+                    column: error.column,
+                });
+                continue;
+            }
+        };
+        for reference in parsed.references() {
+            let root = root_key(&reference.path, &known_keys);
+            used_keys.insert(root.to_string());
+            let known = known_keys.contains(&root) || is_builtin(&reference.path);
+            if !known && !reference.in_loop {
+                validation.missing_placeholders.push(TemplateDiagnostic {
+                    source: source.clone(),
+                    message: format!("No template value for {{{{{}}}}}", reference.path),
+                    line: reference.line,
+                    column: reference.column,
+                });
+            }
+        }
+    }
+    validation.unused_values = value_keys
+        .iter()
+        .filter(|key| !used_keys.contains(**key))
+        .map(|key| key.to_string())
+        .collect();
+
+    let block_match = BlockMatch {
+        profile: profile.clone(),
+        block: block.clone(),
+        extracted_params: path_params
+            .iter()
+            .map(|param| (param.clone(), SAMPLE_PATH_PARAM.to_string()))
+            .collect(),
+        shadowed: Vec::new(),
+        variant: active_variant(block).map(|variant| variant.name.clone()),
+        conditional_variant: false,
+        state: Map::new(),
+        delay_ms: None,
+    };
+    let method = Method::from_bytes(block.method.as_bytes()).unwrap_or(Method::GET);
+    let headers = HeaderMap::new();
+    let query = HashMap::new();
+    let request = IncomingRequest {
+        method: &method,
+        path: &block.path,
+        host: None,
+        headers: &headers,
+        query: &query,
+        body: &[],
+    };
+    let rendered = render_block(&block_match, &request);
+
+    if looks_like_json(&block.response_template) {
+        if let Err(error) = serde_json::from_str::<serde_json::Value>(&rendered.body) {
+            validation.json_error = Some(TemplateDiagnostic {
+                source: "body".to_string(),
+                message: error.to_string(),
+                line: error.line(),
+                column: error.column(),
+            });
+        }
+    }
+    if let Some(status) = rendered.status.filter(|status| !status.trim().is_empty()) {
+        let valid = status
+            .trim()
+            .parse::<u16>()
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .is_some();
+        if !valid {
+            validation.invalid_status = Some(format!(
+                "\"{}\" is not a valid status code; the response will use 200",
+                status
+            ));
+        }
+    }
+    for (name, value) in rendered.headers {
+        let reason = if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            "Invalid header name"
+        } else if HeaderValue::from_str(&value).is_err() {
+            "Invalid header value (control characters or non-visible ASCII)"
+        } else {
+            continue;
+        };
+        validation.invalid_headers.push(HeaderDiagnostic {
+            name,
+            value,
+            reason: reason.to_string(),
+        });
+    }
+    validation.rendered = rendered.body;
+    validation
+}
+
+/// Whether a validation found anything worth reporting as a warning.
+pub fn has_issues(validation: &BlockValidation) -> bool {
+    !validation.template_errors.is_empty()
+        || validation.json_error.is_some()
+        || !validation.missing_placeholders.is_empty()
+        || !validation.unused_values.is_empty()
+        || !validation.invalid_values.is_empty()
+        || !validation.invalid_headers.is_empty()
+        || validation.invalid_status.is_some()
+}
+
+/// The value key a path reads: the whole path when a key has that exact name, otherwise
+/// its first segment (`user` for `user.address[0]`).
+fn root_key<'a>(path: &'a str, value_keys: &[&str]) -> &'a str {
+    if value_keys.contains(&path) {
+        return path;
+    }
+    path.split(['.', '[']).next().unwrap_or(path)
+}
+
+/// Paths filled by something other than template values.
+fn is_builtin(path: &str) -> bool {
+    path.starts_with('$')
+        || path.starts_with('@')
+        || path == "this"
+        || path.starts_with("this.")
+        || path == "request"
+        || path.starts_with("request.")
+        || path == "state"
+        || path.starts_with("state.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block, store};
+    use crate::types::TemplateValue;
+
+    fn value(key: &str, value_type: &str, value: &str) -> TemplateValue {
+        TemplateValue {
+            id: key.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            value_type: value_type.to_string(),
+        }
+    }
+
+    fn validate(block: Block) -> BlockValidation {
+        validate_block(&store(Vec::new()).profiles[0], &block)
+    }
+
+    #[test]
+    fn clean_blocks_have_no_issues() {
+        let validation = validate(Block {
+            response_template: r#"{"id": {{id}}, "name": "{{name}}", "at": "{{$now}}"}"#
+                .to_string(),
+            template_values: vec![value("name", "string", "Ada")],
+            ..block("user", "GET", "/users/{id:int}")
+        });
+
+        assert!(!has_issues(&validation), "{:?}", validation);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&validation.rendered).unwrap()["id"],
+            1
+        );
+    }
+
+    #[test]
+    fn missing_and_unused_values_are_reported_with_positions() {
+        let validation = validate(Block {
+            response_template:
+                "{\n  \"a\": \"{{missing}}\",\n  \"b\": \"{{#each items}}{{x}}{{/each}}\"\n}"
+                    .to_string(),
+            template_values: vec![value("unused", "string", ""), value("items", "array", "")],
+            ..block("b", "GET", "/b")
+        });
+
+        let missing: Vec<(&str, usize, usize)> = validation
+            .missing_placeholders
+            .iter()
+            .map(|item| (item.message.as_str(), item.line, item.column))
+            .collect();
+        assert_eq!(missing, [("No template value for {{missing}}", 2, 9)]);
+        assert_eq!(validation.unused_values, ["unused"]);
+    }
+
+    #[test]
+    fn syntax_errors_and_bad_json_are_reported() {
+        let validation = validate(Block {
+            response_template: r#"{"a": {{#if x}}1}"#.to_string(),
+            ..block("b", "GET", "/b")
+        });
+        assert_eq!(validation.template_errors[0].source, "body");
+        assert!(validation.template_errors[0]
+            .message
+            .contains("never closed"));
+
+        let validation = validate(Block {
+            response_template: r#"{"a": 1,}"#.to_string(),
+            ..block("b", "GET", "/b")
+        });
+        assert!(validation.json_error.is_some());
+    }
+
+    #[test]
+    fn bad_statuses_headers_and_values_are_reported() {
+        let validation = validate(Block {
+            status: Some("{{code}}".to_string()),
+            response_headers: HashMap::from([
+                ("X-Ok".to_string(), "fine".to_string()),
+                ("X-Bad".to_string(), "a\nb".to_string()),
+            ]),
+            template_values: vec![value("code", "number", "abc")],
+            ..block("b", "GET", "/b")
+        });
+
+        assert_eq!(
+            validation.invalid_status.as_deref(),
+            Some("\"abc\" is not a valid status code; the response will use 200")
+        );
+        let headers: Vec<&str> = validation
+            .invalid_headers
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect();
+        assert_eq!(headers, ["X-Bad"]);
+        assert_eq!(
+            validation.invalid_values,
+            ["b: number value \"code\" \"abc\" is not a number"]
+        );
+    }
+}
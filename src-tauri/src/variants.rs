@@ -0,0 +1,185 @@
+use crate::generators::Generator;
+use crate::state::{AppState, ProfileKey};
+use crate::types::{BlockMatch, TemplateVariant};
+
+/// Key for a block's hit counter in `AppState::block_hits`.
+pub fn hit_key(profile: &str, block_id: &str) -> ProfileKey {
+    (profile.to_string(), block_id.to_string())
+}
+
+/// Counts the hit and, for blocks with a `selection_mode` other than `static`, switches the
+/// match to the variant that mode picks for this hit. A variant chosen by its conditions
+/// always wins; variants with conditions are left out of the rotation.
+pub async fn apply_selection_mode(state: &AppState, block_match: &mut BlockMatch) {
+    let hit = {
+        let mut hits = state.block_hits.lock().await;
+        let counter = hits
+            .entry(hit_key(&block_match.profile.name, &block_match.block.id))
+            .or_insert(0);
+        *counter += 1;
+        *counter - 1
+    };
+
+    let block = &block_match.block;
+    let mode = block.selection_mode.as_str();
+    if matches!(mode, "" | "static") || block_match.conditional_variant {
+        return;
+    }
+    let pool: Vec<&TemplateVariant> = block
+        .template_variants
+        .iter()
+        .filter(|variant| variant.conditions.is_empty())
+        .collect();
+    if pool.is_empty() {
+        return;
+    }
+
+    let index = match mode {
+        "sequence" => (hit as usize).min(pool.len() - 1),
+        "roundRobin" => (hit % pool.len() as u64) as usize,
+        "weighted" => {
+            // A seeded block still varies per hit, but the same hit number always picks the same
+            // variant.
+            let seed = block.seed.or(block_match.profile.seed);
+            let mut generator = Generator::new(seed.map(|seed| seed.wrapping_add(hit)));
+            weighted_index(&pool, &mut generator)
+        }
+        _ => return,
+    };
+    let variant = pool[index];
+    block_match.variant = Some(variant.name.clone());
+    block_match.block.active_variant_id = Some(variant.id.clone());
+}
+
+/// Picks by `weight` (default 1); variants weighted 0 or less are never picked unless every
+/// variant is, in which case the pick is uniform.
+fn weighted_index(pool: &[&TemplateVariant], generator: &mut Generator) -> usize {
+    let weights: Vec<f64> = pool
+        .iter()
+        .map(|variant| variant.weight.unwrap_or(1.0).max(0.0))
+        .collect();
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return (generator.next_u64() % pool.len() as u64) as usize;
+    }
+    let mut target = generator.next_f64() * total;
+    for (index, weight) in weights.iter().enumerate() {
+        if target < *weight {
+            return index;
+        }
+        target -= weight;
+    }
+    weights
+        .iter()
+        .rposition(|weight| *weight > 0.0)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{app_state, block, block_match};
+    use crate::types::{Block, VariantCondition};
+
+    fn variant(id: &str, weight: Option<f64>) -> TemplateVariant {
+        TemplateVariant {
+            id: id.to_string(),
+            name: id.to_string(),
+            weight,
+            ..Default::default()
+        }
+    }
+
+    fn users(mode: &str, variants: Vec<TemplateVariant>, seed: Option<u64>) -> BlockMatch {
+        block_match(Block {
+            selection_mode: mode.to_string(),
+            template_variants: variants,
+            seed,
+            ..block("users", "GET", "/users")
+        })
+    }
+
+    async fn picks(state: &AppState, template: &BlockMatch, hits: usize) -> Vec<String> {
+        let mut picked = Vec::new();
+        for _ in 0..hits {
+            let mut block_match = template.clone();
+            apply_selection_mode(state, &mut block_match).await;
+            picked.push(block_match.variant.unwrap_or_default());
+        }
+        picked
+    }
+
+    #[tokio::test]
+    async fn sequence_stops_on_the_last_variant_and_skips_conditional_ones() {
+        let mut conditional = variant("conditional", None);
+        conditional.conditions = vec![VariantCondition::default()];
+        let variants = vec![variant("a", None), conditional, variant("b", None)];
+        let template = users("sequence", variants, None);
+        let state = app_state();
+        assert_eq!(picks(&state, &template, 4).await, ["a", "b", "b", "b"]);
+    }
+
+    #[tokio::test]
+    async fn round_robin_cycles_and_counts_per_block() {
+        let variants = vec![variant("a", None), variant("b", None), variant("c", None)];
+        let template = users("roundRobin", variants, None);
+        let state = app_state();
+        assert_eq!(picks(&state, &template, 4).await, ["a", "b", "c", "a"]);
+
+        let mut other = template.clone();
+        other.block.id = "orders".to_string();
+        assert_eq!(picks(&state, &other, 1).await, ["a"]);
+        assert_eq!(
+            state.block_hits.lock().await[&hit_key("default", "users")],
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn seeded_weighted_picks_repeat_and_skip_zero_weights() {
+        let variants = vec![
+            variant("heavy", Some(3.0)),
+            variant("light", None),
+            variant("never", Some(0.0)),
+        ];
+        let template = users("weighted", variants, Some(11));
+        let first = picks(&app_state(), &template, 40).await;
+        let second = picks(&app_state(), &template, 40).await;
+        assert_eq!(first, second);
+        let count = |name: &str| first.iter().filter(|picked| *picked == name).count();
+        assert_eq!(count("never"), 0);
+        assert!(count("heavy") > count("light"));
+        assert!(count("light") > 0);
+    }
+
+    #[tokio::test]
+    async fn static_mode_and_conditional_variants_are_left_alone() {
+        let variants = vec![variant("a", None), variant("b", None)];
+        let state = app_state();
+        let mut fixed = users("static", variants.clone(), None);
+        apply_selection_mode(&state, &mut fixed).await;
+        assert_eq!(fixed.variant, None);
+
+        let mut conditional = users("roundRobin", variants, None);
+        conditional.variant = Some("b".to_string());
+        conditional.conditional_variant = true;
+        apply_selection_mode(&state, &mut conditional).await;
+        apply_selection_mode(&state, &mut conditional).await;
+        assert_eq!(conditional.variant.as_deref(), Some("b"));
+        assert_eq!(
+            state.block_hits.lock().await[&hit_key("default", "users")],
+            3
+        );
+    }
+
+    #[test]
+    fn all_zero_weights_pick_uniformly() {
+        let variants = [variant("a", Some(0.0)), variant("b", Some(-1.0))];
+        let pool: Vec<&TemplateVariant> = variants.iter().collect();
+        let mut generator = Generator::new(Some(5));
+        let picks: Vec<usize> = (0..50)
+            .map(|_| weighted_index(&pool, &mut generator))
+            .collect();
+        assert!(picks.contains(&0) && picks.contains(&1));
+    }
+}
@@ -139,6 +139,7 @@ const App = () => {
   const {
     libraryBlocks,
     activeBlocks,
+    blockWarnings,
     isBuilderOpen,
     builderName,
     builderMethod,
@@ -277,12 +278,16 @@ const App = () => {
           return;
         }
         const library = parsed.libraryBlocks as BlocksPayload["libraryBlocks"];
-        await updateBlocks(selectedProfile, {
+        const saved = await updateBlocks(selectedProfile, {
           libraryBlocks: library,
           activeBlocks: activeBlocks,
         });
         replaceBlocksForProfile(selectedProfile, library, activeBlocks);
-        setImportBlocksMessage("Import successful.");
+        setImportBlocksMessage(
+          saved.warnings.length > 0
+            ? `Import successful. ${saved.warnings.length} block(s) have warnings.`
+            : "Import successful.",
+        );
         setTimeout(() => setImportBlocksMessage(null), 3000);
       } catch (err) {
         const message = err instanceof Error ? err.message : "Import failed.";
@@ -428,6 +433,7 @@ const App = () => {
             />
             <ActivePanel
               blocks={activeBlocks}
+              warnings={blockWarnings}
               onDragOver={allowDrop}
               onDragEnter={handleDragEnter}
               onDropActive={handleDrop("active")}
@@ -468,6 +474,7 @@ const App = () => {
             />
             <ActivePanel
               blocks={activeBlocks}
+              warnings={blockWarnings}
               onDragOver={allowDrop}
               onDragEnter={handleDragEnter}
               onDropActive={handleDrop("active")}
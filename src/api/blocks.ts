@@ -31,6 +31,61 @@ export type BlocksPayload = {
   categories?: string[];
 };
 
+export type TemplateDiagnostic = {
+  /** `body`, `status` or `header <name>`. */
+  source: string;
+  message: string;
+  line: number;
+  column: number;
+};
+
+export type HeaderDiagnostic = {
+  name: string;
+  value: string;
+  reason: string;
+};
+
+/** Findings from rendering a block with its active variant outside of a real request. */
+export type BlockValidation = {
+  blockId: string;
+  blockName: string;
+  variant?: string | null;
+  templateErrors: TemplateDiagnostic[];
+  jsonError?: TemplateDiagnostic | null;
+  missingPlaceholders: TemplateDiagnostic[];
+  unusedValues: string[];
+  invalidValues: string[];
+  invalidHeaders: HeaderDiagnostic[];
+  invalidStatus?: string | null;
+  rendered: string;
+};
+
+/** The saved blocks plus validation findings for blocks that have any. */
+export type UpdateBlocksResponse = BlocksPayload & {
+  warnings: BlockValidation[];
+};
+
+/** One line per finding, for listing a block's warnings. */
+export const blockValidationMessages = (validation: BlockValidation) => {
+  const at = (diagnostic: TemplateDiagnostic) =>
+    `${diagnostic.source} ${diagnostic.line}:${diagnostic.column}: ${diagnostic.message}`;
+  return [
+    ...validation.templateErrors.map(at),
+    ...(validation.jsonError
+      ? [`Invalid JSON at ${at(validation.jsonError)}`]
+      : []),
+    ...validation.missingPlaceholders.map(at),
+    ...validation.invalidValues,
+    ...validation.invalidHeaders.map(
+      (header) => `Header ${header.name}: ${header.reason}`,
+    ),
+    ...(validation.invalidStatus ? [validation.invalidStatus] : []),
+    ...(validation.unusedValues.length > 0
+      ? [`Unused values: ${validation.unusedValues.join(", ")}`]
+      : []),
+  ];
+};
+
 export const fetchBlocks = async (profileName: string) => {
   const response = await fetch(
     `${API_BASE}/api/profiles/${encodeURIComponent(profileName)}/blocks`
@@ -49,5 +104,5 @@ export const updateBlocks = async (profileName: string, payload: BlocksPayload)
     }
   );
   await ensureOk(response);
-  return (await response.json()) as BlocksPayload;
+  return (await response.json()) as UpdateBlocksResponse;
 };
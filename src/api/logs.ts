@@ -1,13 +1,27 @@
 export type RequestLogEntry = {
+  id: number;
   timestampMs: number;
   method: string;
   path: string;
   query: Record<string, string>;
+  requestHeaders?: Record<string, string>;
+  requestBody?: string | null;
+  /** False when the log lost part of the request, which can then not be explained. */
+  explainable?: boolean;
   matched: boolean;
   profile?: string | null;
   subProfile?: string | null;
   request?: string | null;
   block?: string | null;
+  /** Other active blocks that matched but ranked below `block`. */
+  shadowedBlocks?: { id: string; name: string }[];
+  variant?: string | null;
+  /** Resource collection that answered the request. */
+  resource?: string | null;
+  /** Simulated latency applied before responding. */
+  delayMs?: number | null;
+  /** Fault served instead of the logged response. */
+  fault?: string | null;
   response?: {
     status?: number | null;
     headers?: Record<string, string>;
@@ -47,3 +61,29 @@ export const fetchRequestLogs = async () => {
   await ensureOk(response);
   return (await response.json()) as RequestLogEntry[];
 };
+
+export type CandidateExplanation = {
+  id?: string | null;
+  name: string;
+  subProfile?: string | null;
+  accepted: boolean;
+  reason: string;
+  params: Record<string, string>;
+};
+
+export type MatchExplanation = {
+  profile?: string | null;
+  outcome: "block" | "resource" | "request" | "passthrough";
+  summary: string;
+  matchedBlock?: { id: string; name: string } | null;
+  matchedResource?: string | null;
+  matchedRequest?: string | null;
+  blocks: CandidateExplanation[];
+  requests: CandidateExplanation[];
+};
+
+export const fetchLogExplanation = async (logId: number) => {
+  const response = await fetch(`${API_BASE}/api/logs/${logId}/explain`);
+  await ensureOk(response);
+  return (await response.json()) as MatchExplanation;
+};
@@ -86,3 +86,66 @@ export async function getRecordingStatus(): Promise<RecordingStatus> {
   await ensureOk(res, "Failed to fetch recording status");
   return res.json();
 }
+
+export type RecordConfig = {
+  enabled: boolean;
+  /** Library of the active profile that receives recorded blocks (`local` or a remote id). */
+  libraryId?: string;
+  /** Host patterns to record; empty records every host. */
+  hosts?: string[];
+  /** Path prefixes to record; empty records every path. */
+  paths?: string[];
+  /** Replace numeric and UUID path segments with typed params. */
+  parameterize?: boolean;
+  /** Refresh blocks already recorded for the same method and path. */
+  overwrite?: boolean;
+};
+
+export async function getRecordConfig(): Promise<RecordConfig> {
+  const res = await fetch(`${BASE_URL}/api/proxy/record`);
+  await ensureOk(res, "Failed to fetch record mode");
+  return res.json();
+}
+
+export async function setRecordConfig(config: RecordConfig): Promise<RecordConfig> {
+  const res = await fetch(`${BASE_URL}/api/proxy/record`, {
+    method: "PUT",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(config),
+  });
+  await ensureOk(res, "Failed to update record mode");
+  return res.json();
+}
+
+export type NetworkProfile = {
+  /** Preset link; the fields below override it. */
+  preset?: "3g" | "edge" | "lossyWifi" | "custom" | "";
+  downloadKbps?: number | null;
+  latencyMs?: number | null;
+  /** Chance (0 to 1) that a chunk stalls for a retransmit. */
+  loss?: number | null;
+};
+
+export type NetworkConditions = {
+  global?: NetworkProfile | null;
+  /** Per-host profiles; the first matching host wins over `global`. */
+  hosts?: (NetworkProfile & { host: string })[];
+};
+
+export async function getNetworkConditions(): Promise<NetworkConditions> {
+  const res = await fetch(`${BASE_URL}/api/network`);
+  await ensureOk(res, "Failed to fetch network conditions");
+  return res.json();
+}
+
+export async function setNetworkConditions(
+  conditions: NetworkConditions
+): Promise<NetworkConditions> {
+  const res = await fetch(`${BASE_URL}/api/network`, {
+    method: "PUT",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(conditions),
+  });
+  await ensureOk(res, "Failed to update network conditions");
+  return res.json();
+}
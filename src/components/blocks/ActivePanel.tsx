@@ -1,9 +1,12 @@
 import type { DragEventHandler, PointerEventHandler } from "react";
+import { blockValidationMessages, type BlockValidation } from "../../api/blocks";
 import type { Block } from "../../types/block";
 import BlockCard from "./BlockCard";
 
 type ActivePanelProps = {
   blocks: Block[];
+  /** Validation findings from the last save, listed above the blocks. */
+  warnings?: BlockValidation[];
   onDragOver: DragEventHandler<HTMLDivElement>;
   onDragEnter: DragEventHandler<HTMLDivElement>;
   onDropActive: DragEventHandler<HTMLDivElement>;
@@ -24,6 +27,7 @@ type ActivePanelProps = {
 
 const ActivePanel = ({
   blocks,
+  warnings = [],
   onDragOver,
   onDragEnter,
   onDropActive,
@@ -56,6 +60,17 @@ const ActivePanel = ({
           </div>
         ) : null}
       </header>
+      {warnings.length > 0 ? (
+        <ul className="panel__warnings">
+          {warnings.map((warning) => (
+            <li key={warning.blockId} className="panel__warning">
+              <strong>{warning.blockName}</strong>
+              {warning.variant ? ` (${warning.variant})` : ""}:{" "}
+              {blockValidationMessages(warning).join("; ")}
+            </li>
+          ))}
+        </ul>
+      ) : null}
       <div
         className="panel__body"
         data-drop-zone="active"
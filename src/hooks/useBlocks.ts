@@ -4,7 +4,12 @@ import type {
   PointerEvent as ReactPointerEvent,
 } from "react";
 import { useCallback, useEffect, useMemo, useRef, useState } from "react";
-import { fetchBlocks, updateBlocks } from "../api/blocks";
+import {
+  fetchBlocks,
+  updateBlocks,
+  type BlockValidation,
+  type BlocksPayload,
+} from "../api/blocks";
 import type { RequestLogEntry } from "../api/logs";
 import {
   addLibrary as addLibraryApi,
@@ -26,6 +31,8 @@ type UseBlocksParams = {
 type UseBlocksReturn = {
   libraryBlocks: Block[];
   activeBlocks: Block[];
+  /** Validation findings returned by the last save of the selected profile's blocks. */
+  blockWarnings: BlockValidation[];
   isBuilderOpen: boolean;
   builderName: string;
   builderMethod: string;
@@ -145,6 +152,7 @@ const useBlocks = ({
     Record<string, Library[]>
   >({});
   const [refreshTrigger, setRefreshTrigger] = useState(0);
+  const [blockWarnings, setBlockWarnings] = useState<BlockValidation[]>([]);
   const [isBuilderOpen, setBuilderOpenRaw] = useState(false);
   const setIsBuilderOpen = useCallback(
     (open: boolean, libraryId?: string) => {
@@ -398,6 +406,19 @@ const useBlocks = ({
     setRefreshTrigger((t) => t + 1);
   }, []);
 
+  useEffect(() => {
+    setBlockWarnings([]);
+  }, [selectedProfile]);
+
+  const saveBlocks = useCallback(
+    async (profileName: string, payload: BlocksPayload) => {
+      const response = await updateBlocks(profileName, payload);
+      setBlockWarnings(response.warnings ?? []);
+      return response;
+    },
+    [],
+  );
+
   const addLibrary = useCallback(
     async (input: AddLibraryInput) => {
       if (!selectedProfile) return;
@@ -487,7 +508,7 @@ const useBlocks = ({
         }));
       }
 
-      updateBlocks(selectedProfile, {
+      saveBlocks(selectedProfile, {
         libraryBlocks: nextLibrary,
         activeBlocks: nextActive,
         categories,
@@ -497,7 +518,14 @@ const useBlocks = ({
         }
       });
     },
-    [blockIndex, selectedProfile, libraryBlocks, activeBlocks, categories],
+    [
+      blockIndex,
+      selectedProfile,
+      libraryBlocks,
+      activeBlocks,
+      categories,
+      saveBlocks,
+    ],
   );
 
   useEffect(() => {
@@ -651,7 +679,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextLibrary,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks: nextLibrary,
       activeBlocks,
       categories,
@@ -763,7 +791,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextActive,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks: nextLibrary,
       activeBlocks: nextActive,
       categories,
@@ -1088,7 +1116,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextActive,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks: nextLibrary,
       activeBlocks: nextActive,
       categories,
@@ -1108,7 +1136,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextCategories,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks,
       activeBlocks,
       categories: nextCategories,
@@ -1143,7 +1171,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextActive,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks: nextLibrary,
       activeBlocks: nextActive,
       categories: nextCategories,
@@ -1175,7 +1203,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextActive,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks: nextLibrary,
       activeBlocks: nextActive,
       categories: nextCategories,
@@ -1205,7 +1233,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: nextActive,
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks: nextLibrary,
       activeBlocks: nextActive,
       categories,
@@ -1282,7 +1310,7 @@ const useBlocks = ({
         ...prev,
         [selectedProfile]: nextActive,
       }));
-      updateBlocks(selectedProfile, {
+      saveBlocks(selectedProfile, {
         libraryBlocks: nextLibrary,
         activeBlocks: nextActive,
         categories,
@@ -1297,6 +1325,7 @@ const useBlocks = ({
       libraryBlocks,
       activeBlocks,
       categories,
+      saveBlocks,
     ],
   );
 
@@ -1322,7 +1351,7 @@ const useBlocks = ({
       ...prev,
       [selectedProfile]: [],
     }));
-    updateBlocks(selectedProfile, {
+    saveBlocks(selectedProfile, {
       libraryBlocks,
       activeBlocks: [],
       categories,
@@ -1331,11 +1360,18 @@ const useBlocks = ({
         console.error("[blocks] failed to clear active blocks", error);
       }
     });
-  }, [selectedProfile, activeBlocks.length, libraryBlocks, categories]);
+  }, [
+    selectedProfile,
+    activeBlocks.length,
+    libraryBlocks,
+    categories,
+    saveBlocks,
+  ]);
 
   return {
     libraryBlocks,
     activeBlocks,
+    blockWarnings,
     isBuilderOpen,
     builderName,
     builderMethod,
@@ -1,4 +1,10 @@
-export type TemplateValueType = "string" | "array";
+export type TemplateValueType =
+  | "string"
+  | "number"
+  | "boolean"
+  | "object"
+  | "null"
+  | "array";
 
 /** For array-type template values: each item has value and optional enabled flag. */
 export type ArrayItemEntry = { v: string; e?: boolean };
@@ -38,6 +44,49 @@ export type TemplateVariant = {
   id: string;
   name: string;
   values: TemplateValue[];
+  /** Overrides the block status while this variant is active. */
+  status?: string | null;
+  /** Picks this variant for requests meeting all conditions. */
+  conditions?: VariantCondition[];
+  /** Relative weight in the `weighted` selection mode; defaults to 1. */
+  weight?: number | null;
+  /** Overrides the block delay while this variant is active. */
+  delay?: DelayConfig | null;
+  /** Overrides the block fault while this variant is active; "" disables it. */
+  fault?: FaultMode | "" | null;
+};
+
+/** Network failures served instead of a well-formed response. */
+export type FaultMode =
+  | "closeConnection"
+  | "hang"
+  | "resetAfterHeaders"
+  | "truncatedBody";
+
+export type DelayConfig = {
+  /** `fixed` uses `ms`; `uniform` picks in `minMs..maxMs`; `normal` centres on `ms`. */
+  distribution?: "fixed" | "uniform" | "normal";
+  ms?: number;
+  minMs?: number;
+  maxMs?: number;
+  stdDevMs?: number;
+};
+
+export type VariantCondition = FieldCondition & {
+  source: "query" | "header" | "path" | "body";
+};
+
+export type BodyMatcher = {
+  /** `json` (exact), `jsonPath` (value at `path`), `contains` (JSON subset or text) or `regex`. */
+  type: "json" | "jsonPath" | "contains" | "regex";
+  path?: string | null;
+  value: string;
+};
+
+export type FieldCondition = {
+  name: string;
+  operator: "equals" | "regex" | "present" | "absent";
+  value?: string;
 };
 
 export type Block = {
@@ -45,13 +94,40 @@ export type Block = {
   name: string;
   method: string;
   path: string;
+  /** Optional host pattern: exact, wildcard (`*.example.com`) or `regex:` prefixed. */
+  host?: string | null;
   description: string;
+  /** Response status; may contain `{{placeholders}}`. Defaults to 200. */
+  status?: string | null;
   category?: string;
   responseTemplate: string;
   responseHeaders: Record<string, string>;
+  bodyMatchers?: BodyMatcher[];
+  headerConditions?: FieldCondition[];
+  queryConditions?: FieldCondition[];
   templateValues: TemplateValue[];
   templateVariants: TemplateVariant[];
   activeVariantId?: string | null;
   /** When set, block is stored in this library (e.g. "local" or remote library id). */
   sourceLibraryId?: string | null;
+  /** Explicit match priority; higher wins before specificity ranking. */
+  priority?: number | null;
+  /** Seed for {{$...}} generators; overrides the profile seed. */
+  seed?: number | null;
+  /** How variants are picked per request; conditional variants always win. */
+  selectionMode?: "static" | "sequence" | "roundRobin" | "weighted";
+  /** Mock state writes applied before the response renders; templates read `{{state.key}}`. */
+  effects?: StateEffect[];
+  /** Simulated latency before the response is sent. */
+  delay?: DelayConfig | null;
+  fault?: FaultMode | null;
+};
+
+export type StateEffect = {
+  action: "set" | "append" | "increment" | "remove" | "removeItem" | "clear";
+  key: string;
+  /** Template for the value; empty means the request body (or 1 for `increment`). */
+  value?: string;
+  /** For `removeItem`: JSON path in each item compared with the value. */
+  field?: string | null;
 };
@@ -1,5 +1,6 @@
 import type RequestConfig from "../../interfaces/request";
 import type SubProfile from "../../interfaces/subProfile";
+import type { TemplateValue } from "./block";
 
 export type Profile = {
   name: string;
@@ -7,6 +8,89 @@ export type Profile = {
   params: string[];
   subProfiles?: SubProfile[];
   requests?: RequestConfig[];
+  seed?: number | null;
+  /** Template values every block falls back to. */
+  variables?: TemplateValue[];
+  /** Sub-profile whose params block templates can read. */
+  activeSubProfile?: string | null;
+  /** In-memory REST collections served alongside the blocks. */
+  resources?: Resource[];
+  /** Rewrites applied to real upstream responses of passthrough traffic. */
+  modifiers?: ResponseModifier[];
+  /** Rewrites applied to passthrough requests before they are forwarded. */
+  rewrites?: RequestRewrite[];
+};
+
+export type Resource = {
+  id: string;
+  /** Collection path, e.g. `/api/todos`; items live at `/api/todos/:id`. */
+  path: string;
+  /** Item field holding the id. Defaults to `id`. */
+  idField?: string;
+  /** Items the collection starts with and returns to when reset. */
+  seed?: unknown[];
+};
+
+/** Edits passthrough responses; body rules run first, then status and headers. */
+export type ResponseModifier = {
+  id: string;
+  name?: string;
+  disabled?: boolean;
+  /** Empty or `*` matches any method. */
+  method?: string;
+  /** Path template as in blocks; empty matches any path. */
+  path?: string;
+  /** Host pattern as in blocks; empty matches any host. */
+  host?: string | null;
+  /** RFC 7396 merge patch for JSON bodies. */
+  mergePatch?: unknown;
+  /** RFC 6902 operations for JSON bodies, applied all or nothing. */
+  jsonPatch?: JsonPatchOperation[];
+  /** Regex find and replace on text bodies. */
+  replacements?: { find: string; replace?: string }[];
+  setHeaders?: Record<string, string>;
+  removeHeaders?: string[];
+  status?: number | null;
+};
+
+/** Edits passthrough requests before forwarding: query, headers, body, then redirect. */
+export type RequestRewrite = {
+  id: string;
+  name?: string;
+  disabled?: boolean;
+  /** Empty or `*` matches any method. */
+  method?: string;
+  /** Path template as in blocks; its params can be used in `redirect.path`. */
+  path?: string;
+  /** Host pattern as in blocks; empty matches any host. */
+  host?: string | null;
+  setHeaders?: Record<string, string>;
+  removeHeaders?: string[];
+  /** Query params to add, replacing any with the same name. */
+  setQuery?: Record<string, string>;
+  removeQuery?: string[];
+  /** RFC 7396 merge patch for JSON bodies. */
+  mergePatch?: unknown;
+  /** RFC 6902 operations for JSON bodies, applied all or nothing. */
+  jsonPatch?: JsonPatchOperation[];
+  /** Send the request elsewhere, e.g. `{ scheme: "http", host: "localhost:8080" }`. */
+  redirect?: RedirectTarget | null;
+};
+
+export type RedirectTarget = {
+  scheme?: "http" | "https" | null;
+  /** Hostname, optionally with a port. */
+  host?: string | null;
+  port?: number | null;
+  /** Replacement path; `{name}` is filled from the rule's path params. */
+  path?: string | null;
+};
+
+export type JsonPatchOperation = {
+  op: "add" | "remove" | "replace" | "move" | "copy" | "test";
+  path: string;
+  from?: string;
+  value?: unknown;
 };
 
 export type NewSubProfileNames = Record<string, string>;